@@ -1,5 +1,8 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use clap::Args as ClapArgs;
 use std::path::PathBuf;
+use serde::Deserialize;
+use crate::dork::DorkErrorPolicy;
 
 #[derive(Parser, Clone)]
 #[command(name = "shodan-pipeline", version)]
@@ -13,6 +16,118 @@ pub struct Args {
     #[arg(long, default_value_t = false)]
     pub debug: bool,
 
+    /// Perfil YAML del pipeline (sección `rustscan:` con ports, range, ulimit, tries, scan_order, timeout_ms, batch); los flags --rs-* tienen prioridad
+    #[arg(long)]
+    pub profile: Option<PathBuf>,
+
+    /// Vocabulario YAML keywords -> dork (alias, campos, plantillas); por defecto el embebido (vocab/default.yaml)
+    #[arg(long)]
+    pub vocab: Option<PathBuf>,
+
+    /// Máximo de créditos de consulta Shodan a gastar en esta ejecución (1 por página de búsqueda)
+    #[arg(long)]
+    pub max_credits: Option<usize>,
+
+    /// URL base de la API Shodan (proxy de caché o mock local). Si no, config persistente o https://api.shodan.io
+    #[arg(long, env = "SHODAN_BASE_URL")]
+    pub shodan_base_url: Option<String>,
+
+    /// Peticiones por segundo a la API de Shodan (compartido por todos los endpoints)
+    #[arg(long, default_value_t = 1.0)]
+    pub shodan_rate: f64,
+
+    /// Intentos máximos por petición Shodan ante 429 / 5xx / timeouts (backoff exponencial con jitter)
+    #[arg(long, default_value_t = 4)]
+    pub shodan_max_attempts: u32,
+
+    /// Carpeta de la caché de páginas de búsqueda y fichas de host (default: <out>/shodan_cache)
+    #[arg(long)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Vigencia de la caché Shodan en segundos
+    #[arg(long, default_value_t = 86400)]
+    pub cache_ttl: u64,
+
+    /// No leer ni escribir la caché Shodan
+    #[arg(long, default_value_t = false)]
+    pub no_cache: bool,
+
+    /// Ignorar lo cacheado y volver a descargar (la caché se actualiza)
+    #[arg(long, default_value_t = false)]
+    pub refresh: bool,
+
+    /// Permite escanear rangos reservados/privados (RFC 1918, loopback, CGNAT, documentación...); para evaluaciones internas
+    #[arg(long, default_value_t = false)]
+    pub allow_private: bool,
+
+    /// Máximo de direcciones que puede expandir un prefijo o rango (IPv4 o IPv6) de un archivo de objetivos
+    #[arg(long, default_value_t = crate::targets::DEFAULT_MAX_HOSTS)]
+    pub max_hosts: u128,
+
+    /// Servidor(es) DNS para resolver objetivos (IP o IP:puerto, separados por coma; p. ej. un stub local o el DNS interno)
+    #[arg(long, value_delimiter = ',', value_parser = crate::targets::parse_nameserver, conflicts_with = "dns_system")]
+    pub dns_server: Vec<std::net::SocketAddr>,
+
+    /// Resolver con la configuración del sistema (/etc/resolv.conf: servidores, dominios de búsqueda, opciones)
+    #[arg(long, default_value_t = false)]
+    pub dns_system: bool,
+
+    /// Timeout por consulta DNS en milisegundos (default: el de resolv.conf o 5000)
+    #[arg(long)]
+    pub dns_timeout_ms: Option<u64>,
+
+    /// Intentos por consulta DNS (default: el de resolv.conf o 2)
+    #[arg(long)]
+    pub dns_attempts: Option<usize>,
+
+    /// Resoluciones DNS simultáneas
+    #[arg(long, default_value_t = crate::targets::DEFAULT_DNS_CONCURRENCY)]
+    pub dns_concurrency: usize,
+
+    /// Motor de descubrimiento de puertos cuando la fuente es rustscan/union: rustscan (binario externo) | native (connect en Rust)
+    #[arg(long, value_enum, default_value_t = DiscoveryEngine::Rustscan)]
+    pub discovery: DiscoveryEngine,
+
+    /// Host con descubrimiento fallido (rustscan no arranca, sale con error o vence el plazo): retry[:N] | nmap-top | mark-error
+    #[arg(long, default_value = "nmap-top")]
+    pub on_discovery_error: crate::discovery::DiscoveryErrorPolicy,
+
+    /// Tiempo máximo de rustscan por host en segundos (al vencer se termina y cuenta como fallo)
+    #[arg(long, default_value_t = crate::rustscan::DEFAULT_RS_HOST_TIMEOUT_SECS)]
+    pub rs_host_timeout_secs: u64,
+
+    /// Puertos del escáner nativo (ej: 1-1024,3306,8000-8100)
+    #[arg(long, default_value = "1-65535")]
+    pub scan_ports: String,
+
+    /// Timeout de cada conexión del escáner nativo en milisegundos
+    #[arg(long, default_value_t = 1500)]
+    pub scan_timeout_ms: u64,
+
+    /// Tope global de sockets simultáneos del escáner nativo (se ajusta al ulimit de descriptores)
+    #[arg(long, default_value_t = 768)]
+    pub scan_max_sockets: usize,
+
+    /// Lote inicial de puertos por host del escáner nativo (se adapta según los recursos disponibles)
+    #[arg(long, default_value_t = 256)]
+    pub scan_batch: usize,
+
+    /// No consultar el DNS inverso (PTR) de las IPs del reporte
+    #[arg(long, default_value_t = false)]
+    pub no_ptr: bool,
+
+    /// Lista de alcance permitido (IPs, CIDR, rangos o dominios, uno por línea); fuera de ella nada se escanea
+    #[arg(long)]
+    pub allowlist: Option<PathBuf>,
+
+    /// Lista de exclusiones (mismo formato); tiene prioridad sobre la allowlist
+    #[arg(long)]
+    pub denylist: Option<PathBuf>,
+
+    /// Archivo YAML de autorización (client, start, end en RFC 3339); fuera de la ventana no se escanea
+    #[arg(long)]
+    pub engagement: Option<PathBuf>,
+
     /// Carpeta de trabajo para outputs (XML, logs, csv, etc.)
     #[arg(long, default_value = "out")]
     pub out: PathBuf,
@@ -25,7 +140,7 @@ pub struct Args {
 pub enum Cmd {
     /// Ejecuta TODO: keywords -> Shodan (N páginas) -> (opcional RustScan) -> Nmap -> reglas -> CSV
     Full {
-        /// Palabras clave separadas por coma. Ej: 'chile,.cl,muni'
+        /// Palabras clave separadas por coma (admite '|', '( )', '-x' y 'campo:valor'; ver 'dork --explain'). Ej: 'chile,.cl,muni'
         #[arg(long)]
         keywords: String,
         /// Máximo de IPs a recolectar desde Shodan
@@ -55,16 +170,23 @@ pub enum Cmd {
         /// Archivo opcional con objetivos (IP o dominio), uno por línea
         #[arg(long)]
         targets: Option<PathBuf>,
-        /// Si se define, Nmap ignora RustScan y escanea estos puertos fijos (matriz), ej: "22,80,443"
+        /// Si se define, Nmap ignora RustScan y escanea estos puertos fijos (matriz), ej: "22,80,443" o "22,443,U:53,161" (UDP con root)
         #[arg(long)]
         fixed_ports: Option<String>,
+        /// Origen de la lista de puertos para Nmap: shodan | rustscan | fixed | union (shodan+rustscan).
+        /// Por defecto: fixed si hay --fixed-ports, si no rustscan
+        #[arg(long, value_enum)]
+        port_source: Option<PortSource>,
         /// Concurrencia para RustScan (IPs simultáneas)
         #[arg(long, default_value_t = 32)]
         rs_concurrency: usize,
+        // --rs-ports, --rs-range, --rs-ulimit, --rs-tries, --rs-scan-order, --rs-timeout-ms, --rs-batch
+        #[command(flatten)]
+        rs: RustscanSettings,
         /// Concurrencia para Nmap (IPs simultáneas)
         #[arg(long, default_value_t = 3)]
         nmap_concurrency: usize,
-        /// Extra para Nmap (ej: "-sV -sC -Pn"). Valor por defecto seguro sin root.
+        /// Extra para Nmap (ej: "-sV -sC -Pn"; con root "-sU" suma UDP). Valor por defecto seguro sin root.
     #[arg(long, default_value = "-sT -sV -Pn --version-intensity 5 --max-retries 2")]
     nmap_extra: String,
         /// Archivo YAML de reglas dinámicas (puerto/servicio -> comandos)
@@ -79,11 +201,75 @@ pub enum Cmd {
     only_open: bool,
     #[arg(long, default_value_t = false)]
     confirm_wrapped: bool,
+        /// No consultar /shodan/host/{ip} para los objetivos de --targets
+        #[arg(long, default_value_t = false)]
+        no_enrich: bool,
+        /// Incluir banners históricos al enriquecer objetivos (/shodan/host?history=true)
+        #[arg(long, default_value_t = false)]
+        enrich_history: bool,
+        /// Si /count rechaza el dork: abort | fallback:<query> | simplify (descarta campos pesados como http.html)
+        #[arg(long, default_value = "abort")]
+        on_dork_error: DorkErrorPolicy,
+    },
+    /// Solo estadísticas del dork (/count + facets) -> stats.json / stats.csv, sin gastar créditos de consulta
+    Stats {
+        /// Palabras clave separadas por coma (admite '|', '( )', '-x' y 'campo:valor'; ver 'dork --explain'). Ej: 'chile,.cl,muni'
+        #[arg(long)]
+        keywords: String,
+        /// Facets a desglosar (csv; admite "facet:N")
+        #[arg(long, default_value = crate::shodan::DEFAULT_FACETS)]
+        facets: String,
+        /// Valores por facet cuando no se indica ":N"
+        #[arg(long, default_value_t = 10)]
+        facet_size: usize,
+    },
+    /// Enriquecer un archivo de objetivos (IPs/dominios) con /shodan/host/{ip} -> enrich.json (usa la caché Shodan)
+    Enrich {
+        #[arg(long)]
+        input_targets: PathBuf,
+        /// Incluir banners históricos
+        #[arg(long, default_value_t = false)]
+        history: bool,
+    },
+    /// Muestra el dork Shodan generado para una expresión de keywords (sin llamar a la API)
+    Dork {
+        /// Expresión: ',' = AND, '|' = OR, '( )' agrupa, '-x' excluye, 'campo:valor' pasa crudo. Ej: 'chile, (muni | hospital), -port:23'
+        #[arg(long)]
+        keywords: String,
+        /// Imprime además el árbol (AST) con la expansión de cada keyword
+        #[arg(long, default_value_t = false)]
+        explain: bool,
+    },
+    /// Varias consultas con nombre desde un YAML -> out/<campaña>/<consulta>/ + reporte combinado deduplicado
+    Campaign {
+        /// Archivo YAML de la campaña (ver campaign.example.yaml)
+        #[arg(long)]
+        file: PathBuf,
+        /// Concurrencia para RustScan (IPs simultáneas)
+        #[arg(long, default_value_t = 32)]
+        rs_concurrency: usize,
+        /// Concurrencia para Nmap (IPs simultáneas)
+        #[arg(long, default_value_t = 3)]
+        nmap_concurrency: usize,
+        #[arg(long, default_value = "-sT -sV -Pn --version-intensity 5 --max-retries 2")]
+        nmap_extra: String,
+        /// Reanudar: si existe <consulta>/<IP>/nmap.xml no vuelve a ejecutar Nmap
+        #[arg(long, default_value_t = false)]
+        resume: bool,
+        #[arg(long, default_value_t = true)]
+        hide_tcpwrapped: bool,
+        #[arg(long, default_value_t = true)]
+        only_open: bool,
+        #[arg(long, default_value_t = false)]
+        confirm_wrapped: bool,
+        /// Si /count rechaza el dork de una consulta: abort | fallback:<query> | simplify
+        #[arg(long, default_value = "abort")]
+        on_dork_error: DorkErrorPolicy,
     },
     /// Solo Shodan -> ips.txt (y sale)
-    Intel { #[arg(long)] keywords: String, #[arg(long, default_value_t = 5)] limit: usize, #[arg(long, default_value_t = 20)] pages: usize },
+    Intel { #[arg(long)] keywords: String, #[arg(long, default_value_t = 5)] limit: usize, #[arg(long, default_value_t = 20)] pages: usize, #[arg(long, default_value = "abort")] on_dork_error: DorkErrorPolicy },
     /// Solo RustScan sobre un archivo de objetivos (IPs/dominios). Guarda rustscan.jsonl
-    Rustscan { #[arg(long)] input_targets: PathBuf, #[arg(long, default_value_t = 1500)] timeout_ms: u64, #[arg(long, default_value_t = 4500)] batch: u32, #[arg(long, default_value_t = 32)] concurrency: usize },
+    Rustscan { #[arg(long)] input_targets: PathBuf, #[command(flatten)] rs: RustscanSettings, #[arg(long, default_value_t = 32)] concurrency: usize },
     /// Solo Nmap desde un JSONL con {ip,ports:[...]} (o con --fixed-ports)
     Nmap { #[arg(long)] input_jsonl: Option<PathBuf>, #[arg(long)] fixed_ports: Option<String>, #[arg(long, default_value = "-sT -sV -Pn --version-intensity 5 --max-retries 2")] nmap_extra: String, #[arg(long, default_value_t = 3)] concurrency: usize, #[arg(long, default_value_t = false)] resume: bool, #[arg(long, default_value_t = true)] hide_tcpwrapped: bool, #[arg(long, default_value_t = true)] only_open: bool, #[arg(long, default_value_t = false)] confirm_wrapped: bool },
     /// Configurar o mostrar la API key persistente (~/.config/.../api_key)
@@ -91,6 +277,9 @@ pub enum Cmd {
         /// Guarda la clave indicada y termina
         #[arg(long)]
         set: Option<String>,
+        /// Guarda una URL base de la API Shodan (proxy/mock) y termina
+        #[arg(long)]
+        set_base_url: Option<String>,
         /// Muestra la ruta del archivo donde se almacena
         #[arg(long, default_value_t = false)]
         show_path: bool,
@@ -103,3 +292,74 @@ pub enum Cmd {
         deep: bool,
     }
 }
+
+/// De dónde sale el mapa IP -> puertos que se entrega a Nmap.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PortSource {
+    /// Puertos que Shodan reportó abiertos para cada IP
+    Shodan,
+    /// Descubrimiento activo con RustScan
+    Rustscan,
+    /// Matriz fija de --fixed-ports
+    Fixed,
+    /// Unión de Shodan + RustScan
+    Union,
+}
+
+/// Opciones de RustScan (`--rs-*` en `full` y `rustscan`, o sección `rustscan:` del perfil). Sin valor se usa el del perfil
+/// y, si tampoco, el predeterminado (timeout 1500 ms, batch 4500, los de rustscan para el resto).
+#[derive(ClapArgs, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RustscanSettings {
+    /// Puertos para RustScan (lista y rangos: 22,80,8000-8100); excluyente con --rs-range
+    #[arg(long = "rs-ports")]
+    pub ports: Option<String>,
+    /// Rango continuo para RustScan (ej: 1-65535)
+    #[arg(long = "rs-range")]
+    pub range: Option<String>,
+    /// Límite de descriptores que RustScan pide al sistema (debe ser >= batch)
+    #[arg(long = "rs-ulimit")]
+    pub ulimit: Option<u64>,
+    /// Intentos por puerto (1-10)
+    #[arg(long = "rs-tries")]
+    pub tries: Option<u8>,
+    /// Orden de escaneo de puertos
+    #[arg(long = "rs-scan-order", value_enum)]
+    pub scan_order: Option<ScanOrder>,
+    /// Timeout por puerto en milisegundos
+    #[arg(long = "rs-timeout-ms", alias = "timeout-ms")]
+    pub timeout_ms: Option<u64>,
+    /// Puertos por lote (--batch-size de RustScan)
+    #[arg(long = "rs-batch", alias = "batch")]
+    pub batch: Option<u32>,
+}
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanOrder {
+    /// En orden ascendente
+    Serial,
+    /// Aleatorio (menos evidente para un IDS)
+    Random,
+}
+
+/// Implementación del descubrimiento activo de puertos.
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiscoveryEngine {
+    /// Binario externo `rustscan`
+    Rustscan,
+    /// Escáner TCP connect nativo (no requiere rustscan instalado)
+    Native,
+}
+
+impl PortSource {
+    /// Resuelve el valor efectivo cuando no se indicó `--port-source`.
+    pub fn resolve(explicit: Option<PortSource>, fixed_ports: Option<&str>) -> PortSource {
+        explicit.unwrap_or(if fixed_ports.is_some() { PortSource::Fixed } else { PortSource::Rustscan })
+    }
+
+    /// ¿Los puertos salen (también) de los banners de Shodan?
+    pub fn uses_shodan(self) -> bool { matches!(self, PortSource::Shodan | PortSource::Union) }
+}
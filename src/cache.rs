@@ -0,0 +1,71 @@
+//! Caché en disco de respuestas Shodan direccionada por contenido (hash de la petición) con TTL.
+use anyhow::Result;
+use serde_json::{Value, json};
+use std::{fs, path::PathBuf, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+/// `Use`: lee y escribe; `Refresh`: ignora lo guardado pero escribe lo nuevo; `Off`: ni lee ni escribe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode { Use, Refresh, Off }
+
+#[derive(Debug, Clone)]
+pub struct DiskCache { dir: PathBuf, ttl: Duration, mode: CacheMode }
+
+impl DiskCache {
+    pub fn new(dir: PathBuf, ttl: Duration, mode: CacheMode) -> Self { Self { dir, ttl, mode } }
+
+    pub fn disabled() -> Self { Self::new(PathBuf::new(), Duration::ZERO, CacheMode::Off) }
+
+    /// Clave estable (FNV-1a 64 en hex) para las partes de una petición, p.ej. `["search", query, "1"]`.
+    pub fn key(parts: &[&str]) -> String {
+        let mut h: u64 = 0xcbf29ce484222325;
+        // Separador \0 para que ["ab","c"] y ["a","bc"] no colisionen
+        for b in parts.join("\0").bytes() { h ^= b as u64; h = h.wrapping_mul(0x100000001b3); }
+        format!("{h:016x}")
+    }
+
+    fn path(&self, namespace: &str, key: &str) -> PathBuf { self.dir.join(namespace).join(format!("{key}.json")) }
+
+    /// Cuerpo guardado si existe y no superó el TTL (solo en modo `Use`).
+    pub fn get(&self, namespace: &str, key: &str) -> Option<Value> {
+        if self.mode != CacheMode::Use { return None; }
+        let entry: Value = serde_json::from_str(&fs::read_to_string(self.path(namespace, key)).ok()?).ok()?;
+        let fetched_at = entry.get("fetched_at")?.as_u64()?;
+        if now_secs().saturating_sub(fetched_at) > self.ttl.as_secs() { return None; }
+        entry.get("body").cloned()
+    }
+
+    /// Guarda `body` junto a la petición original (`request`, para depurar) y la hora de descarga.
+    pub fn put(&self, namespace: &str, key: &str, request: &[&str], body: &Value) -> Result<()> {
+        if self.mode == CacheMode::Off { return Ok(()); }
+        let path = self.path(namespace, key);
+        if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
+        fs::write(path, serde_json::to_string(&json!({ "fetched_at": now_secs(), "request": request, "body": body }))?)?;
+        Ok(())
+    }
+}
+
+fn now_secs() -> u64 { SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_and_ttl() {
+        let dir = std::env::temp_dir().join(format!("shodan-pipeline-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let k = DiskCache::key(&["search", "country:CL", "1"]);
+        assert_ne!(k, DiskCache::key(&["search", "country:CL", "2"]));
+        assert_ne!(DiskCache::key(&["ab", "c"]), DiskCache::key(&["a", "bc"]));
+        let body = json!({ "matches": [] });
+        let cache = DiskCache::new(dir.clone(), Duration::from_secs(60), CacheMode::Use);
+        assert!(cache.get("search", &k).is_none());
+        cache.put("search", &k, &["country:CL", "1"], &body).unwrap();
+        assert_eq!(cache.get("search", &k), Some(body.clone()));
+        assert!(DiskCache::new(dir.clone(), Duration::from_secs(60), CacheMode::Refresh).get("search", &k).is_none());
+        // TTL vencido: escribimos una entrada con fetched_at antiguo
+        fs::write(dir.join("search").join(format!("{k}.json")), json!({ "fetched_at": 1, "body": body }).to_string()).unwrap();
+        assert!(cache.get("search", &k).is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
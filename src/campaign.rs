@@ -0,0 +1,222 @@
+//! Campañas: lista YAML de consultas con nombre que se ejecutan en serie (Shodan -> puertos -> Nmap -> reglas),
+//! cada una en `out/<campaña>/<consulta>/`, más un reporte combinado y deduplicado por IP.
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::{collections::{BTreeMap, BTreeSet}, path::{Path, PathBuf}, sync::Arc};
+use crate::{
+    args::PortSource,
+    discovery::{build_ports_map, DiscoveryOptions},
+    dork::{self, DorkErrorPolicy},
+    dynamic::run_dynamic_tools,
+    models::HostReport,
+    reserved::drop_reserved_matches,
+    nmap::{confirm_tcpwrapped, nmap_many_with_progress},
+    rdns::PtrEnricher,
+    rustscan::RustscanOptions,
+    output::{export_csv, export_json, export_markdown, export_stats_json, print_dork_changes},
+    rules::{load_rules, Rules},
+    scope::{add_match_names, Scope},
+    shodan::{attach_shodan_matches, check_dork, export_collected, facets_param, shodan_collect, unique_ips, ShodanApi, DEFAULT_FACETS},
+    vocab::Vocabulary,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct Campaign {
+    /// Nombre (subdirectorio de `out/`); por defecto el nombre del archivo sin extensión
+    #[serde(default)]
+    pub name: String,
+    pub queries: Vec<CampaignQuery>,
+}
+
+/// Una consulta de la campaña; los valores por defecto son los de `full`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CampaignQuery {
+    pub name: String,
+    /// Expresión de keywords (misma gramática que `--keywords`)
+    pub keywords: String,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+    #[serde(default = "default_pages")]
+    pub pages: usize,
+    #[serde(default)]
+    pub port_source: Option<PortSource>,
+    #[serde(default)]
+    pub fixed_ports: Option<String>,
+    #[serde(default = "default_rules")]
+    pub rules: PathBuf,
+}
+
+fn default_limit() -> usize { 5 }
+fn default_pages() -> usize { 20 }
+fn default_rules() -> PathBuf { PathBuf::from("rules.yaml") }
+
+/// Opciones de escaneo comunes a todas las consultas (flags del subcomando `campaign`).
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    pub discovery: DiscoveryOptions,
+    pub nmap_concurrency: usize,
+    pub nmap_extra: String,
+    pub resume: bool,
+    pub hide_tcpwrapped: bool,
+    pub only_open: bool,
+    pub confirm_wrapped: bool,
+    pub on_dork_error: DorkErrorPolicy,
+    pub debug: bool,
+    pub scope: Arc<Scope>,
+    pub allow_private: bool,
+    /// DNS inverso de las IPs escaneadas (`None` con `--no-ptr`).
+    pub ptr: Option<PtrEnricher>,
+}
+
+/// Resultado de una consulta para el resumen `campaign.json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryOutcome {
+    pub name: String,
+    pub dork: String,
+    pub ips: usize,
+    pub hosts: usize,
+    pub dork_changes: Vec<String>,
+    pub error: Option<String>,
+}
+
+fn valid_name(n: &str) -> bool { !n.is_empty() && n != "." && n != ".." && n.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) }
+
+/// Lee y valida la campaña: al menos una consulta, nombres únicos y aptos como directorio, keywords parseables.
+pub fn load_campaign(path: &Path) -> Result<Campaign> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("No pude leer la campaña {}", path.display()))?;
+    let mut c: Campaign = serde_yaml::from_str(&text).with_context(|| format!("Campaña inválida {}", path.display()))?;
+    if c.name.is_empty() { c.name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default(); }
+    if !valid_name(&c.name) { bail!("nombre de campaña inválido '{}' (usa letras, dígitos, '-', '_' o '.')", c.name); }
+    if c.queries.is_empty() { bail!("la campaña '{}' no tiene consultas", c.name); }
+    let mut seen = BTreeSet::new();
+    for q in &c.queries {
+        if !valid_name(&q.name) { bail!("nombre de consulta inválido '{}' (usa letras, dígitos, '-', '_' o '.')", q.name); }
+        if !seen.insert(q.name.as_str()) { bail!("consulta duplicada '{}'", q.name); }
+        dork::parse(&q.keywords).with_context(|| format!("keywords inválidas en '{}'", q.name))?;
+    }
+    Ok(c)
+}
+
+/// Ejecuta una consulta completa en `dir` y devuelve sus reportes (ya filtrados para exportar).
+pub async fn run_query(api: &ShodanApi, vocab: &Vocabulary, q: &CampaignQuery, dir: &Path, opts: &ScanOptions) -> Result<(QueryOutcome, Vec<HostReport>)> {
+    tokio::fs::create_dir_all(dir).await?;
+    let expr = dork::parse(&q.keywords)?;
+    let checked = check_dork(api, &expr, vocab, &opts.on_dork_error, &facets_param(DEFAULT_FACETS, 5)).await?;
+    println!("[{}] Dork: {} ({} resultados)", q.name, checked.query, checked.stats.total);
+    export_stats_json(&dir.join("stats.json"), &checked.stats)?;
+    let port_source = PortSource::resolve(q.port_source, q.fixed_ports.as_deref());
+    let matches = drop_reserved_matches(shodan_collect(api, &checked.query, q.limit, q.pages, port_source.uses_shodan(), opts.debug).await?, opts.allow_private);
+    export_collected(dir, &unique_ips(&matches), &matches)?;
+    let mut names = BTreeMap::new();
+    add_match_names(&mut names, &matches);
+    let ips = opts.scope.filter_ips(&unique_ips(&matches), &names)?;
+    let nmap_fixed = if port_source == PortSource::Fixed { q.fixed_ports.as_deref() } else { None };
+    // El stderr de RustScan va junto al resto de archivos del host, en el directorio de la consulta
+    let discovery = DiscoveryOptions { rustscan: RustscanOptions { out_dir: Some(dir.to_path_buf()), ..opts.discovery.rustscan.clone() }, ..opts.discovery.clone() };
+    let discovered = build_ports_map(port_source, &ips, q.fixed_ports.as_deref(), &matches, &discovery, false, Some(&dir.join("rustscan.jsonl"))).await?;
+    let pairs = discovered.nmap_pairs(&ips);
+    let mut reports = nmap_many_with_progress(&pairs, &discovered, dir, &opts.nmap_extra, nmap_fixed, opts.nmap_concurrency, opts.resume).await?;
+    discovered.annotate(&mut reports);
+    if opts.confirm_wrapped { confirm_tcpwrapped(&mut reports).await.ok(); }
+    attach_shodan_matches(&mut reports, &matches);
+    if let Some(ptr) = &opts.ptr { ptr.enrich(&mut reports).await?; }
+    let rules_cfg = load_rules(&q.rules).unwrap_or_else(|_| Rules { rules: vec![] });
+    if !rules_cfg.rules.is_empty() { run_dynamic_tools(&rules_cfg, &opts.scope.retain_reports(&reports)?, dir).await?; }
+    export_csv(&dir.join("report.csv"), &reports, opts.hide_tcpwrapped, opts.only_open)?;
+    export_json(&dir.join("report.json"), &reports, opts.hide_tcpwrapped, opts.only_open)?;
+    export_markdown(&dir.join("report.md"), &reports, opts.hide_tcpwrapped, opts.only_open)?;
+    let outcome = QueryOutcome { name: q.name.clone(), dork: checked.query, ips: ips.len(), hosts: reports.len(), dork_changes: checked.changes, error: None };
+    Ok((outcome, reports))
+}
+
+/// Ejecuta todas las consultas; un fallo en una no detiene las demás (queda registrado en el resumen).
+/// Escribe `campaign.json` y el reporte combinado en `out/<campaña>/`.
+pub async fn run_campaign(api: &ShodanApi, vocab: &Vocabulary, campaign: &Campaign, out: &Path, opts: &ScanOptions) -> Result<Vec<HostReport>> {
+    let root = out.join(&campaign.name);
+    tokio::fs::create_dir_all(&root).await?;
+    let mut outcomes = Vec::new();
+    let mut all = Vec::new();
+    for (i, q) in campaign.queries.iter().enumerate() {
+        println!("=== [{}/{}] {} ===", i + 1, campaign.queries.len(), q.name);
+        match run_query(api, vocab, q, &root.join(&q.name), opts).await {
+            Ok((outcome, reports)) => { print_dork_changes(&outcome.dork_changes); outcomes.push(outcome); all.extend(reports); }
+            Err(e) => {
+                eprintln!("[ERROR] Consulta '{}': {e:#}", q.name);
+                outcomes.push(QueryOutcome { name: q.name.clone(), dork: String::new(), ips: 0, hosts: 0, dork_changes: vec![], error: Some(format!("{e:#}")) });
+            }
+        }
+    }
+    let merged = merge_reports(all);
+    std::fs::write(root.join("campaign.json"), serde_json::to_string_pretty(&outcomes)?)?;
+    export_csv(&root.join("report.csv"), &merged, opts.hide_tcpwrapped, opts.only_open)?;
+    export_json(&root.join("report.json"), &merged, opts.hide_tcpwrapped, opts.only_open)?;
+    export_markdown(&root.join("report.md"), &merged, opts.hide_tcpwrapped, opts.only_open)?;
+    println!("=== CAMPAÑA {} ===", campaign.name);
+    for o in &outcomes {
+        match &o.error { Some(e) => println!("- {}: ERROR {e}", o.name), None => println!("- {}: {} IPs, {} hosts escaneados", o.name, o.ips, o.hosts) }
+    }
+    println!("Hosts únicos: {}", merged.len());
+    Ok(merged)
+}
+
+/// Une reportes de varias consultas por IP: objetivos y puertos sin repetir (gana el primer resultado de cada puerto/protocolo).
+pub fn merge_reports(reports: Vec<HostReport>) -> Vec<HostReport> {
+    let mut by_ip: BTreeMap<String, HostReport> = BTreeMap::new();
+    for r in reports {
+        match by_ip.get_mut(&r.ip) {
+            None => { by_ip.insert(r.ip.clone(), r); }
+            Some(acc) => {
+                if !acc.target.split(',').any(|t| t == r.target) { acc.target = format!("{},{}", acc.target, r.target); }
+                for p in r.ports { if !acc.ports.iter().any(|x| x.port == p.port && x.protocol == p.protocol) { acc.ports.push(p); } }
+                acc.ports.sort_by_key(|p| (p.protocol, p.port));
+                for m in r.shodan { if !acc.shodan.contains(&m) { acc.shodan.push(m); } }
+                if acc.shodan_host.is_none() { acc.shodan_host = r.shodan_host; }
+                for t in r.tags { if !acc.tags.contains(&t) { acc.tags.push(t); } }
+                if acc.ptr.is_empty() { acc.ptr = r.ptr; }
+                if acc.host_scripts.is_empty() { acc.host_scripts = r.host_scripts; }
+                // Un resultado "up" de otra consulta gana a uno caído o ausente
+                if acc.nmap.as_ref().is_none_or(|n| n.is_down()) && r.nmap.is_some() { acc.nmap = r.nmap; }
+                if acc.discovery_error.is_none() { acc.discovery_error = r.discovery_error; }
+            }
+        }
+    }
+    by_ip.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{PortDetail, Protocol};
+
+    fn port(port: u16, service: &str) -> PortDetail { PortDetail { port, protocol: Protocol::Tcp, state: "open".into(), service: Some(service.into()), ..Default::default() } }
+
+    #[test]
+    fn merge_dedups_by_ip_and_port() {
+        let a = HostReport { target: "192.0.2.1".into(), ip: "192.0.2.1".into(), ports: vec![port(443, "https")], ..Default::default() };
+        let quic = PortDetail { protocol: Protocol::Udp, ..port(443, "quic") };
+        let b = HostReport { target: "192.0.2.1".into(), ip: "192.0.2.1".into(), ports: vec![quic, port(22, "ssh"), port(443, "http")], ..Default::default() };
+        let c = HostReport { target: "192.0.2.2".into(), ip: "192.0.2.2".into(), ports: vec![], ..Default::default() };
+        let merged = merge_reports(vec![a, c, b]);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].target, "192.0.2.1");
+        assert_eq!(merged[0].ports.iter().map(|p| p.label()).collect::<Vec<_>>(), vec!["22", "443", "443/udp"]);
+        assert_eq!(merged[0].ports[1].service.as_deref(), Some("https"));
+    }
+
+    #[test]
+    fn campaign_validation() {
+        let dir = std::env::temp_dir().join(format!("shodan-pipeline-campaign-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("semanal.yaml");
+        std::fs::write(&path, "queries:\n  - name: munis\n    keywords: 'chile, muni'\n    port_source: shodan\n  - name: rdp\n    keywords: 'chile, port:3389'\n    fixed_ports: '3389'\n    limit: 50\n").unwrap();
+        let c = load_campaign(&path).unwrap();
+        assert_eq!(c.name, "semanal");
+        assert_eq!(c.queries[0].port_source, Some(PortSource::Shodan));
+        assert_eq!((c.queries[1].limit, c.queries[1].pages), (50, 20));
+        std::fs::write(&path, "queries:\n  - name: a\n    keywords: chile\n  - name: a\n    keywords: pe\n").unwrap();
+        assert!(load_campaign(&path).is_err());
+        std::fs::write(&path, "queries:\n  - name: ../x\n    keywords: chile\n").unwrap();
+        assert!(load_campaign(&path).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
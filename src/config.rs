@@ -2,12 +2,23 @@ use anyhow::{Context, Result};
 use directories::ProjectDirs;
 use std::{fs, path::PathBuf};
 
-pub fn config_file() -> Result<PathBuf> {
+/// URL de la API pública de Shodan (se puede sustituir por un proxy o mock local).
+pub const DEFAULT_SHODAN_BASE_URL: &str = "https://api.shodan.io";
+
+pub fn config_dir() -> Result<PathBuf> {
     let proj = ProjectDirs::from("io", "shodan", "shodan-pipeline")
         .context("No pude resolver el directorio de configuración")?;
     let dir = proj.config_dir();
     fs::create_dir_all(dir)?;
-    Ok(dir.join("api_key"))
+    Ok(dir.to_path_buf())
+}
+
+pub fn config_file() -> Result<PathBuf> {
+    Ok(config_dir()?.join("api_key"))
+}
+
+pub fn base_url_file() -> Result<PathBuf> {
+    Ok(config_dir()?.join("base_url"))
 }
 
 pub fn save_key(key: &str) -> Result<PathBuf> {
@@ -34,3 +45,15 @@ pub fn load_key_from_file() -> Option<String> {
     let k = s.trim().to_string();
     if k.is_empty() { None } else { Some(k) }
 }
+
+pub fn save_base_url(url: &str) -> Result<PathBuf> {
+    let path = base_url_file()?;
+    fs::write(&path, url.trim().trim_end_matches('/'))?;
+    Ok(path)
+}
+
+pub fn load_base_url_from_file() -> Option<String> {
+    let s = fs::read_to_string(base_url_file().ok()?).ok()?;
+    let u = s.trim().to_string();
+    if u.is_empty() { None } else { Some(u) }
+}
@@ -0,0 +1,186 @@
+use anyhow::{Result, anyhow};
+use std::{collections::BTreeMap, fmt, path::Path, str::FromStr};
+use crate::{args::{DiscoveryEngine, PortSource}, models::{HostReport, IpPorts, ShodanMatch}, nmap::PortSpec, portscan::{native_scan_many_with_progress, NativeScanOptions}, rustscan::{rustscan_many_with_progress, RustscanOptions}};
+
+pub type PortsMap = BTreeMap<String, Vec<u16>>;
+
+/// Qué hacer con un host cuyo descubrimiento falló: reintentar N veces (y luego marcarlo como error),
+/// pasarlo a Nmap con sus top-ports (por defecto) o marcarlo como error sin escanearlo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscoveryErrorPolicy { Retry(u32), NmapTop, MarkError }
+
+impl FromStr for DiscoveryErrorPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nmap-top" => Ok(Self::NmapTop),
+            "mark-error" => Ok(Self::MarkError),
+            "retry" => Ok(Self::Retry(2)),
+            _ => match s.strip_prefix("retry:").map(str::parse::<u32>) {
+                Some(Ok(n)) if n > 0 => Ok(Self::Retry(n)),
+                _ => Err(format!("política inválida '{s}' (usa retry[:N], nmap-top o mark-error)")),
+            },
+        }
+    }
+}
+
+impl fmt::Display for DiscoveryErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self { Self::Retry(n) => write!(f, "retry:{n}"), Self::NmapTop => write!(f, "nmap-top"), Self::MarkError => write!(f, "mark-error") }
+    }
+}
+
+/// Cómo se hace el descubrimiento activo (`--discovery`, `--rs-concurrency`, opciones `--scan-*` del escáner nativo,
+/// de RustScan y `--on-discovery-error`).
+#[derive(Debug, Clone)]
+pub struct DiscoveryOptions { pub engine: DiscoveryEngine, pub concurrency: usize, pub native: NativeScanOptions, pub rustscan: RustscanOptions, pub on_error: DiscoveryErrorPolicy }
+
+async fn run_engine(ips: &[String], d: &DiscoveryOptions) -> Result<Vec<IpPorts>> {
+    match d.engine {
+        DiscoveryEngine::Rustscan => rustscan_many_with_progress(ips, d.concurrency, &d.rustscan).await,
+        DiscoveryEngine::Native => native_scan_many_with_progress(ips, d.concurrency, &d.native).await,
+    }
+}
+
+/// Descubrimiento sobre `ips` con los reintentos de `retry:N`; cada host queda con el estado de su último intento.
+pub async fn discover(ips: &[String], d: &DiscoveryOptions) -> Result<Vec<IpPorts>> {
+    let mut results = run_engine(ips, d).await?;
+    if let DiscoveryErrorPolicy::Retry(n) = d.on_error {
+        for attempt in 1..=n {
+            let failed: Vec<String> = results.iter().filter(|r| !r.status.is_ok()).map(|r| r.ip.clone()).collect();
+            if failed.is_empty() { break; }
+            eprintln!("[DISCOVERY] Reintento {attempt}/{n} de {} host(s) fallido(s)", failed.len());
+            for r in run_engine(&failed, d).await? { if let Some(slot) = results.iter_mut().find(|x| x.ip == r.ip) { *slot = r; } }
+        }
+    }
+    for r in results.iter().filter(|r| !r.status.is_ok()) { eprintln!("[WARN] Descubrimiento fallido en {} ({}): {}", r.ip, r.status, r.error.as_deref().unwrap_or("")); }
+    Ok(results)
+}
+
+/// Mapa IP -> puertos TCP para Nmap, puertos UDP conocidos (`U:` de `--fixed-ports`, banners UDP de Shodan; solo se usan con -sU)
+/// y los hosts cuyo descubrimiento falló (IP -> descripción del fallo y de lo que se hizo).
+/// Con `mark-error` (o `retry` agotado) el host fallido no está en `ports` y no se escanea.
+#[derive(Debug, Default)]
+pub struct Discovered { pub ports: PortsMap, pub udp: PortsMap, pub failures: BTreeMap<String, String> }
+
+impl Discovered {
+    /// Pares (objetivo, IP) que pasan a Nmap.
+    pub fn nmap_pairs(&self, ips: &[String]) -> Vec<(String, String)> { ips.iter().filter(|ip| self.ports.contains_key(*ip)).map(|ip| (ip.clone(), ip.clone())).collect() }
+
+    /// Anota el fallo en los reportes de Nmap y agrega un reporte sin puertos por cada host que no se escaneó.
+    pub fn annotate(&self, reports: &mut Vec<HostReport>) {
+        for (ip, err) in &self.failures {
+            match reports.iter_mut().find(|r| &r.ip == ip) {
+                Some(r) => r.discovery_error = Some(err.clone()),
+                None => reports.push(HostReport { target: ip.clone(), ip: ip.clone(), discovery_error: Some(err.clone()), ..Default::default() }),
+            }
+        }
+    }
+}
+
+/// Puertos TCP que Shodan vio por IP (ordenados y sin duplicados).
+pub fn shodan_ports_map(ips: &[String], matches: &[ShodanMatch]) -> PortsMap { shodan_map(ips, matches, false) }
+
+/// Puertos UDP que Shodan vio por IP (solo las IPs con alguno).
+pub fn shodan_udp_map(ips: &[String], matches: &[ShodanMatch]) -> PortsMap {
+    let mut m = shodan_map(ips, matches, true); m.retain(|_, v| !v.is_empty()); m
+}
+
+fn shodan_map(ips: &[String], matches: &[ShodanMatch], udp: bool) -> PortsMap {
+    let mut m = PortsMap::new();
+    for ip in ips {
+        let mut ports: Vec<u16> = matches.iter().filter(|x| &x.ip == ip && x.port > 0 && (x.transport.as_deref() == Some("udp")) == udp).map(|x| x.port).collect();
+        ports.sort_unstable(); ports.dedup();
+        m.insert(ip.clone(), ports);
+    }
+    m
+}
+
+/// Reparte los resultados del descubrimiento según la política: los fallidos van con lista vacía (top-ports de Nmap)
+/// o fuera del mapa (marcados como error; también con `retry`, cuyos reintentos ya se hicieron en `discover`).
+pub fn apply_policy(results: Vec<IpPorts>, policy: DiscoveryErrorPolicy) -> Discovered {
+    let mut d = Discovered::default();
+    for r in results {
+        if r.status.is_ok() { d.ports.insert(r.ip, r.ports); continue; }
+        let what = format!("{}: {}", r.status, r.error.unwrap_or_default());
+        if policy == DiscoveryErrorPolicy::NmapTop {
+            d.failures.insert(r.ip.clone(), format!("{what}; Nmap con sus top-ports"));
+            d.ports.insert(r.ip, Vec::new());
+        } else {
+            d.failures.insert(r.ip, format!("{what}; marcado como error, sin Nmap"));
+        }
+    }
+    d
+}
+
+/// Construye el mapa IP -> puertos para Nmap según `source`.
+/// Una lista vacía hace que Nmap use su set por defecto (top 1000).
+/// `nmap_only` omite el descubrimiento (variables RUST_SHODAN_*_NMAP_ONLY); si `rs_jsonl` existe se anexan ahí sus resultados
+/// (mismo formato con RustScan o con el escáner nativo; los fallidos llevan `status` y `error`).
+pub async fn build_ports_map(source: PortSource, ips: &[String], fixed_ports: Option<&str>, matches: &[ShodanMatch], discovery: &DiscoveryOptions, nmap_only: bool, rs_jsonl: Option<&Path>) -> Result<Discovered> {
+    let empty = || Discovered { ports: ips.iter().map(|ip| (ip.clone(), Vec::new())).collect(), ..Default::default() };
+    let active = || async {
+        if nmap_only { return Ok::<Discovered, anyhow::Error>(empty()); }
+        let rs = discover(ips, discovery).await?;
+        if let Some(path) = rs_jsonl {
+            use std::io::Write;
+            let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+            for r in &rs { writeln!(file, "{}", serde_json::to_string(r)?)?; }
+        }
+        Ok(apply_policy(rs, discovery.on_error))
+    };
+    match source {
+        PortSource::Fixed => {
+            let fp = fixed_ports.ok_or_else(|| anyhow!("--port-source fixed requiere --fixed-ports"))?;
+            let fixed = PortSpec::parse(fp)?;
+            let udp = if fixed.udp.is_empty() { PortsMap::new() } else { ips.iter().map(|ip| (ip.clone(), fixed.udp.clone())).collect() };
+            Ok(Discovered { ports: ips.iter().map(|ip| (ip.clone(), fixed.tcp.clone())).collect(), udp, ..Default::default() })
+        }
+        PortSource::Shodan => Ok(Discovered { ports: shodan_ports_map(ips, matches), udp: shodan_udp_map(ips, matches), ..Default::default() }),
+        PortSource::Rustscan => active().await,
+        PortSource::Union => {
+            let shodan = shodan_ports_map(ips, matches);
+            let mut d = active().await?;
+            d.udp = shodan_udp_map(ips, matches);
+            // Un host fallido sigue la política (top-ports o fuera); los demás suman lo que vio Shodan
+            for (ip, ports) in d.ports.iter_mut() { if !d.failures.contains_key(ip) && let Some(s) = shodan.get(ip) { ports.extend(s); ports.sort_unstable(); ports.dedup(); } }
+            Ok(d)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::DiscoveryStatus;
+
+    #[test]
+    fn shodan_ports_per_ip_skip_udp() {
+        let m = |ip: &str, port: u16, t: &str| ShodanMatch { ip: ip.into(), port, transport: Some(t.into()), ..Default::default() };
+        let matches = vec![m("1.1.1.1", 443, "tcp"), m("1.1.1.1", 80, "tcp"), m("1.1.1.1", 443, "tcp"), m("1.1.1.1", 161, "udp"), m("2.2.2.2", 22, "tcp")];
+        let map = shodan_ports_map(&["1.1.1.1".into(), "3.3.3.3".into()], &matches);
+        assert_eq!(map["1.1.1.1"], vec![80, 443]);
+        assert!(map["3.3.3.3"].is_empty());
+        assert!(!map.contains_key("2.2.2.2"));
+        let udp = shodan_udp_map(&["1.1.1.1".into(), "3.3.3.3".into()], &matches);
+        assert_eq!((udp["1.1.1.1"].as_slice(), udp.contains_key("3.3.3.3")), ([161].as_slice(), false));
+    }
+
+    #[test]
+    fn failure_policies() {
+        assert_eq!("retry".parse::<DiscoveryErrorPolicy>(), Ok(DiscoveryErrorPolicy::Retry(2)));
+        assert_eq!("retry:5".parse::<DiscoveryErrorPolicy>(), Ok(DiscoveryErrorPolicy::Retry(5)));
+        assert!("retry:0".parse::<DiscoveryErrorPolicy>().is_err() && "ignorar".parse::<DiscoveryErrorPolicy>().is_err());
+        let results = || vec![IpPorts::ok("1.1.1.1".into(), vec![22]), IpPorts::failed("2.2.2.2".into(), DiscoveryStatus::Timeout, "lento".into())];
+        let top = apply_policy(results(), DiscoveryErrorPolicy::NmapTop);
+        assert_eq!(top.ports["2.2.2.2"], Vec::<u16>::new());
+        assert!(top.failures["2.2.2.2"].starts_with("timeout: lento"));
+        let marked = apply_policy(results(), DiscoveryErrorPolicy::MarkError);
+        let ips = vec!["1.1.1.1".to_string(), "2.2.2.2".to_string()];
+        assert_eq!(marked.nmap_pairs(&ips), vec![("1.1.1.1".to_string(), "1.1.1.1".to_string())]);
+        let mut reports = vec![HostReport { target: "1.1.1.1".into(), ip: "1.1.1.1".into(), ..Default::default() }];
+        marked.annotate(&mut reports);
+        assert_eq!(reports.len(), 2);
+        assert!(reports[1].discovery_error.as_deref().unwrap().contains("sin Nmap"));
+    }
+}
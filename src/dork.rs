@@ -0,0 +1,292 @@
+//! Mini lenguaje de keywords: `,` = AND, `|` = OR, `( )` agrupa, `-x` excluye y `filtro:valor` pasa crudo a Shodan.
+//!
+//! Ejemplo: `chile, (muni | hospital), -port:23, product:"nginx"`.
+//! Una lista plana separada por comas produce exactamente el mismo dork que antes.
+use anyhow::{Result, anyhow, bail};
+use std::{collections::BTreeSet, fmt, str::FromStr};
+use crate::{shodan::expand_keyword, vocab::Vocabulary};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DorkExpr {
+    And(Vec<DorkExpr>),
+    Or(Vec<DorkExpr>),
+    Not(Box<DorkExpr>),
+    /// Palabra clave normalizada (minúsculas, sin comillas) que se expande con el vocabulario
+    Keyword(String),
+    /// Filtro Shodan literal (`port:3389`, `product:"nginx"`)
+    Raw(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token { LParen, RParen, Comma, Pipe, Text(String) }
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut out = Vec::new();
+    let mut cur = String::new();
+    let mut in_quotes = false;
+    let flush = |cur: &mut String, out: &mut Vec<Token>| { let t = cur.trim(); if !t.is_empty() { out.push(Token::Text(t.to_string())); } cur.clear(); };
+    for c in input.chars() {
+        if c == '"' { in_quotes = !in_quotes; cur.push(c); continue; }
+        if in_quotes { cur.push(c); continue; }
+        let tok = match c { '(' => Token::LParen, ')' => Token::RParen, ',' => Token::Comma, '|' => Token::Pipe, _ => { cur.push(c); continue; } };
+        flush(&mut cur, &mut out);
+        out.push(tok);
+    }
+    if in_quotes { bail!("comillas sin cerrar en '{input}'"); }
+    flush(&mut cur, &mut out);
+    Ok(out)
+}
+
+struct Parser { toks: Vec<Token>, pos: usize }
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> { self.toks.get(self.pos) }
+    fn next(&mut self) -> Option<Token> { let t = self.toks.get(self.pos).cloned(); self.pos += 1; t }
+
+    fn parse_and(&mut self) -> Result<DorkExpr> {
+        let mut items = Vec::new();
+        loop {
+            match self.peek() {
+                // Elementos vacíos (",,") se ignoran como en la lista clásica
+                Some(Token::Comma) => { self.pos += 1; continue; }
+                None | Some(Token::RParen) => break,
+                _ => items.push(self.parse_or()?),
+            }
+        }
+        Ok(if items.len() == 1 { items.remove(0) } else { DorkExpr::And(items) })
+    }
+
+    fn parse_or(&mut self) -> Result<DorkExpr> {
+        let mut items = vec![self.parse_unary()?];
+        while self.peek() == Some(&Token::Pipe) { self.pos += 1; items.push(self.parse_unary()?); }
+        Ok(if items.len() == 1 { items.remove(0) } else { DorkExpr::Or(items) })
+    }
+
+    fn parse_unary(&mut self) -> Result<DorkExpr> {
+        match self.next() {
+            Some(Token::LParen) => {
+                let inner = self.parse_and()?;
+                if self.next() != Some(Token::RParen) { bail!("falta ')'"); }
+                if inner == DorkExpr::And(vec![]) { bail!("grupo vacío '()'"); }
+                Ok(inner)
+            }
+            Some(Token::Text(t)) if t.starts_with('-') => {
+                let rest = t.trim_start_matches('-').trim();
+                // "-" suelto niega el siguiente elemento: -(a | b)
+                let inner = if rest.is_empty() { self.parse_unary()? } else { leaf(rest) };
+                Ok(DorkExpr::Not(Box::new(inner)))
+            }
+            Some(Token::Text(t)) => Ok(leaf(&t)),
+            Some(tok) => Err(anyhow!("token inesperado {tok:?}")),
+            None => Err(anyhow!("expresión incompleta")),
+        }
+    }
+}
+
+/// `campo:valor` (campo = letras, dígitos, `.`, `_`) se pasa crudo; el resto es keyword.
+fn is_raw_filter(t: &str) -> bool {
+    match t.split_once(':') {
+        Some((field, value)) => !value.is_empty() && field.chars().next().is_some_and(|c| c.is_ascii_alphabetic()) && field.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_'),
+        None => false,
+    }
+}
+
+fn leaf(t: &str) -> DorkExpr {
+    if is_raw_filter(t) { DorkExpr::Raw(t.to_string()) } else { DorkExpr::Keyword(t.replace(['"', '\''], "").trim().to_lowercase()) }
+}
+
+pub fn parse(input: &str) -> Result<DorkExpr> {
+    let mut p = Parser { toks: tokenize(input)?, pos: 0 };
+    let expr = p.parse_and()?;
+    if p.pos < p.toks.len() { bail!("')' sin abrir"); }
+    Ok(expr)
+}
+
+/// Forma intermedia con las negaciones empujadas a las hojas (De Morgan).
+#[derive(Debug, Clone, PartialEq)]
+enum Clause { Atom(String), Neg(String), All(Vec<Clause>), Any(Vec<Clause>) }
+
+/// Separa una cláusula `(a OR b OR c)` en sus átomos (para negarlos o descartarlos uno a uno).
+/// Solo corta en los OR del primer nivel; cualquier otra forma se deja entera.
+fn split_or(s: &str) -> Vec<String> {
+    let Some(inner) = s.strip_prefix('(').and_then(|x| x.strip_suffix(')')) else { return vec![s.to_string()] };
+    let (mut depth, mut in_quotes, mut start, mut parts) = (0i32, false, 0usize, Vec::new());
+    for (i, c) in inner.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '(' if !in_quotes => depth += 1,
+            ')' if !in_quotes => { depth -= 1; if depth < 0 { return vec![s.to_string()]; } }
+            ' ' if !in_quotes && depth == 0 && inner[i..].starts_with(" OR ") => { parts.push(inner[start..i].trim().to_string()); start = i + 4; }
+            _ => {}
+        }
+    }
+    if depth != 0 || parts.is_empty() { return vec![s.to_string()]; }
+    parts.push(inner[start..].trim().to_string());
+    parts
+}
+
+/// Campo de un átomo `campo:valor` (sin el `-` de exclusión).
+fn atom_field(atom: &str) -> &str { atom.trim_start_matches('-').split(':').next().unwrap_or("") }
+
+fn group(items: Vec<Clause>, all: bool) -> Option<Clause> {
+    let mut items = items;
+    match items.len() { 0 => None, 1 => items.pop(), _ => Some(if all { Clause::All(items) } else { Clause::Any(items) }) }
+}
+
+/// `dropped`: campos cuyos átomos se descartan al expandir keywords (los filtros crudos no se tocan).
+fn to_clause(e: &DorkExpr, vocab: &Vocabulary, negate: bool, dropped: &[&str]) -> Option<Clause> {
+    match e {
+        DorkExpr::Keyword(k) if k.is_empty() => None,
+        DorkExpr::Keyword(k) => {
+            let s = expand_keyword(k, vocab)?;
+            let all = split_or(&s);
+            // Nunca se vacía una keyword: si todos sus campos están descartados se conserva entera
+            let kept: Vec<String> = all.iter().filter(|a| !dropped.contains(&atom_field(a))).cloned().collect();
+            let atoms = if kept.is_empty() { all } else { kept };
+            // NOT (a OR b) = -a AND -b
+            if negate { group(atoms.into_iter().map(Clause::Neg).collect(), true) } else { group(atoms.into_iter().map(Clause::Atom).collect(), false) }
+        }
+        DorkExpr::Raw(r) => Some(if negate { Clause::Neg(r.clone()) } else { Clause::Atom(r.clone()) }),
+        DorkExpr::Not(inner) => to_clause(inner, vocab, !negate, dropped),
+        DorkExpr::And(xs) => group(xs.iter().filter_map(|x| to_clause(x, vocab, negate, dropped)).collect(), !negate),
+        DorkExpr::Or(xs) => group(xs.iter().filter_map(|x| to_clause(x, vocab, negate, dropped)).collect(), negate),
+    }
+}
+
+fn render_clause(c: &Clause) -> String {
+    match c {
+        Clause::Atom(s) => s.clone(),
+        Clause::Neg(s) => format!("-{s}"),
+        Clause::All(v) => format!("({})", v.iter().map(render_clause).collect::<Vec<_>>().join(" AND ")),
+        Clause::Any(v) => format!("({})", v.iter().map(render_clause).collect::<Vec<_>>().join(" OR ")),
+    }
+}
+
+/// Dork Shodan para la expresión: cada elemento del AND principal va entre paréntesis y sin duplicados.
+pub fn render(expr: &DorkExpr, vocab: &Vocabulary) -> String { render_without(expr, vocab, &[]).unwrap_or_else(|| vocab.fallback.clone()) }
+
+/// Como `render` pero sin los átomos de `dropped`; `None` si no queda ninguna cláusula.
+pub fn render_without(expr: &DorkExpr, vocab: &Vocabulary, dropped: &[&str]) -> Option<String> {
+    let top = match to_clause(expr, vocab, false, dropped)? {
+        Clause::All(v) => v,
+        c => vec![c],
+    };
+    let mut seen = BTreeSet::new();
+    Some(top.iter().map(render_clause).filter(|t| seen.insert(t.clone())).map(|t| format!("({t})")).collect::<Vec<_>>().join(" AND "))
+}
+
+/// Campos de búsqueda de texto ordenados del más caro al más barato para Shodan; `simplify` los descarta en este orden.
+pub const HEAVY_FIELDS: &[&str] = &["http.html", "ssl", "http.title", "ssl.cert.subject.cn", "ssl.cert.issuer.cn", "hostname", "org", "product"];
+
+/// Versiones cada vez más simples del dork: (campo descartado, dork resultante), acumulando descartes.
+/// Se omiten los pasos que no cambian nada; cada keyword conserva al menos un campo.
+pub fn simplifications(expr: &DorkExpr, vocab: &Vocabulary) -> Vec<(String, String)> {
+    let mut dropped: Vec<&str> = Vec::new();
+    let mut last = render(expr, vocab);
+    let mut out = Vec::new();
+    for field in HEAVY_FIELDS {
+        dropped.push(field);
+        match render_without(expr, vocab, &dropped) {
+            // Solo cuenta si el dork se acorta (descartar el último campo de una keyword la restaura entera)
+            Some(q) if q.len() < last.len() => { out.push((field.to_string(), q.clone())); last = q; }
+            _ => { dropped.pop(); }
+        }
+    }
+    out
+}
+
+/// Qué hacer si `/count` rechaza el dork: abortar (por defecto), usar otro dork o simplificarlo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DorkErrorPolicy { Abort, Fallback(String), Simplify }
+
+impl FromStr for DorkErrorPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(Self::Abort),
+            "simplify" => Ok(Self::Simplify),
+            _ => match s.strip_prefix("fallback:") {
+                Some(q) if !q.trim().is_empty() => Ok(Self::Fallback(q.trim().to_string())),
+                _ => Err(format!("política inválida '{s}' (usa abort, fallback:<query> o simplify)")),
+            },
+        }
+    }
+}
+
+impl fmt::Display for DorkErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self { Self::Abort => write!(f, "abort"), Self::Fallback(q) => write!(f, "fallback:{q}"), Self::Simplify => write!(f, "simplify") }
+    }
+}
+
+/// Árbol legible del AST con la expansión de cada keyword (subcomando `dork --explain`).
+pub fn explain(expr: &DorkExpr, vocab: &Vocabulary) -> String {
+    fn walk(e: &DorkExpr, vocab: &Vocabulary, depth: usize, out: &mut String) {
+        let pad = "  ".repeat(depth);
+        match e {
+            DorkExpr::And(xs) | DorkExpr::Or(xs) => {
+                out.push_str(&format!("{pad}{}\n", if matches!(e, DorkExpr::And(_)) { "AND" } else { "OR" }));
+                for x in xs { walk(x, vocab, depth + 1, out); }
+            }
+            DorkExpr::Not(x) => { out.push_str(&format!("{pad}NOT\n")); walk(x, vocab, depth + 1, out); }
+            DorkExpr::Keyword(k) => out.push_str(&format!("{pad}keyword \"{k}\" -> {}\n", expand_keyword(k, vocab).unwrap_or_else(|| "(sin cláusula)".into()))),
+            DorkExpr::Raw(r) => out.push_str(&format!("{pad}filtro {r}\n")),
+        }
+    }
+    let mut out = String::new();
+    walk(expr, vocab, 0, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dork(input: &str) -> String { render(&parse(input).unwrap(), &Vocabulary::builtin()) }
+
+    #[test]
+    fn parses_groups_negation_and_raw_filters() {
+        let e = parse("chile, (muni | .cl), -port:23, product:\"nginx\"").unwrap();
+        assert_eq!(e, DorkExpr::And(vec![
+            DorkExpr::Keyword("chile".into()),
+            DorkExpr::Or(vec![DorkExpr::Keyword("muni".into()), DorkExpr::Keyword(".cl".into())]),
+            DorkExpr::Not(Box::new(DorkExpr::Raw("port:23".into()))),
+            DorkExpr::Raw("product:\"nginx\"".into()),
+        ]));
+        assert!(parse("(chile").is_err());
+        assert!(parse("chile)").is_err());
+        assert!(parse("a | ").is_err());
+        assert!(parse("\"abc").is_err());
+    }
+
+    #[test]
+    fn renders_shodan_syntax() {
+        assert_eq!(dork("chile, -port:23, port:3389"), "(country:CL) AND (-port:23) AND (port:3389)");
+        assert_eq!(dork("chile | pe"), "((country:CL OR country:PE))");
+        // Negar una keyword niega cada campo de su OR
+        assert_eq!(dork("-nginx"), "(-http.title:\"nginx\") AND (-http.html:\"nginx\") AND (-org:\"nginx\") AND (-product:\"nginx\")");
+        assert_eq!(dork("-(chile | port:22)"), "(-country:CL) AND (-port:22)");
+        assert_eq!(dork("pe | -port:22, chile"), "((country:PE OR -port:22)) AND (country:CL)");
+        assert_eq!(dork(""), "country:CL");
+    }
+
+    #[test]
+    fn simplify_drops_heavy_fields_in_order() {
+        let vocab = Vocabulary::builtin();
+        let steps = simplifications(&parse("chile, nginx, -port:23").unwrap(), &vocab);
+        let fields: Vec<&str> = steps.iter().map(|(f, _)| f.as_str()).collect();
+        assert_eq!(fields, vec!["http.html", "http.title", "org"]);
+        assert_eq!(steps[0].1, "(country:CL) AND ((http.title:\"nginx\" OR org:\"nginx\" OR product:\"nginx\")) AND (-port:23)");
+        assert_eq!(steps[2].1, "(country:CL) AND (product:\"nginx\") AND (-port:23)");
+        assert_eq!(split_or("(a:\"x OR y\" OR (b:1 OR c:2))"), vec!["a:\"x OR y\"", "(b:1 OR c:2)"]);
+        assert_eq!("fallback:country:PE".parse::<DorkErrorPolicy>(), Ok(DorkErrorPolicy::Fallback("country:PE".into())));
+        assert!("fallback:".parse::<DorkErrorPolicy>().is_err());
+    }
+
+    #[test]
+    fn explain_lists_expansions() {
+        let text = explain(&parse("chile, -port:23").unwrap(), &Vocabulary::builtin());
+        assert!(text.contains("keyword \"chile\" -> country:CL"));
+        assert!(text.contains("NOT\n    filtro port:23"));
+    }
+}
@@ -1,11 +1,77 @@
 use anyhow::Result;
-use regex::Regex;
 use tokio::process::Command;
-use crate::{rules::Rules, models::HostReport};
+use crate::{rules::Rules, models::{HostReport, Protocol}};
 
 pub async fn run_dynamic_tools(rules: &Rules, reports: &Vec<HostReport>, out: &std::path::Path) -> Result<()> {
-    for h in reports { if h.ports.is_empty(){ continue; } let ip_dir = out.join(&h.ip); tokio::fs::create_dir_all(&ip_dir).await.ok(); for p in &h.ports { let mut matched_cmds: Vec<(&str, &String)> = Vec::new(); for rule in &rules.rules { let port_match = !rule.ports.is_empty() && rule.ports.contains(&p.port); let mut service_match = false; if let Some(re)= &rule.service_regex { if let Some(svc)= &p.service { if Regex::new(re).ok().map(|r| r.is_match(svc)).unwrap_or(false){ service_match = true; } } } if port_match || (rule.service_regex.is_some() && service_match) { for cmd in &rule.cmds { matched_cmds.push((&rule.name, cmd)); } } } for (rname, cmd_tpl) in matched_cmds { let cmd_line = cmd_tpl.replace("{ip}", &h.ip).replace("{target}", &h.target).replace("{port}", &p.port.to_string()).replace("{service}", &p.service.clone().unwrap_or_default()); println!("[{}] {}: {}", h.ip, rname, cmd_line); let log_path = ip_dir.join(format!("{}_{}.log", rname, p.port)); run_and_log(&cmd_line, &log_path).await?; } } }
+    for h in reports {
+        if h.ports.is_empty() && h.host_scripts.is_empty() { continue; }
+        let ip_dir = out.join(&h.ip); tokio::fs::create_dir_all(&ip_dir).await.ok();
+        for p in &h.ports {
+            let matched_cmds: Vec<(&str, &String)> = rules.rules.iter().filter(|r| r.matches_port(p)).flat_map(|r| r.cmds.iter().map(move |c| (r.name.as_str(), c))).collect();
+            for (rname, cmd_tpl) in matched_cmds {
+                let targets = targets_for(cmd_tpl, h);
+                for target in &targets {
+                    let cmd_line = cmd_tpl.replace("{ip}", &h.ip).replace("{target}", target).replace("{hostname}", hostname(h)).replace("{port}", &p.port.to_string()).replace("{protocol}", &p.protocol.to_string()).replace("{service}", &p.service.clone().unwrap_or_default());
+                    println!("[{}] {}: {}", h.ip, rname, cmd_line);
+                    let base = if p.protocol == Protocol::Udp { format!("{}_{}_udp", rname, p.port) } else { format!("{}_{}", rname, p.port) };
+                    run_and_log(&cmd_line, &ip_dir.join(log_name(&base, target, targets.len()))).await?;
+                }
+            }
+        }
+        // Scripts de host (<hostscript>): una ejecución por host, sin puerto ni servicio
+        for rule in rules.rules.iter().filter(|r| r.matches_scripts(&h.host_scripts)) {
+            for cmd_tpl in &rule.cmds {
+                let targets = targets_for(cmd_tpl, h);
+                for target in &targets {
+                    let cmd_line = cmd_tpl.replace("{ip}", &h.ip).replace("{target}", target).replace("{hostname}", hostname(h)).replace("{port}", "").replace("{protocol}", "").replace("{service}", "");
+                    println!("[{}] {}: {}", h.ip, rule.name, cmd_line);
+                    run_and_log(&cmd_line, &ip_dir.join(log_name(&format!("{}_host", rule.name), target, targets.len()))).await?;
+                }
+            }
+        }
+    }
     Ok(())
 }
 
+/// Nombre para `{hostname}`: primer PTR, si no el primer objetivo que no sea la propia IP, si no la IP.
+pub fn hostname(h: &HostReport) -> &str {
+    h.ptr.first().map(String::as_str).or_else(|| h.target.split(',').find(|t| !t.is_empty() && *t != h.ip)).unwrap_or(&h.ip)
+}
+
+/// Valores de `{target}`: cada hostname del host (`target` va separado por comas) o la IP si no tiene; uno solo si la plantilla no lo usa.
+fn targets_for<'a>(tpl: &str, h: &'a HostReport) -> Vec<&'a str> {
+    let names: Vec<&str> = h.target.split(',').filter(|t| !t.is_empty()).collect();
+    if names.is_empty() || !tpl.contains("{target}") { vec![names.first().copied().unwrap_or(&h.ip)] } else { names }
+}
+
+/// Con varios hostnames cada ejecución lleva el suyo en el log para no pisarse.
+fn log_name(base: &str, target: &str, count: usize) -> String { if count > 1 { format!("{base}_{target}.log") } else { format!("{base}.log") } }
+
 async fn run_and_log(cmd_line: &str, log_path: &std::path::Path) -> Result<()> { let parts = shell_words::split(cmd_line)?; if parts.is_empty(){ return Ok(()); } let (bin, args) = parts.split_first().unwrap(); let output = Command::new(bin).args(args).output().await?; let mut content = String::new(); content.push_str(&format!("$ {}\n\n", cmd_line)); content.push_str(&String::from_utf8_lossy(&output.stdout)); if !output.stderr.is_empty(){ content.push_str("\n[stderr]\n"); content.push_str(&String::from_utf8_lossy(&output.stderr)); } tokio::fs::write(log_path, content).await?; Ok(()) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::PortDetail;
+
+    #[test]
+    fn target_expands_per_hostname() {
+        let h = HostReport { ip: "192.0.2.10".into(), target: "a.ejemplo.cl,b.ejemplo.cl".into(), ..Default::default() };
+        assert_eq!(targets_for("curl https://{target}/", &h), vec!["a.ejemplo.cl", "b.ejemplo.cl"]);
+        assert_eq!(targets_for("curl http://{ip}/", &h).len(), 1);
+        assert_eq!(targets_for("curl https://{target}/", &HostReport { ip: "192.0.2.10".into(), ..Default::default() }), vec!["192.0.2.10"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn runs_once_per_hostname() {
+        let dir = std::env::temp_dir().join(format!("shodan-pipeline-dynamic-{}", std::process::id()));
+        let rules: Rules = serde_yaml::from_str("rules:\n  - name: web\n    ports: [443]\n    cmds: [\"echo https://{target}:{port}/\"]\n").unwrap();
+        let h = HostReport { ip: "192.0.2.10".into(), target: "a.ejemplo.cl,b.ejemplo.cl".into(), ports: vec![PortDetail { port: 443, ..Default::default() }], ..Default::default() };
+        run_dynamic_tools(&rules, &vec![h], &dir).await.unwrap();
+        let log = |n: &str| std::fs::read_to_string(dir.join("192.0.2.10").join(n)).unwrap();
+        assert!(log("web_443_a.ejemplo.cl.log").contains("https://a.ejemplo.cl:443/"));
+        assert!(log("web_443_b.ejemplo.cl.log").contains("https://b.ejemplo.cl:443/"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,164 @@
+//! Limitador de tasa (token bucket) y política de reintentos compartidos por todas las llamadas HTTP a Shodan.
+use anyhow::Result;
+use reqwest::{Client, Response, StatusCode, header::RETRY_AFTER};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::{sync::Mutex, time::sleep};
+
+/// Token bucket: `rate_per_sec` fichas por segundo con ráfaga máxima `burst`.
+#[derive(Debug)]
+pub struct RateLimiter { rate_per_sec: f64, burst: f64, state: Mutex<(f64, Instant)> }
+
+impl RateLimiter {
+    pub fn new(rate_per_sec: f64, burst: u32) -> Self {
+        let burst = burst.max(1) as f64;
+        Self { rate_per_sec: rate_per_sec.max(0.001), burst, state: Mutex::new((burst, Instant::now())) }
+    }
+
+    /// Espera hasta que haya una ficha disponible y la consume.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut st = self.state.lock().await;
+                let now = Instant::now();
+                st.0 = (st.0 + now.duration_since(st.1).as_secs_f64() * self.rate_per_sec).min(self.burst);
+                st.1 = now;
+                if st.0 >= 1.0 { st.0 -= 1.0; return; }
+                Duration::from_secs_f64((1.0 - st.0) / self.rate_per_sec)
+            };
+            sleep(wait).await;
+        }
+    }
+}
+
+/// Reintentos ante 429, 5xx y errores de red/timeout: backoff exponencial con jitter, respetando `Retry-After`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy { pub max_attempts: u32, pub base_delay: Duration, pub max_delay: Duration }
+
+impl Default for RetryPolicy {
+    fn default() -> Self { Self { max_attempts: 4, base_delay: Duration::from_millis(500), max_delay: Duration::from_secs(30) } }
+}
+
+impl RetryPolicy {
+    /// Espera antes del reintento `attempt` (1 = primer reintento): base·2^(n-1) acotado a `max_delay`, con jitter del 50–100%.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self.base_delay.saturating_mul(1u32 << attempt.saturating_sub(1).min(16)).min(self.max_delay);
+        exp.mul_f64(0.5 + jitter() * 0.5)
+    }
+
+    fn retryable_status(status: StatusCode) -> bool { status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() }
+}
+
+/// Fracción pseudoaleatoria en [0, 1) (suficiente para desincronizar reintentos).
+fn jitter() -> f64 {
+    let n = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    (n.wrapping_mul(2654435761) % 1_000_000) as f64 / 1_000_000.0
+}
+
+/// `Retry-After` en segundos (no se soporta la forma de fecha HTTP).
+fn retry_after(resp: &Response) -> Option<Duration> {
+    resp.headers().get(RETRY_AFTER)?.to_str().ok()?.trim().parse::<u64>().ok().map(Duration::from_secs)
+}
+
+/// GET con limitador + reintentos. Si se agotan los intentos ante 429/5xx devuelve la última respuesta
+/// (el llamador decide); ante error de red agotado devuelve el error.
+pub async fn get_with_retry(client: &Client, url: &str, limiter: &RateLimiter, policy: &RetryPolicy) -> Result<Response> {
+    let max = policy.max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        limiter.acquire().await;
+        match client.get(url).send().await {
+            Ok(resp) if RetryPolicy::retryable_status(resp.status()) && attempt < max => {
+                let wait = retry_after(&resp).map(|d| d.min(policy.max_delay)).unwrap_or_else(|| policy.backoff(attempt));
+                eprintln!("[!] HTTP {} – reintento {}/{} en {:.1}s", resp.status(), attempt, max - 1, wait.as_secs_f64());
+                sleep(wait).await;
+            }
+            Ok(resp) => return Ok(resp),
+            Err(e) if (e.is_timeout() || e.is_connect() || e.is_request()) && attempt < max => {
+                let wait = policy.backoff(attempt);
+                eprintln!("[!] Error de red ({}) – reintento {}/{} en {:.1}s", if e.is_timeout() { "timeout" } else { "conexión" }, attempt, max - 1, wait.as_secs_f64());
+                sleep(wait).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+
+    /// Servidor HTTP mínimo: responde en orden las respuestas crudas dadas (repite la última) y cuenta peticiones.
+    /// Una respuesta vacía significa "no contestar" (para provocar timeouts).
+    async fn mock_server(responses: Vec<&'static str>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let h = hits.clone();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut sock, _)) = listener.accept().await else { break };
+                let n = h.fetch_add(1, Ordering::SeqCst);
+                let raw = responses[n.min(responses.len() - 1)];
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 4096];
+                    let _ = sock.read(&mut buf).await;
+                    if raw.is_empty() { sleep(Duration::from_secs(5)).await; return; }
+                    let _ = sock.write_all(raw.as_bytes()).await;
+                    let _ = sock.shutdown().await;
+                });
+            }
+        });
+        (format!("http://{addr}/"), hits)
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+    const TOO_MANY: &str = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const UNAVAILABLE: &str = "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+    fn fast_policy(max_attempts: u32) -> RetryPolicy { RetryPolicy { max_attempts, base_delay: Duration::from_millis(10), max_delay: Duration::from_millis(50) } }
+
+    #[tokio::test]
+    async fn retries_429_and_5xx_until_success() {
+        let (url, hits) = mock_server(vec![TOO_MANY, UNAVAILABLE, OK]).await;
+        let resp = get_with_retry(&Client::new(), &url, &RateLimiter::new(1000.0, 10), &fast_policy(4)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts_and_returns_last_response() {
+        let (url, hits) = mock_server(vec![UNAVAILABLE]).await;
+        let resp = get_with_retry(&Client::new(), &url, &RateLimiter::new(1000.0, 10), &fast_policy(3)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retries_timeouts() {
+        let (url, hits) = mock_server(vec!["", OK]).await;
+        let client = Client::builder().timeout(Duration::from_millis(200)).build().unwrap();
+        let resp = get_with_retry(&client, &url, &RateLimiter::new(1000.0, 10), &fast_policy(3)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_spaces_requests() {
+        let limiter = RateLimiter::new(20.0, 1);
+        let start = Instant::now();
+        for _ in 0..5 { limiter.acquire().await; }
+        // 1 ficha inicial + 4 a 50ms c/u
+        assert!(start.elapsed() >= Duration::from_millis(180), "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        let p = RetryPolicy { max_attempts: 10, base_delay: Duration::from_millis(100), max_delay: Duration::from_secs(1) };
+        assert!(p.backoff(1) <= Duration::from_millis(100) && p.backoff(1) >= Duration::from_millis(50));
+        assert!(p.backoff(3) >= Duration::from_millis(200));
+        assert!(p.backoff(20) <= Duration::from_secs(1));
+    }
+}
@@ -2,10 +2,21 @@
 pub mod args;
 pub mod rules;
 pub mod models;
+pub mod http;
+pub mod cache;
 pub mod shodan;
+pub mod dork;
+pub mod vocab;
 pub mod targets;
+pub mod scope;
+pub mod reserved;
+pub mod rdns;
 pub mod rustscan;
+pub mod portscan;
+pub mod discovery;
 pub mod nmap;
 pub mod dynamic;
+pub mod campaign;
 pub mod output;
 pub mod config;
+pub mod profile;
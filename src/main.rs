@@ -2,19 +2,65 @@
 use anyhow::Result;
 use clap::Parser;
 use shodan_pipeline::{
-    args::{Args, Cmd},
-    config::{load_key_from_file, save_key, config_file},
+    args::{Args, Cmd, DiscoveryEngine, PortSource, RustscanSettings},
+    cache::{CacheMode, DiskCache},
+    campaign::{load_campaign, run_campaign, ScanOptions},
+    config::{load_base_url_from_file, load_key_from_file, save_base_url, save_key, config_file, DEFAULT_SHODAN_BASE_URL},
+    discovery::{apply_policy, build_ports_map, discover, Discovered, DiscoveryOptions},
+    dork,
     dynamic::run_dynamic_tools,
+    http::RetryPolicy,
     models::IpPorts,
-    nmap::{nmap_many_with_progress, split_ports, confirm_tcpwrapped},
-    output::{export_csv, export_json, export_markdown, read_jsonl, summarize, write_jsonl, print_host_details, print_host_details_with_interest, filter_ports, is_interesting_host},
+    nmap::{nmap_many_with_progress, confirm_tcpwrapped, PortSpec},
+    rdns::PtrEnricher,
+    output::{export_csv, export_json, export_markdown, export_target_index, export_stats_csv, export_stats_json, print_dork_changes, print_stats, read_jsonl, summarize, write_jsonl, print_host_details, print_host_details_with_interest, filter_ports, is_interesting_host},
     rules::{load_rules, Rules},
-    rustscan::rustscan_many_with_progress,
-    shodan::{build_dork_from_keywords, http_client, shodan_collect, shodan_precheck_count},
-    targets::{load_targets, resolve_targets},
+    reserved::drop_reserved_matches,
+    portscan::NativeScanOptions,
+    profile::{load_profile, Profile},
+    rustscan::RustscanOptions,
+    scope::{add_match_names, add_target_names, Scope},
+    shodan::{account_guard, attach_shodan_hosts, attach_shodan_matches, enrich_hosts, build_dork_with_vocab, export_collected, facets_param, shodan_collect, shodan_count, check_dork, unique_ips, ShodanApi, DEFAULT_FACETS},
+    targets::{attach_tags, attach_targets, DnsOptions, load_targets, resolve_targets},
+    vocab::Vocabulary,
 };
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Cliente Shodan con el presupuesto y la política HTTP de la línea de comandos.
+fn shodan_api(args: &Args, key: &str) -> Result<ShodanApi> {
+    let retry = RetryPolicy { max_attempts: args.shodan_max_attempts, ..RetryPolicy::default() };
+    // URL base: --shodan-base-url / SHODAN_BASE_URL > config persistente > API pública
+    let base_url = args.shodan_base_url.clone().or_else(load_base_url_from_file).unwrap_or_else(|| DEFAULT_SHODAN_BASE_URL.into());
+    if base_url != DEFAULT_SHODAN_BASE_URL { println!("[*] API Shodan: {base_url}"); }
+    Ok(ShodanApi::new(key, args.max_credits, args.shodan_rate, retry)?.with_base_url(&base_url).with_cache(disk_cache(args)))
+}
+
+/// Caché en disco según `--cache-dir`, `--cache-ttl`, `--no-cache` y `--refresh` (Shodan y PTR).
+fn disk_cache(args: &Args) -> DiskCache {
+    let mode = if args.no_cache { CacheMode::Off } else if args.refresh { CacheMode::Refresh } else { CacheMode::Use };
+    let cache_dir = args.cache_dir.clone().unwrap_or_else(|| args.out.join("shodan_cache"));
+    DiskCache::new(cache_dir, Duration::from_secs(args.cache_ttl), mode)
+}
+
+/// Resolvedor DNS de los objetivos según `--dns-*`.
+fn dns_options(args: &Args) -> DnsOptions {
+    DnsOptions { servers: args.dns_server.clone(), system: args.dns_system, timeout_ms: args.dns_timeout_ms, attempts: args.dns_attempts, concurrency: args.dns_concurrency }
+}
+
+/// Descubrimiento activo según `--discovery` y `--scan-*`, con `concurrency` hosts simultáneos; los `--rs-*` de `rs` priman sobre el perfil.
+fn discovery_options(args: &Args, profile: &Profile, concurrency: usize, rs: &RustscanSettings) -> Result<DiscoveryOptions> {
+    let native = NativeScanOptions::new(&args.scan_ports, args.scan_timeout_ms, args.scan_max_sockets, args.scan_batch)?;
+    let settings = rs.clone().or(&profile.rustscan);
+    settings.validate()?;
+    let rustscan = RustscanOptions { settings, host_timeout: Duration::from_secs(args.rs_host_timeout_secs), out_dir: Some(args.out.clone()) };
+    Ok(DiscoveryOptions { engine: args.discovery, concurrency, native, rustscan, on_error: args.on_discovery_error })
+}
+
+/// DNS inverso de los reportes salvo `--no-ptr`.
+fn ptr_enricher(args: &Args) -> Option<PtrEnricher> { (!args.no_ptr).then(|| PtrEnricher::new(dns_options(args), disk_cache(args))) }
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -32,11 +78,24 @@ async fn main() -> Result<()> {
     tokio::fs::create_dir_all(&args.out).await.ok();
 
     let debug = args.debug;
+    // Vocabulario keywords -> dork: --vocab o el embebido (vocab/default.yaml)
+    let vocab = match &args.vocab { Some(p) => Vocabulary::load(p)?, None => Vocabulary::builtin() };
+    // Perfil del pipeline: valores por defecto de --rs-* (los flags priman)
+    let profile = match &args.profile { Some(p) => load_profile(p)?, None => Profile::default() };
+    // Alcance autorizado: filtra IPs antes de RustScan / Nmap / reglas y bloquea fuera de la ventana del engagement
+    let scope = Arc::new(Scope::load(args.allowlist.as_deref(), args.denylist.as_deref(), args.engagement.as_deref(), &args.out.join("out_of_scope.txt"))?);
+    if matches!(args.cmd, Cmd::Full { .. } | Cmd::Campaign { .. } | Cmd::Rustscan { .. } | Cmd::Nmap { .. }) {
+        scope.check_window()?;
+        if let Some(e) = &scope.engagement { println!("[SCOPE] Autorización vigente: {}", e.client); }
+    }
     match args.cmd.clone() {
-    Cmd::Config { set, show_path } => {
+    Cmd::Config { set, set_base_url, show_path } => {
             if let Some(value) = set {
                 let path = save_key(&value)?;
                 println!("[+] API key guardada en {}", path.display());
+            } else if let Some(url) = set_base_url {
+                let path = save_base_url(&url)?;
+                println!("[+] URL base Shodan guardada en {}", path.display());
             } else if show_path {
                 let path = config_file()?;
                 println!("Ruta archivo key: {}", path.display());
@@ -47,22 +106,36 @@ async fn main() -> Result<()> {
             }
             return Ok(());
         }
-    Cmd::Full { keywords, limit, interesting_target, interesting_min_open, pages, targets, fixed_ports, rs_concurrency, nmap_concurrency, nmap_extra, rules, resume, hide_tcpwrapped, only_open, confirm_wrapped, hunt, hunt_needed, hunt_min_open, hunt_batch } => {
+    Cmd::Full { keywords, limit, interesting_target, interesting_min_open, pages, targets, fixed_ports, port_source, rs_concurrency, nmap_concurrency, nmap_extra, rules, resume, hide_tcpwrapped, only_open, confirm_wrapped, hunt, hunt_needed, hunt_min_open, hunt_batch, no_enrich, enrich_history, on_dork_error, rs } => {
+            // El archivo de objetivos se valida antes de gastar créditos o escanear
+            let targets = targets.map(|t| load_targets(&t, args.max_hosts)).transpose()?;
             let key = key_resolved.expect("Falta API key (usa --key, variable SHODAN_API_KEY o 'config --set')");
-            let client = http_client()?;
-            let mut query = build_dork_from_keywords(&keywords);
-            println!("[*] Dork Shodan: {query}");
-            if let Err(e) = shodan_precheck_count(&client, &key, &query).await {
-                eprintln!("[WARN] Dork inválido (/count): {e}");
-                eprintln!("[WARN] Fallback a country:CL");
-                query = "country:CL".into();
-                println!("[*] Dork Fallback: {query}");
-            }
+            let mut api = shodan_api(&args, &key)?;
+            account_guard(&mut api).await;
+            let expr = dork::parse(&keywords)?;
+            println!("[*] Dork Shodan: {}", dork::render(&expr, &vocab));
+            // Pre-vuelo: /count con facets valida el dork y dimensiona la campaña
+            let checked = check_dork(&api, &expr, &vocab, &on_dork_error, &facets_param(DEFAULT_FACETS, 5)).await?;
+            let query = checked.query.clone();
+            if !checked.changes.is_empty() { println!("[*] Dork usado: {query}"); }
+            print_stats(&checked.stats, 5);
+            export_stats_json(&args.out.join("stats.json"), &checked.stats)?;
             if debug { eprintln!("[DEBUG] Iniciando recolección Shodan limit={limit} pages={pages}"); }
 
+            let port_source = PortSource::resolve(port_source, fixed_ports.as_deref());
+            let discovery = discovery_options(&args, &profile, rs_concurrency, &rs)?;
+            // Solo la fuente `fixed` fuerza `-p <matriz>` en Nmap; el resto usa el mapa IP -> puertos
+            let nmap_fixed = if port_source == PortSource::Fixed { fixed_ports.as_deref() } else { None };
+            if debug { eprintln!("[DEBUG] Fuente de puertos: {port_source:?}"); }
+
             // 1) Shodan → IPs (modo simple o adaptativo)
-            let mut ip_seed = shodan_collect(&client, &key, &query, limit, pages, &args.out, debug).await?;
-            println!("[*] Shodan → {} IPs", ip_seed.len());
+            let mut shodan_matches = drop_reserved_matches(shodan_collect(&api, &query, limit, pages, port_source.uses_shodan(), debug).await?, args.allow_private);
+            let mut ip_seed = unique_ips(&shodan_matches);
+            export_collected(&args.out, &ip_seed, &shodan_matches)?;
+            // Hostnames por IP para las reglas de dominio del alcance
+            let mut scope_names = BTreeMap::new();
+            add_match_names(&mut scope_names, &shodan_matches);
+            println!("[*] Shodan → {} IPs ({} banners, {} créditos de consulta)", ip_seed.len(), shodan_matches.len(), api.budget.spent());
             if hunt {
                 use std::cmp::min;
                 use shodan_pipeline::models::HostReport;
@@ -74,43 +147,38 @@ async fn main() -> Result<()> {
 
                 while needed > 0 && cursor < ip_seed.len() {
                     let end = min(cursor + hunt_batch, ip_seed.len());
-                    let batch = &ip_seed[cursor..end];
+                    let batch = &scope.filter_ips(&ip_seed[cursor..end], &scope_names)?;
+                    println!("[HUNT] Lote {cursor}..{end} ({} IPs, {} en alcance)", end - cursor, batch.len());
                     cursor = end;
-                    println!("[HUNT] Lote {}..{} ({} IPs)", end.saturating_sub(batch.len()), end, batch.len());
                     let hunt_nmap_only = std::env::var("RUST_SHODAN_HUNT_NMAP_ONLY").map(|v| v=="1" || v.eq_ignore_ascii_case("true")).unwrap_or(false);
                     if hunt_nmap_only { println!("[HUNT] Modo solo Nmap habilitado (RUST_SHODAN_HUNT_NMAP_ONLY=1)"); }
-                    // RustScan lote
-                    let ports_map = if let Some(fp) = &fixed_ports {
-                        let fixed = split_ports(fp)?; let mut m = BTreeMap::new(); for ip in batch { m.insert(ip.clone(), fixed.clone()); } m
-                    } else {
-                        if hunt_nmap_only {
-                            // Mapa vacío para permitir que Nmap use su set por defecto (top 1000) sin pre-descubrimiento.
-                            let mut m = BTreeMap::new();
-                            for ip in batch { m.insert(ip.clone(), Vec::new()); }
-                            m
-                        } else {
-                            let rs = rustscan_many_with_progress(&batch.to_vec(), rs_concurrency, 1500, 4500).await?; let mut m = BTreeMap::new(); for r in rs { m.insert(r.ip, r.ports); } m
-                        }
-                    };
+                    // Descubrimiento de puertos del lote
+                    let discovered = build_ports_map(port_source, batch, fixed_ports.as_deref(), &shodan_matches, &discovery, hunt_nmap_only, None).await?;
                     // Nmap lote
-                    let pairs: Vec<(String,String)> = batch.iter().map(|ip| (ip.clone(), ip.clone())).collect();
-                    let mut reports = nmap_many_with_progress(&pairs, &ports_map, &args.out, &nmap_extra, fixed_ports.as_deref(), nmap_concurrency, resume).await?;
+                    let pairs = discovered.nmap_pairs(batch);
+                    let mut reports = nmap_many_with_progress(&pairs, &discovered, &args.out, &nmap_extra, nmap_fixed, nmap_concurrency, resume).await?;
+                    discovered.annotate(&mut reports);
                     if confirm_wrapped { println!("[*] Confirmando puertos tcpwrapped..."); confirm_tcpwrapped(&mut reports).await.ok(); }
+                    attach_shodan_matches(&mut reports, &shodan_matches);
+                    if let Some(ptr) = ptr_enricher(&args) { ptr.enrich(&mut reports).await?; }
                     // Filtro y conteo
-                    for mut rep in reports { rep.ports = filter_ports(&rep.ports, hide_tcpwrapped, only_open); if is_interesting_host(&rep.ports, hunt_min_open) { if !interesting.iter().any(|x| x.ip == rep.ip) { interesting.push(rep.clone()); if needed>0 { needed -=1; println!("[HUNT] +1 interesante {} (faltan {})", rep.ip, needed); } } } all_reports.push(rep); }
+                    for mut rep in reports { rep.ports = filter_ports(&rep.ports, hide_tcpwrapped, only_open); if is_interesting_host(&rep.ports, hunt_min_open) && !interesting.iter().any(|x| x.ip == rep.ip) { interesting.push(rep.clone()); if needed>0 { needed -=1; println!("[HUNT] +1 interesante {} (faltan {})", rep.ip, needed); } } all_reports.push(rep); }
                     if needed == 0 { println!("[HUNT] Cupo alcanzado. Deteniendo."); break; }
                     if cursor >= ip_seed.len() && ip_seed.len() < limit { println!("[HUNT] IPs agotadas y aún faltan interesantes."); break; }
                 }
                 summarize(&all_reports);
+                print_dork_changes(&checked.changes);
                 print_host_details_with_interest(&all_reports, hide_tcpwrapped, only_open, hunt_min_open);
                 export_csv(&args.out.join("report.csv"), &all_reports, hide_tcpwrapped, only_open)?;
                 export_json(&args.out.join("report.json"), &all_reports, hide_tcpwrapped, only_open)?;
                 export_markdown(&args.out.join("report.md"), &all_reports, hide_tcpwrapped, only_open)?;
+                let by_target = export_target_index(&args.out.join("report_by_target.json"), &all_reports, hide_tcpwrapped, only_open)?;
                 export_csv(&args.out.join("report_interesting.csv"), &interesting, hide_tcpwrapped, only_open)?;
                 export_json(&args.out.join("report_interesting.json"), &interesting, hide_tcpwrapped, only_open)?;
                 println!("CSV → {}", args.out.join("report.csv").display());
                 println!("JSON → {}", args.out.join("report.json").display());
                 println!("MD  → {}", args.out.join("report.md").display());
+                if by_target { println!("JSON (por hostname) → {}", args.out.join("report_by_target.json").display()); }
                 println!("CSV (interesantes) → {}", args.out.join("report_interesting.csv").display());
                 println!("JSON (interesantes) → {}", args.out.join("report_interesting.json").display());
                 return Ok(());
@@ -119,13 +187,27 @@ async fn main() -> Result<()> {
             let adaptive = interesting_target > 0;
 
             // 2) targets externos (IPs o dominios)
-            if let Some(tfile) = targets {
-                let raw_targets = load_targets(&tfile).await?;
-                let resolved = resolve_targets(&raw_targets).await?;
-                for (_t, ip) in resolved { if !ip_seed.contains(&ip) { ip_seed.push(ip); } }
-                let list_path = args.out.join("ips.txt");
-                fs::write(&list_path, ip_seed.join("\n"))?;
+            let mut shodan_hosts = BTreeMap::new();
+            let mut target_tags = BTreeMap::new();
+            let mut target_names = BTreeMap::new();
+            if let Some(entries) = targets {
+                // targets.csv se escribe según se resuelve; aquí solo queda lo agregado por IP
+                let resolved = resolve_targets(entries, &dns_options(&args), args.allow_private, Some(&args.out.join("targets.csv"))).await?;
+                add_target_names(&mut scope_names, &resolved);
+                if !resolved.failures.is_empty() { println!("[DNS] {} objetivo(s) sin resolver → {}", resolved.failures.len(), args.out.join("targets.csv").display()); }
+                let mut seen: HashSet<String> = ip_seed.iter().cloned().collect();
+                let mut explicit: Vec<String> = Vec::new();
+                for ip in &resolved.ips { if seen.insert(ip.clone()) { explicit.push(ip.clone()); ip_seed.push(ip.clone()); } }
+                target_tags = resolved.tags;
+                target_names = resolved.names;
                 println!("[*] Targets combinados → {}", ip_seed.len());
+                // Los objetivos explícitos no traen contexto Shodan: se consulta /shodan/host/{ip}
+                if !no_enrich && !explicit.is_empty() {
+                    println!("[*] Enriqueciendo {} objetivo(s) con /shodan/host...", explicit.len());
+                    shodan_hosts = enrich_hosts(&api, &explicit, enrich_history).await?;
+                    for h in shodan_hosts.values() { for b in &h.banners { if !shodan_matches.contains(b) { shodan_matches.push(b.clone()); } } }
+                    println!("[*] Shodan conoce {}/{} objetivo(s)", shodan_hosts.len(), explicit.len());
+                }
             }
 
             // Dedup ordenado
@@ -146,31 +228,16 @@ async fn main() -> Result<()> {
                 // Filtrar IPs nuevas no escaneadas
                 let remaining: Vec<String> = ip_seed.iter().filter(|ip| !already_scanned.contains(*ip)).cloned().collect();
                 if remaining.is_empty() { break; }
-                // 3) RustScan/Nmap para remaining
-                let ports_map: BTreeMap<String, Vec<u16>> = if let Some(fp) = &fixed_ports {
-                        if debug { eprintln!("[DEBUG] Modo matriz: Nmap puertos fijos = {fp}"); }
-                        let fixed = split_ports(fp)?;
-                        let mut m = BTreeMap::new();
-                        for ip in &remaining { m.insert(ip.clone(), fixed.clone()); }
-                        m
-                    } else if adaptive_nmap_only {
-                        // Mapa vacío: nmap_one_host detectará lista vacía y dejará que Nmap use top 1000.
-                        if debug { eprintln!("[DEBUG] Adaptive Nmap-only: usando set por defecto de Nmap (sin RustScan)"); }
-                        let mut m = BTreeMap::new();
-                        for ip in &remaining { m.insert(ip.clone(), Vec::new()); }
-                        m
-                    } else {
-                        let rs = rustscan_many_with_progress(&remaining, rs_concurrency, 1500, 4500).await?;
-                        let jsonl_path = args.out.join("rustscan.jsonl");
-                        // Append JSONL incremental
-                        let mut file = if jsonl_path.exists() { std::fs::OpenOptions::new().append(true).open(&jsonl_path)? } else { std::fs::File::create(&jsonl_path)? };
-                        for r in &rs { use std::io::Write; writeln!(file, "{}", serde_json::to_string(r)?)?; }
-                        let mut m = BTreeMap::new();
-                        for r in rs { m.insert(r.ip, r.ports); }
-                        m
-                    };
-                let target_pairs: Vec<(String, String)> = remaining.iter().map(|ip| (ip.clone(), ip.clone())).collect();
-                let mut batch_reports = nmap_many_with_progress(&target_pairs, &ports_map, &args.out, &nmap_extra, fixed_ports.as_deref(), nmap_concurrency, resume).await?;
+                // Las excluidas cuentan como procesadas para no reintentarlas en cada vuelta
+                already_scanned.extend(remaining.iter().cloned());
+                let remaining = scope.filter_ips(&remaining, &scope_names)?;
+                // 3) Descubrimiento (según --port-source) + Nmap para remaining
+                if debug && port_source == PortSource::Fixed { eprintln!("[DEBUG] Modo matriz: Nmap puertos fijos = {}", fixed_ports.as_deref().unwrap_or_default()); }
+                if debug && adaptive_nmap_only { eprintln!("[DEBUG] Adaptive Nmap-only: usando set por defecto de Nmap (sin RustScan)"); }
+                let discovered = build_ports_map(port_source, &remaining, fixed_ports.as_deref(), &shodan_matches, &discovery, adaptive_nmap_only, Some(&args.out.join("rustscan.jsonl"))).await?;
+                let target_pairs = discovered.nmap_pairs(&remaining);
+                let mut batch_reports = nmap_many_with_progress(&target_pairs, &discovered, &args.out, &nmap_extra, nmap_fixed, nmap_concurrency, resume).await?;
+                discovered.annotate(&mut batch_reports);
                 if confirm_wrapped { println!("[*] Confirmando puertos tcpwrapped..."); confirm_tcpwrapped(&mut batch_reports).await.ok(); }
                 // Actualizar contadores
                 for r in &batch_reports {
@@ -185,89 +252,168 @@ async fn main() -> Result<()> {
                     // Si no alcanzado y ya consumimos todas las IPs recolectadas, intentar pedir más páginas extra si posible
                     if already_scanned.len() == ip_seed.len() {
                         if ip_seed.len() >= limit { println!("[ADAPT] Límite rígido de IPs alcanzado ({limit}), deteniendo."); break; }
+                        if api.budget.exhausted() { println!("[ADAPT] Presupuesto de créditos agotado ({} gastados), no amplío el conjunto.", api.budget.spent()); break; }
                         // Pedimos una página adicional si pages permitía más
                         let extra_page_window = 5usize; // pequeñas expansiones
                         let new_limit = (ip_seed.len() + 5).min(limit);
-                        let add = shodan_collect(&client, &key, &query, new_limit, pages + extra_page_window, &args.out, debug).await?;
+                        let add = drop_reserved_matches(shodan_collect(&api, &query, new_limit, pages + extra_page_window, port_source.uses_shodan(), debug).await?, args.allow_private);
                         let before = ip_seed.len();
-                        for ip in add { if !ip_seed.contains(&ip) { ip_seed.push(ip); } }
+                        for ip in unique_ips(&add) { if !ip_seed.contains(&ip) { ip_seed.push(ip); } }
+                        add_match_names(&mut scope_names, &add);
+                        for m in add { if !shodan_matches.contains(&m) { shodan_matches.push(m); } }
                         if ip_seed.len() == before { println!("[ADAPT] No se obtuvieron IPs nuevas adicionales."); break; }
                         println!("[ADAPT] Ampliado conjunto a {} IPs", ip_seed.len());
                         continue; // volver al loop para escanear nuevas
                     }
                 } else { break; }
             }
-            let reports = aggregated_reports;
+            // Una sola exportación con lo acumulado en todas las rondas (y los objetivos explícitos)
+            export_collected(&args.out, &ip_seed, &shodan_matches)?;
+            let mut reports = aggregated_reports;
+            attach_shodan_matches(&mut reports, &shodan_matches);
+            attach_shodan_hosts(&mut reports, &shodan_hosts);
+            attach_tags(&mut reports, &target_tags);
+            attach_targets(&mut reports, &target_names);
+            if let Some(ptr) = ptr_enricher(&args) { println!("[*] DNS inverso de {} IP(s)...", reports.len()); ptr.enrich(&mut reports).await?; }
 
             // 5) Reglas dinámicas
             let rules_cfg = load_rules(&rules).unwrap_or_else(|_| Rules { rules: vec![] });
             if rules_cfg.rules.is_empty() { println!("[*] rules.yaml vacío o no encontrado; saltando herramientas dinámicas."); }
-            else { run_dynamic_tools(&rules_cfg, &reports, &args.out).await?; }
+            else { run_dynamic_tools(&rules_cfg, &scope.retain_reports(&reports)?, &args.out).await?; }
 
             // 6) Resumen + CSV + detalle
             summarize(&reports);
+            print_dork_changes(&checked.changes);
             let interest_threshold = if adaptive { interesting_min_open } else { 0 };
             if interest_threshold > 0 { print_host_details_with_interest(&reports, hide_tcpwrapped, only_open, interest_threshold); } else { print_host_details(&reports, hide_tcpwrapped, only_open); }
             export_csv(&args.out.join("report.csv"), &reports, hide_tcpwrapped, only_open)?;
             export_json(&args.out.join("report.json"), &reports, hide_tcpwrapped, only_open)?;
             println!("CSV → {}", args.out.join("report.csv").display());
             println!("JSON → {}", args.out.join("report.json").display());
+            if export_target_index(&args.out.join("report_by_target.json"), &reports, hide_tcpwrapped, only_open)? { println!("JSON (por hostname) → {}", args.out.join("report_by_target.json").display()); }
         }
-        Cmd::Intel { keywords, limit, pages } => {
+        Cmd::Stats { keywords, facets, facet_size } => {
             let key = key_resolved.expect("Falta API key (usa --key, variable SHODAN_API_KEY o 'config --set')");
-            let client = http_client()?;
-            let mut query = build_dork_from_keywords(&keywords);
+            let mut api = shodan_api(&args, &key)?;
+            account_guard(&mut api).await;
+            let query = build_dork_with_vocab(&keywords, &vocab)?;
             println!("[*] Dork Shodan: {query}");
-            if let Err(e) = shodan_precheck_count(&client, &key, &query).await {
-                eprintln!("[WARN] Dork inválido (/count): {e}");
-                eprintln!("[WARN] Fallback a country:CL");
-                query = "country:CL".into();
-                println!("[*] Dork Fallback: {query}");
+            let stats = shodan_count(&api, &query, &facets_param(&facets, facet_size)).await?;
+            print_stats(&stats, facet_size);
+            export_stats_json(&args.out.join("stats.json"), &stats)?;
+            export_stats_csv(&args.out.join("stats.csv"), &stats)?;
+            println!("JSON → {}", args.out.join("stats.json").display());
+            println!("CSV → {}", args.out.join("stats.csv").display());
+        }
+        Cmd::Enrich { input_targets, history } => {
+            let key = key_resolved.expect("Falta API key (usa --key, variable SHODAN_API_KEY o 'config --set')");
+            let mut api = shodan_api(&args, &key)?;
+            account_guard(&mut api).await;
+            let ips: Vec<String> = resolve_targets(load_targets(&input_targets, args.max_hosts)?, &dns_options(&args), args.allow_private, None).await?.ips.into_iter().collect::<BTreeSet<_>>().into_iter().collect();
+            let hosts = enrich_hosts(&api, &ips, history).await?;
+            for ip in &ips {
+                match hosts.get(ip) {
+                    Some(h) => println!("{ip}: puertos {:?} | hostnames {} | vulns {}", h.ports, h.hostnames.join(","), h.vulns.len()),
+                    None => println!("{ip}: sin información en Shodan"),
+                }
             }
-            let _ips = shodan_collect(&client, &key, &query, limit, pages, &args.out, debug).await?;
+            let path = args.out.join("enrich.json");
+            fs::write(&path, serde_json::to_string_pretty(&hosts.values().collect::<Vec<_>>())?)?;
+            println!("JSON → {}", path.display());
+        }
+        Cmd::Dork { keywords, explain } => {
+            let expr = dork::parse(&keywords)?;
+            if explain { print!("{}", dork::explain(&expr, &vocab)); }
+            println!("{}", dork::render(&expr, &vocab));
+        }
+        Cmd::Campaign { file, rs_concurrency, nmap_concurrency, nmap_extra, resume, hide_tcpwrapped, only_open, confirm_wrapped, on_dork_error } => {
+            let campaign = load_campaign(&file)?;
+            let key = key_resolved.expect("Falta API key (usa --key, variable SHODAN_API_KEY o 'config --set')");
+            let mut api = shodan_api(&args, &key)?;
+            account_guard(&mut api).await;
+            let opts = ScanOptions { discovery: discovery_options(&args, &profile, rs_concurrency, &RustscanSettings::default())?, nmap_concurrency, nmap_extra, resume, hide_tcpwrapped, only_open, confirm_wrapped, on_dork_error, debug, scope: scope.clone(), allow_private: args.allow_private, ptr: ptr_enricher(&args) };
+            run_campaign(&api, &vocab, &campaign, &args.out, &opts).await?;
+            let root = args.out.join(&campaign.name);
+            println!("CSV → {}", root.join("report.csv").display());
+            println!("JSON → {}", root.join("report.json").display());
+            println!("MD  → {}", root.join("report.md").display());
+        }
+        Cmd::Intel { keywords, limit, pages, on_dork_error } => {
+            let key = key_resolved.expect("Falta API key (usa --key, variable SHODAN_API_KEY o 'config --set')");
+            let mut api = shodan_api(&args, &key)?;
+            account_guard(&mut api).await;
+            let expr = dork::parse(&keywords)?;
+            println!("[*] Dork Shodan: {}", dork::render(&expr, &vocab));
+            let checked = check_dork(&api, &expr, &vocab, &on_dork_error, "").await?;
+            let query = checked.query.clone();
+            if !checked.changes.is_empty() { println!("[*] Dork usado: {query}"); }
+            let matches = drop_reserved_matches(shodan_collect(&api, &query, limit, pages, false, debug).await?, args.allow_private);
+            export_collected(&args.out, &unique_ips(&matches), &matches)?;
+            println!("[*] Shodan → {} IPs ({} banners, {} créditos de consulta)", unique_ips(&matches).len(), matches.len(), api.budget.spent());
+            println!("IPs → {}", args.out.join("ips.txt").display());
+            println!("Banners → {}", args.out.join("shodan_matches.jsonl").display());
+            print_dork_changes(&checked.changes);
         }
         Cmd::Clean { deep } => {
             if args.out.exists() { std::fs::remove_dir_all(&args.out).ok(); }
             println!("[+] Borrado directorio out/" );
             if deep { std::fs::remove_dir_all("target").ok(); println!("[+] Borrado target/ (recompilación completa la próxima vez)"); }
         }
-        Cmd::Rustscan { input_targets, timeout_ms, batch, concurrency } => {
-            let raw = load_targets(&input_targets).await?;
-            let pairs = resolve_targets(&raw).await?;
-            let ips: Vec<String> = pairs.into_iter().map(|(_, ip)| ip).collect();
-            let rs = rustscan_many_with_progress(&ips, concurrency, timeout_ms, batch).await?;
+        Cmd::Rustscan { input_targets, rs, concurrency } => {
+            let resolved = resolve_targets(load_targets(&input_targets, args.max_hosts)?, &dns_options(&args), args.allow_private, None).await?;
+            let mut names = BTreeMap::new();
+            add_target_names(&mut names, &resolved);
+            let ips = scope.filter_ips(&resolved.ips, &names)?;
+            let discovery = discovery_options(&args, &profile, concurrency, &rs)?;
+            let mut rs = discover(&ips, &discovery).await?;
+            // Los hostnames viajan en el JSONL para que `nmap` aplique las mismas reglas de dominio
+            for r in &mut rs { if let Some(n) = resolved.names.get(&r.ip) { r.targets = n.clone(); } }
+            let failed = rs.iter().filter(|r| !r.status.is_ok()).count();
+            if failed > 0 { println!("[DISCOVERY] {failed} host(s) con descubrimiento fallido (status/error en el JSONL)"); }
             let jsonl_path = input_targets.with_extension("rustscan.jsonl");
             write_jsonl(&jsonl_path, &rs)?;
-            println!("RustScan JSONL → {}", jsonl_path.display());
+            println!("{} JSONL → {}", if args.discovery == DiscoveryEngine::Native { "Escáner nativo" } else { "RustScan" }, jsonl_path.display());
         }
     Cmd::Nmap { input_jsonl, fixed_ports, nmap_extra, concurrency, resume, hide_tcpwrapped, only_open, confirm_wrapped } => {
             use anyhow::anyhow;
-            let (targets, ports_map): (Vec<(String, String)>, BTreeMap<String, Vec<u16>>) = if let Some(fp) = fixed_ports.clone() {
+            // Hostnames por IP que trae el JSONL (de `rustscan` sobre un archivo de objetivos) para el alcance
+            let mut names: BTreeMap<String, Vec<String>> = BTreeMap::new();
+            let (targets, mut discovered): (Vec<(String, String)>, Discovered) = if let Some(fp) = fixed_ports.clone() {
                 let tuple = if let Some(path) = input_jsonl.clone() {
                     let items: Vec<IpPorts> = read_jsonl(&path).await?;
+                    for it in &items { if !it.targets.is_empty() { names.insert(it.ip.clone(), it.targets.clone()); } }
                     let ips: Vec<String> = items.into_iter().map(|x| x.ip).collect();
-                    (ips, split_ports(&fp)?)
+                    (ips, PortSpec::parse(&fp)?)
                 } else { return Err(anyhow!("Con --fixed-ports necesitas también --input-jsonl o adaptar código para leer out/ips.txt")); };
                 let pairs: Vec<(String, String)> = tuple.0.iter().map(|ip| (ip.clone(), ip.clone())).collect();
-                let mut map = BTreeMap::new();
-                for ip in tuple.0 { map.insert(ip, tuple.1.clone()); }
-                (pairs, map)
+                // La matriz completa, UDP incluido (los U: añaden -sU con root, igual que en full/campaign)
+                let (mut tcp, mut udp) = (BTreeMap::new(), BTreeMap::new());
+                for ip in tuple.0 { tcp.insert(ip.clone(), tuple.1.tcp.clone()); if !tuple.1.udp.is_empty() { udp.insert(ip, tuple.1.udp.clone()); } }
+                (pairs, Discovered { ports: tcp, udp, ..Default::default() })
             } else {
                 let path = input_jsonl.clone().expect("Falta --input-jsonl o usa --fixed-ports");
                 let items: Vec<IpPorts> = read_jsonl(&path).await?;
-                let pairs: Vec<(String, String)> = items.iter().map(|it| (it.ip.clone(), it.ip.clone())).collect();
-                let mut map = BTreeMap::new();
-                for it in items { map.insert(it.ip, it.ports); }
-                (pairs, map)
+                for it in &items { if !it.targets.is_empty() { names.insert(it.ip.clone(), it.targets.clone()); } }
+                let ips: Vec<String> = items.iter().map(|it| it.ip.clone()).collect();
+                // Hosts con descubrimiento fallido en el JSONL: top-ports de Nmap o fuera, según --on-discovery-error
+                let discovered = apply_policy(items, args.on_discovery_error);
+                (discovered.nmap_pairs(&ips), discovered)
             };
-            let mut reports = nmap_many_with_progress(&targets, &ports_map, &args.out, &nmap_extra, fixed_ports.as_deref(), concurrency, resume).await?;
+            let allowed: BTreeSet<String> = scope.filter_ips(&targets.iter().map(|(_, ip)| ip.clone()).chain(discovered.failures.keys().cloned()).collect::<Vec<_>>(), &names)?.into_iter().collect();
+            let targets: Vec<(String, String)> = targets.into_iter().filter(|(_, ip)| allowed.contains(ip)).collect();
+            discovered.failures.retain(|ip, _| allowed.contains(ip));
+            let mut reports = nmap_many_with_progress(&targets, &discovered, &args.out, &nmap_extra, fixed_ports.as_deref(), concurrency, resume).await?;
+            discovered.annotate(&mut reports);
+            attach_targets(&mut reports, &names);
             if confirm_wrapped { println!("[*] Confirmando puertos tcpwrapped..."); confirm_tcpwrapped(&mut reports).await.ok(); }
+            if let Some(ptr) = ptr_enricher(&args) { ptr.enrich(&mut reports).await?; }
             summarize(&reports);
             print_host_details(&reports, hide_tcpwrapped, only_open);
             export_csv(&args.out.join("report.csv"), &reports, hide_tcpwrapped, only_open)?;
             export_json(&args.out.join("report.json"), &reports, hide_tcpwrapped, only_open)?;
             println!("CSV → {}", args.out.join("report.csv").display());
             println!("JSON → {}", args.out.join("report.json").display());
+            if export_target_index(&args.out.join("report_by_target.json"), &reports, hide_tcpwrapped, only_open)? { println!("JSON (por hostname) → {}", args.out.join("report_by_target.json").display()); }
         }
     }
     Ok(())
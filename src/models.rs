@@ -1,10 +1,290 @@
 use serde::{Serialize, Deserialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct IpPorts { pub ip: String, pub ports: Vec<u16> }
+/// Resultado del descubrimiento de una IP (RustScan o escáner nativo). `status`/`error` solo aparecen en el JSONL si falló.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct IpPorts {
+    pub ip: String,
+    pub ports: Vec<u16>,
+    #[serde(default, skip_serializing_if = "DiscoveryStatus::is_ok")]
+    pub status: DiscoveryStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Hostnames del archivo de objetivos que resolvieron a la IP (para las reglas de dominio del alcance en `nmap`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct PortDetail { pub port: u16, pub state: String, pub service: Option<String> }
+impl IpPorts {
+    pub fn ok(ip: String, ports: Vec<u16>) -> Self { Self { ip, ports, ..Default::default() } }
+    pub fn failed(ip: String, status: DiscoveryStatus, error: String) -> Self { Self { ip, status, error: Some(error), ..Default::default() } }
+}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct HostReport { pub target: String, pub ip: String, pub ports: Vec<PortDetail> }
+/// Estado del descubrimiento de puertos de un host.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiscoveryStatus {
+    #[default]
+    Ok,
+    /// No se pudo lanzar el binario (no instalado, permisos...)
+    SpawnFailed,
+    /// Terminó con código de salida distinto de 0 o por señal
+    NonZeroExit,
+    /// Superó el tiempo máximo por host
+    Timeout,
+    /// Otro error (p. ej. objetivo que no es una IP, o puertos sin probar por falta de recursos, en el escáner nativo)
+    Error,
+}
+
+impl DiscoveryStatus {
+    pub fn is_ok(&self) -> bool { *self == DiscoveryStatus::Ok }
+}
+
+impl std::fmt::Display for DiscoveryStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self { Self::Ok => "ok", Self::SpawnFailed => "spawn-failed", Self::NonZeroExit => "non-zero-exit", Self::Timeout => "timeout", Self::Error => "error" })
+    }
+}
+
+/// Protocolo de transporte de un puerto (`tcp` si el origen no lo indica).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str(match self { Self::Tcp => "tcp", Self::Udp => "udp" }) }
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim().to_ascii_lowercase().as_str() { "tcp" => Ok(Self::Tcp), "udp" => Ok(Self::Udp), other => Err(format!("protocolo desconocido '{other}' (usa tcp o udp)")) }
+    }
+}
+
+/// Un puerto del XML de Nmap con la huella de servicio de `-sV` (`<service>`, `<cpe>`) y el motivo del estado.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PortDetail {
+    pub port: u16,
+    #[serde(default)]
+    pub protocol: Protocol,
+    pub state: String,
+    /// Motivo del estado (`syn-ack`, `conn-refused`, `udp-response`...) y TTL de la respuesta.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_ttl: Option<u8>,
+    pub service: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extrainfo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ostype: Option<String>,
+    /// `ssl` si el servicio va sobre TLS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tunnel: Option<String>,
+    /// `probed` (sondeo) o `table` (solo por número de puerto) y confianza 0-10.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conf: Option<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cpe: Vec<String>,
+    /// Scripts NSE ejecutados sobre el puerto (`-sC`, `--script`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scripts: Vec<ScriptResult>,
+}
+
+/// Resultado de un script NSE: `output` es el texto que muestra Nmap y `data` la estructura de `<table>`/`<elem>`
+/// (objeto si los hijos tienen `key`, lista si no; `null` si el script no la da).
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ScriptResult {
+    pub id: String,
+    pub output: String,
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub data: serde_json::Value,
+}
+
+impl PortDetail {
+    /// `22` para TCP y `161/udp` para UDP (consola y reportes).
+    pub fn label(&self) -> String { if self.protocol == Protocol::Udp { format!("{}/udp", self.port) } else { self.port.to_string() } }
+
+    /// "producto versión (extrainfo)" de `-sV`; vacío si Nmap no identificó producto.
+    pub fn fingerprint(&self) -> String {
+        let mut f = [self.product.as_deref(), self.version.as_deref()].into_iter().flatten().collect::<Vec<_>>().join(" ");
+        if let Some(x) = &self.extrainfo { f = if f.is_empty() { x.clone() } else { format!("{f} ({x})") }; }
+        f
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HostReport {
+    pub target: String,
+    pub ip: String,
+    pub ports: Vec<PortDetail>,
+    /// Banners que Shodan reportó para esta IP (vacío si la IP no vino de un dork).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shodan: Vec<ShodanMatch>,
+    /// Ficha de `/shodan/host/{ip}` (enriquecimiento de objetivos explícitos).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shodan_host: Option<ShodanHost>,
+    /// Etiquetas del archivo de objetivos (`10.0.0.5 # web-prod`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Nombres PTR (DNS inverso) de la IP.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ptr: Vec<String>,
+    /// Datos de host del XML de Nmap (estado, MAC, hostnames, SO, uptime, distancia, traza); `None` si no se escaneó.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nmap: Option<NmapHostInfo>,
+    /// Scripts NSE de nivel host (`<hostscript>`: smb-os-discovery, etc.).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub host_scripts: Vec<ScriptResult>,
+    /// Fallo del descubrimiento de puertos y qué se hizo (`timeout: ...; Nmap con sus top-ports`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub discovery_error: Option<String>,
+}
+
+/// Datos de nivel host de `nmap.xml`: `<status>`, `<address>` (MAC y fabricante), `<hostnames>`, `<os>`, `<uptime>`, `<distance>` y `<trace>`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct NmapHostInfo {
+    /// `up`, `down` o `unknown` y el motivo (`syn-ack`, `user-set` con -Pn, `no-response`...).
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mac_vendor: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hostnames: Vec<NmapHostname>,
+    /// Coincidencias de `-O` de mayor a menor exactitud.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub os: Vec<OsMatch>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uptime_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_boot: Option<String>,
+    /// Saltos de red hasta el host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distance: Option<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trace: Vec<TraceHop>,
+}
+
+impl NmapHostInfo {
+    pub fn is_down(&self) -> bool { self.status == "down" }
+
+    /// Mejor coincidencia de SO como "nombre (95%)".
+    pub fn best_os(&self) -> Option<String> { self.os.first().map(|o| format!("{} ({}%)", o.name, o.accuracy)) }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct NmapHostname {
+    pub name: String,
+    /// `user` (el objetivo dado) o `PTR`.
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct OsMatch {
+    pub name: String,
+    pub accuracy: u8,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cpe: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct TraceHop {
+    pub ttl: u8,
+    pub ip: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rtt_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ShodanLocation { pub country_code: Option<String>, pub country_name: Option<String>, pub city: Option<String>, pub latitude: Option<f64>, pub longitude: Option<f64> }
+
+/// Un banner de `/shodan/host/search` (una IP puede tener varios, uno por puerto).
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ShodanMatch {
+    pub ip: String,
+    pub port: u16,
+    #[serde(default)]
+    pub transport: Option<String>,
+    #[serde(default)]
+    pub product: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub hostnames: Vec<String>,
+    #[serde(default)]
+    pub domains: Vec<String>,
+    #[serde(default)]
+    pub org: Option<String>,
+    #[serde(default)]
+    pub asn: Option<String>,
+    #[serde(default)]
+    pub isp: Option<String>,
+    #[serde(default)]
+    pub location: Option<ShodanLocation>,
+    #[serde(default)]
+    pub ssl_subject: Option<String>,
+    #[serde(default)]
+    pub ssl_issuer: Option<String>,
+    #[serde(default)]
+    pub http_title: Option<String>,
+    #[serde(default)]
+    pub vulns: Vec<String>,
+    #[serde(default)]
+    pub timestamp: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct FacetValue { pub value: String, pub count: u64 }
+
+/// Respuesta de `/shodan/host/count`: total de resultados y desglose por facet.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ShodanCount { pub query: String, pub total: u64, pub facets: std::collections::BTreeMap<String, Vec<FacetValue>> }
+
+/// Respuesta de `/api-info`: plan y créditos restantes de la cuenta.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ApiInfo {
+    #[serde(default)]
+    pub plan: Option<String>,
+    #[serde(default)]
+    pub query_credits: i64,
+    #[serde(default)]
+    pub scan_credits: i64,
+    #[serde(default)]
+    pub unlocked_left: i64,
+}
+
+/// Respuesta de `/shodan/host/{ip}`: todo lo que Shodan sabe de una IP (con `history` incluye banners antiguos).
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ShodanHost {
+    pub ip: String,
+    #[serde(default)]
+    pub ports: Vec<u16>,
+    #[serde(default)]
+    pub hostnames: Vec<String>,
+    #[serde(default)]
+    pub vulns: Vec<String>,
+    #[serde(default)]
+    pub org: Option<String>,
+    #[serde(default)]
+    pub os: Option<String>,
+    #[serde(default)]
+    pub last_update: Option<String>,
+    #[serde(default)]
+    pub banners: Vec<ShodanMatch>,
+}
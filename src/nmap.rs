@@ -1,10 +1,15 @@
 use anyhow::{Result, anyhow};
 use indicatif::{ProgressBar, ProgressStyle};
-use std::{collections::BTreeMap, path::Path};
+use std::path::Path;
 use tokio::process::Command;
 use tokio::sync::Semaphore;
 use std::sync::Arc;
-use crate::models::{HostReport, PortDetail};
+use serde_json::Value;
+use crate::{discovery::Discovered, models::{HostReport, NmapHostInfo, NmapHostname, OsMatch, PortDetail, Protocol, ScriptResult, TraceHop}};
+
+/// Puertos UDP que se prueban con `-sU` cuando no hay una lista UDP explícita (servicios UDP habituales en auditorías;
+/// el top 1000 UDP de Nmap tarda demasiado por host).
+pub const UDP_TOP_PORTS: &[u16] = &[53, 67, 68, 69, 111, 123, 135, 137, 138, 161, 162, 177, 427, 443, 500, 514, 520, 623, 631, 1194, 1434, 1701, 1812, 1813, 1900, 2049, 3478, 3702, 4500, 5060, 5353, 5683, 11211, 47808];
 
 /// Detecta si el proceso corre con privilegios (uid efectivo 0) en Linux leyendo /proc/self/status.
 fn is_root() -> bool {
@@ -18,10 +23,15 @@ fn is_root() -> bool {
 /// - Si hay -sS y no eres root -> cambia a -sT (avisa)
 /// - Si el modo final es -sT -> elimina flags específicos de SYN como --defeat-rst-ratelimit
 /// - Evita coexistencia -sS y -sT (prioriza -sT si presente)
+/// - Si hay -sU y no eres root -> lo quita (avisa); sin -sU no se escanea UDP
 /// - Si no hay ninguno, añade -sT por seguridad
 fn normalize_nmap_extra(extra: &str, ip_hint: Option<&str>) -> Vec<String> {
     let mut v: Vec<String> = extra.split_whitespace().map(|s| s.to_string()).collect();
     let have_root = is_root();
+    if v.iter().any(|a| a == "-sU") && !have_root {
+        eprintln!("[WARN] -sU requiere root; se omite el escaneo UDP{}", ip_hint.map(|i| format!(" en {i}")).unwrap_or_default());
+        v.retain(|a| a != "-sU");
+    }
 
     if v.iter().any(|a| a == "-sS") && !have_root {
         eprintln!("[INFO] Sin privilegios para SYN (-sS); usando -sT{}", ip_hint.map(|i| format!(" en {i}")).unwrap_or_default());
@@ -40,11 +50,55 @@ fn normalize_nmap_extra(extra: &str, ip_hint: Option<&str>) -> Vec<String> {
     v
 }
 
-pub fn split_ports(s: &str) -> Result<Vec<u16>> { let mut out = Vec::new(); for part in s.split(',') { let p = part.trim(); if p.is_empty(){ continue; } if let Some((a,b)) = p.split_once('-') { let a: u16 = a.trim().parse()?; let b: u16 = b.trim().parse()?; if a <= b { for x in a..=b { out.push(x); } } } else { out.push(p.parse()?); } } out.sort_unstable(); out.dedup(); Ok(out) }
+/// Puertos por protocolo de una especificación estilo Nmap (`22,80,U:53,161,T:8000-8100`): `T:`/`U:` valen para lo que sigue
+/// hasta el siguiente prefijo; sin prefijo es TCP.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortSpec { pub tcp: Vec<u16>, pub udp: Vec<u16> }
+
+impl PortSpec {
+    pub fn parse(s: &str) -> Result<Self> {
+        let (mut spec, mut udp) = (PortSpec::default(), false);
+        for part in s.split(',') {
+            let mut p = part.trim();
+            if let Some((proto, rest)) = p.split_once(':') {
+                udp = match proto.trim() { "U" | "u" => true, "T" | "t" => false, other => return Err(anyhow!("prefijo de protocolo desconocido '{other}:' (usa T: o U:)")) };
+                p = rest.trim();
+            }
+            if p.is_empty() { continue; }
+            let list = if udp { &mut spec.udp } else { &mut spec.tcp };
+            if let Some((a,b)) = p.split_once('-') { let a: u16 = a.trim().parse()?; let b: u16 = b.trim().parse()?; if a <= b { list.extend(a..=b); } } else { list.push(p.parse()?); }
+        }
+        for list in [&mut spec.tcp, &mut spec.udp] { list.sort_unstable(); list.dedup(); }
+        Ok(spec)
+    }
+
+    pub fn is_empty(&self) -> bool { self.tcp.is_empty() && self.udp.is_empty() }
+
+    /// Valor para `-p`: tramos continuos como rangos y prefijos `T:`/`U:` solo si hay UDP.
+    pub fn to_nmap_arg(&self) -> String {
+        let compact = |ports: &[u16]| {
+            let mut parts = Vec::new(); let mut i = 0;
+            while i < ports.len() { let mut j = i; while j + 1 < ports.len() && ports[j + 1] == ports[j] + 1 { j += 1; } parts.push(if j > i { format!("{}-{}", ports[i], ports[j]) } else { ports[i].to_string() }); i = j + 1; }
+            parts.join(",")
+        };
+        match (self.tcp.is_empty(), self.udp.is_empty()) {
+            (_, true) => compact(&self.tcp),
+            (true, false) => format!("U:{}", compact(&self.udp)),
+            (false, false) => format!("T:{},U:{}", compact(&self.tcp), compact(&self.udp)),
+        }
+    }
+}
+
+/// Puertos TCP de una especificación (RustScan, escáner nativo); los puertos UDP (`U:`) son un error aquí.
+pub fn split_ports(s: &str) -> Result<Vec<u16>> {
+    let spec = PortSpec::parse(s)?;
+    if !spec.udp.is_empty() { return Err(anyhow!("incluye puertos UDP (U:), que solo admite Nmap con -sU")); }
+    Ok(spec.tcp)
+}
 
-pub async fn nmap_many_with_progress(targets: &[(String, String)], ports_map: &BTreeMap<String, Vec<u16>>, out_dir: &Path, extra: &str, fixed_ports: Option<&str>, concurrency: usize, resume: bool) -> Result<Vec<HostReport>> {
+pub async fn nmap_many_with_progress(targets: &[(String, String)], discovered: &Discovered, out_dir: &Path, extra: &str, fixed_ports: Option<&str>, concurrency: usize, resume: bool) -> Result<Vec<HostReport>> {
     let total = targets.len() as u64; let pb = ProgressBar::new(total); pb.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:40.blue/black} {pos}/{len} ({percent}%) Nmap")?.progress_chars("##-"));
-    let sem = Arc::new(Semaphore::new(concurrency)); let mut tasks = Vec::new(); for (target, ip) in targets { let target = target.clone(); let ip = ip.clone(); let s = sem.clone(); let pb2 = pb.clone(); let out = out_dir.to_path_buf(); let extra = extra.to_string(); let fixed = fixed_ports.map(|x| x.to_string()); let ports = ports_map.get(&ip).cloned().unwrap_or_default(); tasks.push(tokio::spawn(async move { let _permit = s.acquire_owned().await.unwrap(); let rep = nmap_one_host(&target, &ip, &ports, &out, &extra, fixed.as_deref(), resume).await; pb2.inc(1); rep })); }
+    let sem = Arc::new(Semaphore::new(concurrency)); let mut tasks = Vec::new(); for (target, ip) in targets { let target = target.clone(); let ip = ip.clone(); let s = sem.clone(); let pb2 = pb.clone(); let out = out_dir.to_path_buf(); let extra = extra.to_string(); let fixed = fixed_ports.map(|x| x.to_string()); let ports = PortSpec { tcp: discovered.ports.get(&ip).cloned().unwrap_or_default(), udp: discovered.udp.get(&ip).cloned().unwrap_or_default() }; tasks.push(tokio::spawn(async move { let _permit = s.acquire_owned().await.unwrap(); let rep = nmap_one_host(&target, &ip, &ports, &out, &extra, fixed.as_deref(), resume).await; pb2.inc(1); rep })); }
     let mut reports = Vec::new(); for t in tasks { reports.push(t.await??); } pb.finish_with_message("Nmap listo"); Ok(reports)
 }
 
@@ -52,7 +106,7 @@ pub async fn nmap_many_with_progress(targets: &[(String, String)], ports_map: &B
 /// Si -sT falla intenta -sS (caso inverso al fallback principal) para completar mejor cobertura.
 pub async fn confirm_tcpwrapped(reports: &mut [HostReport]) -> Result<()> {
     for r in reports.iter_mut() {
-        let wrapped: Vec<u16> = r.ports.iter().filter(|p| p.service.as_deref() == Some("tcpwrapped")).map(|p| p.port).collect();
+        let wrapped: Vec<u16> = r.ports.iter().filter(|p| p.protocol == Protocol::Tcp && p.service.as_deref() == Some("tcpwrapped")).map(|p| p.port).collect();
         if wrapped.is_empty() { continue; }
         let ip = &r.ip;
         let port_list = wrapped.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(",");
@@ -67,23 +121,33 @@ pub async fn confirm_tcpwrapped(reports: &mut [HostReport]) -> Result<()> {
         };
         let xml = String::from_utf8_lossy(&xml_bytes);
         if let Ok(parsed) = parse_nmap_ports(&xml) {
-            for upd in parsed { if wrapped.contains(&upd.port) { if let Some(orig) = r.ports.iter_mut().find(|p| p.port == upd.port) { orig.state = upd.state; orig.service = upd.service; } } }
+            for upd in parsed { if wrapped.contains(&upd.port) && let Some(orig) = r.ports.iter_mut().find(|p| p.protocol == Protocol::Tcp && p.port == upd.port) { *orig = upd; } }
         }
     }
     Ok(())
 }
 
-async fn nmap_one_host(target: &str, ip: &str, ports: &[u16], out_dir: &Path, extra: &str, fixed_ports: Option<&str>, resume: bool) -> Result<HostReport> {
+/// Puertos del escaneo: matriz fija o lista del descubrimiento previo (RustScan, Shodan...). UDP solo con `-sU`
+/// (los `U:` de la matriz lo añaden si hay root) y, sin lista UDP, la curada `UDP_TOP_PORTS`. Sin lista TCP no hay `-p`:
+/// un `-p` solo UDP dejaría el TCP sin escanear, así que Nmap usa su set por defecto en ambos protocolos.
+fn scan_ports(args: &mut Vec<String>, ports: &PortSpec, fixed_ports: Option<&str>, root: bool, ip: &str) -> Result<PortSpec> {
+    let mut spec = match fixed_ports { Some(fp) => PortSpec::parse(fp)?, None => ports.clone() };
+    if fixed_ports.is_some() && !spec.udp.is_empty() && !args.iter().any(|a| a == "-sU") {
+        // Pedir U: en --fixed-ports equivale a pedir -sU
+        if root { args.insert(0, "-sU".into()); } else { eprintln!("[WARN] Puertos U: en --fixed-ports sin root; se omite UDP en {ip}"); }
+    }
+    if spec.tcp.is_empty() || !args.iter().any(|a| a == "-sU") { spec.udp.clear(); } else if spec.udp.is_empty() { spec.udp = UDP_TOP_PORTS.to_vec(); }
+    Ok(spec)
+}
+
+async fn nmap_one_host(target: &str, ip: &str, ports: &PortSpec, out_dir: &Path, extra: &str, fixed_ports: Option<&str>, resume: bool) -> Result<HostReport> {
     let ip_dir = out_dir.join(ip); tokio::fs::create_dir_all(&ip_dir).await.ok(); let xml_path = ip_dir.join("nmap.xml");
-    if resume && xml_path.exists(){ let xml = tokio::fs::read_to_string(&xml_path).await?; let ports = parse_nmap_ports(&xml)?; return Ok(HostReport{ target: target.into(), ip: ip.into(), ports }); }
+    if resume && xml_path.exists(){ let xml = tokio::fs::read_to_string(&xml_path).await?; return Ok(parse_nmap_xml(&xml)?.into_report(target, ip)); }
     // Normalizar flags extra
     let mut args = normalize_nmap_extra(extra, Some(ip));
-    if let Some(fp)= fixed_ports {
-        args.extend(vec!["-p".into(), fp.to_string()]);
-    } else if !ports.is_empty() {
-        // Si se entrega lista de puertos descubrimiento previo (RustScan u otro), la usamos.
-        let port_list = ports.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(",");
-        args.extend(vec!["-p".into(), port_list]);
+    let spec = scan_ports(&mut args, ports, fixed_ports, is_root(), ip)?;
+    if !spec.is_empty() {
+        args.extend(vec!["-p".into(), spec.to_nmap_arg()]);
     } else {
         // Caso lista vacía: dejamos que Nmap utilice su set por defecto (top 1000). No añadimos -p.
         // Esto habilita un modo "solo Nmap" cuando se omite RustScan en el bucle adaptativo.
@@ -112,7 +176,263 @@ async fn nmap_one_host(target: &str, ip: &str, ports: &[u16], out_dir: &Path, ex
         let _ = tokio::fs::write(ip_dir.join("nmap.stderr.txt"), stderr_txt_full.as_bytes()).await;
         anyhow::bail!("nmap falló en {} con args {:?}. stderr: {}", ip, args, stderr_trunc);
     }
-    let xml = tokio::fs::read_to_string(&xml_path).await?; let ports = parse_nmap_ports(&xml)?; Ok(HostReport { target: target.into(), ip: ip.into(), ports })
+    let xml = tokio::fs::read_to_string(&xml_path).await?; Ok(parse_nmap_xml(&xml)?.into_report(target, ip))
 }
 
-fn parse_nmap_ports(xml: &str) -> Result<Vec<PortDetail>> { use quick_xml::{Reader, events::Event}; let mut rd = Reader::from_str(xml); rd.config_mut().trim_text(true); let mut buf = Vec::new(); let mut current_port: Option<u16> = None; let mut current_state: Option<String> = None; let mut current_service: Option<String> = None; let mut out = Vec::<PortDetail>::new(); loop { match rd.read_event_into(&mut buf) { Ok(Event::Start(e)) if e.name().as_ref()== b"port" => { current_port=None; current_state=None; current_service=None; for a in e.attributes().flatten(){ if a.key.as_ref()== b"portid" { current_port = String::from_utf8_lossy(&a.value).parse::<u16>().ok(); } } } Ok(Event::Empty(e)) if e.name().as_ref()== b"state" => { for a in e.attributes().flatten(){ if a.key.as_ref()== b"state" { current_state = Some(String::from_utf8_lossy(&a.value).to_string()); } } } Ok(Event::Start(e)) if e.name().as_ref()== b"state" => { for a in e.attributes().flatten(){ if a.key.as_ref()== b"state" { current_state = Some(String::from_utf8_lossy(&a.value).to_string()); } } } Ok(Event::Empty(e)) if e.name().as_ref()== b"service" => { for a in e.attributes().flatten(){ if a.key.as_ref()== b"name" { current_service = Some(String::from_utf8_lossy(&a.value).to_string()); } } } Ok(Event::Start(e)) if e.name().as_ref()== b"service" => { for a in e.attributes().flatten(){ if a.key.as_ref()== b"name" { current_service = Some(String::from_utf8_lossy(&a.value).to_string()); } } } Ok(Event::End(e)) if e.name().as_ref()== b"port" => { if let Some(p)= current_port { out.push(PortDetail { port: p, state: current_state.clone().unwrap_or_else(|| "unknown".into()), service: current_service.clone() }); } current_port=None; current_state=None; current_service=None; } Ok(Event::Eof) => break, Err(e) => return Err(anyhow!("XML error: {}", e)), _ => {} } } Ok(out) }
+/// Lo que interesa de un `<host>` del XML de Nmap.
+#[derive(Debug, Default)]
+struct NmapHost { ports: Vec<PortDetail>, scripts: Vec<ScriptResult>, info: NmapHostInfo }
+
+impl NmapHost {
+    fn into_report(self, target: &str, ip: &str) -> HostReport { HostReport { target: target.into(), ip: ip.into(), ports: self.ports, host_scripts: self.scripts, nmap: Some(self.info), ..Default::default() } }
+}
+
+fn parse_nmap_ports(xml: &str) -> Result<Vec<PortDetail>> { parse_nmap_xml(xml).map(|h| h.ports) }
+
+/// `<table>` abierta de un script: su clave y los hijos leídos hasta ahora.
+type OpenTable = (Option<String>, Vec<(Option<String>, Value)>);
+
+/// Hijos de un `<table>` a JSON: objeto si todos tienen `key`, lista si ninguno; si se mezclan, los sin clave van por índice.
+fn table_value(children: Vec<(Option<String>, Value)>) -> Value {
+    if children.iter().all(|(k, _)| k.is_none()) { return Value::Array(children.into_iter().map(|(_, v)| v).collect()); }
+    Value::Object(children.into_iter().enumerate().map(|(i, (k, v))| (k.unwrap_or_else(|| i.to_string()), v)).collect())
+}
+
+fn parse_nmap_xml(xml: &str) -> Result<NmapHost> {
+    use quick_xml::{Reader, events::{BytesStart, Event}};
+    fn attrs(e: &BytesStart) -> Vec<(Vec<u8>, String)> { e.attributes().flatten().map(|a| (a.key.as_ref().to_vec(), a.unescape_value().map(|v| v.into_owned()).unwrap_or_else(|_| String::from_utf8_lossy(&a.value).into_owned()))).collect() }
+    fn script_start(e: &BytesStart) -> ScriptResult {
+        let mut s = ScriptResult::default();
+        for (k, v) in attrs(e) { match k.as_slice() { b"id" => s.id = v, b"output" => s.output = v, _ => {} } }
+        s
+    }
+    fn os_match(e: &BytesStart) -> OsMatch {
+        let mut m = OsMatch::default();
+        for (k, v) in attrs(e) { match k.as_slice() { b"name" => m.name = v, b"accuracy" => m.accuracy = v.parse().unwrap_or(0), _ => {} } }
+        m
+    }
+    fn key(e: &BytesStart) -> Option<String> { attrs(e).into_iter().find(|(k, _)| k == b"key").map(|(_, v)| v) }
+    // Sin recorte: el texto de <elem> conserva sus espacios; los nodos de solo espacios se ignoran fuera de <cpe>/<elem>
+    let mut rd = Reader::from_str(xml);
+    let mut buf = Vec::new();
+    let mut host = NmapHost::default();
+    let mut current: Option<PortDetail> = None;
+    let mut in_cpe = false;
+    let mut in_hostscript = false;
+    // <hosthint> repite <status>/<address> antes del resultado: solo cuenta lo que está dentro de <host>
+    let mut in_host = false;
+    let mut in_osmatch = false;
+    let mut hosts_down = 0u32;
+    // Script en curso, pila de <table> abiertas (la base es el propio script) y <elem> en curso
+    let mut script: Option<ScriptResult> = None;
+    let mut tables: Vec<OpenTable> = Vec::new();
+    let mut elem: Option<(Option<String>, String)> = None;
+    loop {
+        match rd.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) if e.name().as_ref() == b"port" => {
+                let mut p = PortDetail { state: "unknown".into(), ..Default::default() };
+                let mut id = None;
+                for (k, v) in attrs(&e) { match k.as_slice() { b"portid" => id = v.parse::<u16>().ok(), b"protocol" => p.protocol = v.parse().unwrap_or_default(), _ => {} } }
+                current = id.map(|port| PortDetail { port, ..p });
+            }
+            Ok(Event::Start(e) | Event::Empty(e)) if e.name().as_ref() == b"state" => {
+                if let Some(p) = current.as_mut() { for (k, v) in attrs(&e) { match k.as_slice() { b"state" => p.state = v, b"reason" => p.reason = Some(v), b"reason_ttl" => p.reason_ttl = v.parse().ok(), _ => {} } } }
+            }
+            Ok(Event::Start(e) | Event::Empty(e)) if e.name().as_ref() == b"service" => {
+                if let Some(p) = current.as_mut() {
+                    for (k, v) in attrs(&e) {
+                        match k.as_slice() {
+                            b"name" => p.service = Some(v), b"product" => p.product = Some(v), b"version" => p.version = Some(v), b"extrainfo" => p.extrainfo = Some(v),
+                            b"ostype" => p.ostype = Some(v), b"tunnel" => p.tunnel = Some(v), b"method" => p.method = Some(v), b"conf" => p.conf = v.parse().ok(), _ => {}
+                        }
+                    }
+                }
+            }
+            Ok(Event::Start(e)) if e.name().as_ref() == b"host" => in_host = true,
+            Ok(Event::End(e)) if e.name().as_ref() == b"host" => in_host = false,
+            Ok(Event::Start(e) | Event::Empty(e)) if e.name().as_ref() == b"status" && in_host => {
+                for (k, v) in attrs(&e) { match k.as_slice() { b"state" => host.info.status = v, b"reason" => host.info.status_reason = Some(v), _ => {} } }
+            }
+            Ok(Event::Start(e) | Event::Empty(e)) if e.name().as_ref() == b"address" && in_host => {
+                let a = attrs(&e);
+                let get = |name: &[u8]| a.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone());
+                if get(b"addrtype").as_deref() == Some("mac") { host.info.mac = get(b"addr"); host.info.mac_vendor = get(b"vendor"); }
+            }
+            Ok(Event::Start(e) | Event::Empty(e)) if e.name().as_ref() == b"hostname" && in_host => {
+                let mut h = NmapHostname::default();
+                for (k, v) in attrs(&e) { match k.as_slice() { b"name" => h.name = v, b"type" => h.kind = Some(v), _ => {} } }
+                if !h.name.is_empty() && !host.info.hostnames.contains(&h) { host.info.hostnames.push(h); }
+            }
+            Ok(Event::Start(e)) if e.name().as_ref() == b"osmatch" && in_host => { host.info.os.push(os_match(&e)); in_osmatch = true; }
+            Ok(Event::Empty(e)) if e.name().as_ref() == b"osmatch" && in_host => host.info.os.push(os_match(&e)),
+            Ok(Event::End(e)) if e.name().as_ref() == b"osmatch" => in_osmatch = false,
+            Ok(Event::Start(e) | Event::Empty(e)) if e.name().as_ref() == b"uptime" && in_host => {
+                for (k, v) in attrs(&e) { match k.as_slice() { b"seconds" => host.info.uptime_secs = v.parse().ok(), b"lastboot" => host.info.last_boot = Some(v), _ => {} } }
+            }
+            Ok(Event::Start(e) | Event::Empty(e)) if e.name().as_ref() == b"distance" && in_host => {
+                host.info.distance = attrs(&e).into_iter().find(|(k, _)| k == b"value").and_then(|(_, v)| v.parse().ok());
+            }
+            Ok(Event::Start(e) | Event::Empty(e)) if e.name().as_ref() == b"hop" && in_host => {
+                let mut hop = TraceHop::default();
+                for (k, v) in attrs(&e) { match k.as_slice() { b"ttl" => hop.ttl = v.parse().unwrap_or(0), b"ipaddr" => hop.ip = v, b"rtt" => hop.rtt_ms = v.parse().ok(), b"host" => hop.host = Some(v), _ => {} } }
+                host.info.trace.push(hop);
+            }
+            Ok(Event::Empty(e)) if e.name().as_ref() == b"hosts" => {
+                hosts_down = attrs(&e).into_iter().find(|(k, _)| k == b"down").and_then(|(_, v)| v.parse().ok()).unwrap_or(0);
+            }
+            Ok(Event::Start(e)) if e.name().as_ref() == b"cpe" => in_cpe = current.is_some() || in_osmatch,
+            Ok(Event::End(e)) if e.name().as_ref() == b"cpe" => in_cpe = false,
+            Ok(Event::Start(e)) if e.name().as_ref() == b"hostscript" => in_hostscript = true,
+            Ok(Event::End(e)) if e.name().as_ref() == b"hostscript" => in_hostscript = false,
+            Ok(Event::Start(e)) if e.name().as_ref() == b"script" => { script = Some(script_start(&e)); tables = vec![(None, Vec::new())]; }
+            Ok(Event::Empty(e)) if e.name().as_ref() == b"script" => {
+                let s = script_start(&e);
+                if let Some(p) = current.as_mut() { p.scripts.push(s); } else if in_hostscript { host.scripts.push(s); }
+            }
+            Ok(Event::Start(e)) if e.name().as_ref() == b"table" && script.is_some() => tables.push((key(&e), Vec::new())),
+            Ok(Event::Empty(e)) if e.name().as_ref() == b"table" && script.is_some() => { if let Some(t) = tables.last_mut() { t.1.push((key(&e), Value::Array(vec![]))); } }
+            Ok(Event::End(e)) if e.name().as_ref() == b"table" && tables.len() > 1 => {
+                let (k, children) = tables.pop().unwrap_or_default();
+                if let Some(t) = tables.last_mut() { t.1.push((k, table_value(children))); }
+            }
+            Ok(Event::Start(e)) if e.name().as_ref() == b"elem" && script.is_some() => elem = Some((key(&e), String::new())),
+            Ok(Event::Empty(e)) if e.name().as_ref() == b"elem" && script.is_some() => { if let Some(t) = tables.last_mut() { t.1.push((key(&e), Value::String(String::new()))); } }
+            Ok(Event::End(e)) if e.name().as_ref() == b"elem" => {
+                if let (Some((k, text)), Some(t)) = (elem.take(), tables.last_mut()) { t.1.push((k, Value::String(text))); }
+            }
+            Ok(Event::Text(t)) if in_cpe => {
+                let cpe = String::from_utf8_lossy(&t).trim().to_string();
+                if let Some(p) = current.as_mut() { p.cpe.push(cpe); } else if let Some(m) = host.info.os.last_mut() && !m.cpe.contains(&cpe) { m.cpe.push(cpe); }
+            }
+            Ok(Event::Text(t)) => { if let Some((_, text)) = elem.as_mut() { text.push_str(&String::from_utf8_lossy(&t)); } }
+            Ok(Event::GeneralRef(r)) => {
+                if let Some((_, text)) = elem.as_mut() {
+                    let name = String::from_utf8_lossy(&r).into_owned();
+                    match r.resolve_char_ref().ok().flatten() {
+                        Some(c) => text.push(c),
+                        None => text.push_str(match name.as_str() { "amp" => "&", "lt" => "<", "gt" => ">", "quot" => "\"", "apos" => "'", _ => "" }),
+                    }
+                }
+            }
+            Ok(Event::End(e)) if e.name().as_ref() == b"script" => {
+                if let Some(mut s) = script.take() {
+                    let children = tables.pop().map(|t| t.1).unwrap_or_default();
+                    tables.clear();
+                    if !children.is_empty() { s.data = table_value(children); }
+                    if let Some(p) = current.as_mut() { p.scripts.push(s); } else if in_hostscript { host.scripts.push(s); }
+                }
+            }
+            Ok(Event::End(e)) if e.name().as_ref() == b"port" => { if let Some(p) = current.take() { host.ports.push(p); } }
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(anyhow!("XML error: {}", e)),
+            _ => {}
+        }
+        buf.clear();
+    }
+    // Sin -v Nmap no escribe el <host> de un equipo caído: solo lo cuenta en <runstats>
+    if host.info.status.is_empty() { host.info.status = if hosts_down > 0 { "down".into() } else { "unknown".into() }; }
+    Ok(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn port_specs_by_protocol() {
+        let spec = PortSpec::parse("22,80, U:53,161-162,T:8000-8002").unwrap();
+        assert_eq!(spec, PortSpec { tcp: vec![22, 80, 8000, 8001, 8002], udp: vec![53, 161, 162] });
+        assert_eq!(spec.to_nmap_arg(), "T:22,80,8000-8002,U:53,161-162");
+        assert_eq!(PortSpec::parse("443,1-3").unwrap().to_nmap_arg(), "1-3,443");
+        assert_eq!(PortSpec::parse("U:161").unwrap().to_nmap_arg(), "U:161");
+        assert!(PortSpec::parse("S:9").is_err());
+        assert_eq!(split_ports("T:22,80").unwrap(), vec![22, 80]);
+        assert!(split_ports("22,U:53").is_err());
+    }
+
+    #[test]
+    fn udp_ports_for_scan() {
+        let args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+        // -sU sin lista TCP: sin -p, así Nmap sigue escaneando su set TCP por defecto (y el UDP)
+        let mut a = args("-sU");
+        assert!(scan_ports(&mut a, &PortSpec::default(), None, true, "192.0.2.10").unwrap().is_empty());
+        assert!(scan_ports(&mut args("-sT -sU"), &PortSpec { tcp: vec![], udp: vec![161] }, None, true, "x").unwrap().is_empty());
+        // Con lista TCP, la curada en lugar del top 1000 UDP de Nmap
+        let tcp_only = PortSpec { tcp: vec![22], udp: vec![] };
+        assert_eq!(scan_ports(&mut args("-sT -sU"), &tcp_only, None, true, "x").unwrap().udp, UDP_TOP_PORTS);
+        // Sin -sU no hay UDP, ni siquiera el que vio Shodan
+        let mut a = args("-sT");
+        assert!(scan_ports(&mut a, &PortSpec { tcp: vec![], udp: vec![161] }, None, true, "x").unwrap().is_empty());
+        // U: en la matriz fija añade -sU solo con root
+        let mut a = args("-sT");
+        assert_eq!(scan_ports(&mut a, &PortSpec::default(), Some("T:22,U:161"), true, "x").unwrap(), PortSpec { tcp: vec![22], udp: vec![161] });
+        assert_eq!(a, args("-sU -sT"));
+        let mut a = args("-sT");
+        assert_eq!(scan_ports(&mut a, &PortSpec::default(), Some("T:22,U:161"), false, "x").unwrap(), PortSpec { tcp: vec![22], udp: vec![] });
+    }
+
+    #[test]
+    fn parses_port_protocol() {
+        let xml = r#"<nmaprun><host><ports><port protocol="tcp" portid="22"><state state="open"/><service name="ssh"/></port><port protocol="udp" portid="161"><state state="open|filtered"/><service name="snmp"/></port></ports></host></nmaprun>"#;
+        let ports = parse_nmap_ports(xml).unwrap();
+        assert_eq!(ports.iter().map(|p| (p.label(), p.state.as_str())).collect::<Vec<_>>(), vec![("22".to_string(), "open"), ("161/udp".to_string(), "open|filtered")]);
+    }
+
+    #[test]
+    fn parses_service_fingerprint() {
+        let xml = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/nmap/host.xml")).unwrap();
+        let ports = parse_nmap_ports(&xml).unwrap();
+        let ssh = &ports[0];
+        assert_eq!((ssh.reason.as_deref(), ssh.reason_ttl, ssh.method.as_deref(), ssh.conf, ssh.ostype.as_deref()), (Some("syn-ack"), Some(0), Some("probed"), Some(10), Some("Linux")));
+        assert_eq!(ssh.fingerprint(), "OpenSSH 8.4p1 (Debian 5+deb11u3; protocol 2.0)");
+        assert_eq!(ssh.cpe, vec!["cpe:/a:openbsd:openssh:8.4p1", "cpe:/o:linux:linux_kernel"]);
+        assert_eq!((ports[1].tunnel.as_deref(), ports[1].fingerprint().as_str()), (Some("ssl"), "nginx 1.18.0"));
+    }
+
+    #[test]
+    fn parses_nse_scripts() {
+        let xml = r#"<nmaprun><host><ports><port protocol="tcp" portid="443"><state state="open"/><service name="https"/>
+<script id="ssl-cert" output="Subject: commonName=ejemplo.cl"><table key="subject"><elem key="commonName">ejemplo.cl</elem></table><table key="extensions"><table><elem key="name">X509v3 Subject Alternative Name</elem></table><table><elem key="name">X509v3 Key Usage</elem></table></table><elem key="sig_algo">sha256WithRSAEncryption</elem></script>
+<script id="http-title" output="A &amp; B"><elem key="title">A &amp; B</elem></script><script id="vacio" output=""/></port></ports>
+<hostscript><script id="smb-os-discovery" output="&#xa;  OS: Windows&#xa;"><elem key="os">Windows</elem><elem>suelto</elem></script></hostscript></host>
+<postscript><script id="fuera" output="x"/></postscript></nmaprun>"#;
+        let host = parse_nmap_xml(xml).unwrap();
+        let scripts = &host.ports[0].scripts;
+        assert_eq!(scripts.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(), vec!["ssl-cert", "http-title", "vacio"]);
+        assert_eq!(scripts[0].data, serde_json::json!({"subject": {"commonName": "ejemplo.cl"}, "extensions": [{"name": "X509v3 Subject Alternative Name"}, {"name": "X509v3 Key Usage"}], "sig_algo": "sha256WithRSAEncryption"}));
+        assert_eq!((scripts[1].output.as_str(), &scripts[1].data), ("A & B", &serde_json::json!({"title": "A & B"})));
+        assert!(scripts[2].data.is_null());
+        assert_eq!(host.scripts.len(), 1);
+        assert_eq!(host.scripts[0].output, "\n  OS: Windows\n");
+        assert_eq!(host.scripts[0].data, serde_json::json!({"os": "Windows", "1": "suelto"}));
+    }
+
+    #[test]
+    fn parses_host_info() {
+        let xml = r#"<nmaprun><hosthint><status state="up" reason="unknown-response"/><address addr="192.0.2.20" addrtype="ipv4"/></hosthint>
+<host><status state="up" reason="arp-response" reason_ttl="0"/><address addr="192.0.2.20" addrtype="ipv4"/><address addr="00:11:22:33:44:55" addrtype="mac" vendor="Dell"/>
+<hostnames><hostname name="srv.ejemplo.cl" type="user"/><hostname name="srv.ejemplo.cl" type="user"/><hostname name="srv.local" type="PTR"/></hostnames>
+<ports><port protocol="tcp" portid="22"><state state="open"/><service name="ssh"><cpe>cpe:/a:openbsd:openssh</cpe></service></port></ports>
+<os><portused state="open" proto="tcp" portid="22"/><osmatch name="Linux 5.0 - 5.14" accuracy="95" line="1"><osclass type="general purpose" vendor="Linux" osfamily="Linux" osgen="5.X" accuracy="95"><cpe>cpe:/o:linux:linux_kernel:5</cpe></osclass><osclass accuracy="95"><cpe>cpe:/o:linux:linux_kernel:5</cpe></osclass></osmatch><osmatch name="Linux 4.15" accuracy="88" line="2"/></os>
+<uptime seconds="93784" lastboot="Thu Oct 15 10:00:00 2026"/><distance value="2"/>
+<trace port="22" proto="tcp"><hop ttl="1" ipaddr="192.0.2.1" rtt="0.45" host="gw.local"/><hop ttl="2" ipaddr="192.0.2.20" rtt="1.10"/></trace></host>
+<runstats><hosts up="1" down="0" total="1"/></runstats></nmaprun>"#;
+        let info = parse_nmap_xml(xml).unwrap().info;
+        assert_eq!((info.status.as_str(), info.status_reason.as_deref()), ("up", Some("arp-response")));
+        assert_eq!((info.mac.as_deref(), info.mac_vendor.as_deref()), (Some("00:11:22:33:44:55"), Some("Dell")));
+        assert_eq!(info.hostnames.iter().map(|h| h.name.as_str()).collect::<Vec<_>>(), vec!["srv.ejemplo.cl", "srv.local"]);
+        assert_eq!(info.os.iter().map(|o| (o.name.as_str(), o.accuracy)).collect::<Vec<_>>(), vec![("Linux 5.0 - 5.14", 95), ("Linux 4.15", 88)]);
+        assert_eq!(info.os[0].cpe, vec!["cpe:/o:linux:linux_kernel:5"]);
+        assert_eq!(info.best_os().as_deref(), Some("Linux 5.0 - 5.14 (95%)"));
+        assert_eq!((info.uptime_secs, info.distance), (Some(93784), Some(2)));
+        assert_eq!((info.trace[0].ip.as_str(), info.trace[0].host.as_deref(), info.trace[1].rtt_ms), ("192.0.2.1", Some("gw.local"), Some(1.10)));
+        assert!(!info.is_down());
+
+        // Caído sin -v: no hay <host>, solo el recuento de runstats; con -v el <status> es explícito
+        let down = parse_nmap_xml(r#"<nmaprun><runstats><hosts up="0" down="1" total="1"/></runstats></nmaprun>"#).unwrap();
+        assert!(down.info.is_down() && down.ports.is_empty());
+        let explicit = parse_nmap_xml(r#"<nmaprun><host><status state="down" reason="no-response"/><address addr="192.0.2.30" addrtype="ipv4"/></host></nmaprun>"#).unwrap().info;
+        assert_eq!((explicit.status.as_str(), explicit.status_reason.as_deref()), ("down", Some("no-response")));
+        assert_eq!(parse_nmap_xml("<nmaprun/>").unwrap().info.status, "unknown");
+    }
+}
@@ -1,6 +1,6 @@
 use anyhow::Result;
-use crate::models::{HostReport, IpPorts, PortDetail};
-use std::fs;
+use crate::models::{HostReport, IpPorts, NmapHostInfo, PortDetail, Protocol, ScriptResult, ShodanCount, ShodanHost, ShodanMatch};
+use std::{collections::BTreeMap, fs};
 
 pub fn summarize(reports: &[HostReport]) {
 	let total_hosts = reports.len();
@@ -13,13 +13,27 @@ pub fn summarize(reports: &[HostReport]) {
 	println!("Puertos abiertos:   {}", open_total);
 	println!("Puertos cerrados:   {}", closed_total);
 	println!("Puertos filtrados:  {}", filtered_total);
+	let down: Vec<&str> = reports.iter().filter(|r| r.nmap.as_ref().is_some_and(|n| n.is_down())).map(|r| r.ip.as_str()).collect();
+	if !down.is_empty() { println!("Hosts caídos (down): {} ({})", down.len(), down.join(", ")); }
+	let failed: Vec<&HostReport> = reports.iter().filter(|r| r.discovery_error.is_some()).collect();
+	if !failed.is_empty() {
+		println!("Descubrimiento fallido: {} host(s)", failed.len());
+		for r in failed { println!("  {}: {}", r.ip, r.discovery_error.as_deref().unwrap_or_default()); }
+	}
+}
+
+/// Cambios aplicados al dork por `--on-dork-error` (nada si se usó el original).
+pub fn print_dork_changes(changes: &[String]) {
+	if changes.is_empty() { return; }
+	println!("=== CAMBIOS AL DORK ===");
+	for c in changes { println!("- {c}"); }
 }
 
 pub fn filter_ports(ports: &[PortDetail], hide_tcpwrapped: bool, only_open: bool) -> Vec<PortDetail> {
 	let mut v: Vec<PortDetail> = ports.iter().filter(|p| {
 		(!only_open || p.state == "open") && (!hide_tcpwrapped || p.service.as_deref() != Some("tcpwrapped"))
 	}).cloned().collect();
-	v.sort_by_key(|p| p.port);
+	v.sort_by_key(|p| (p.protocol, p.port));
 	v
 }
 
@@ -28,23 +42,39 @@ pub fn print_host_details(reports: &[HostReport], hide_tcpwrapped: bool, only_op
 	for h in reports {
 		let filtered = filter_ports(&h.ports, hide_tcpwrapped, only_open);
 		println!("{} ({})", h.ip, h.target);
+		if let Some(e) = &h.discovery_error { println!("  descubrimiento fallido: {e}"); }
+		print_nmap_host(h);
 		if filtered.is_empty() { println!("  (sin puertos tras filtro)"); continue; }
 		let mut conocidos = Vec::new();
 		let mut otros = Vec::new();
 		for p in filtered { match p.service.as_deref() { Some("tcpwrapped") | None | Some("unknown") => otros.push(p), _ => conocidos.push(p) } }
 		if !conocidos.is_empty() {
-			let list = conocidos.iter().map(|p| format!("{}:{}:{}", p.port, p.service.as_deref().unwrap_or(""), p.state)).collect::<Vec<_>>().join(", ");
+			let list = conocidos.iter().map(known_port).collect::<Vec<_>>().join(", ");
 			println!("  conocidos: {}", list);
 		}
 		if !otros.is_empty() {
 			let show = 15usize.min(otros.len());
-			let list = otros.iter().take(show).map(|p| format!("{}:{}:{}", p.port, p.service.as_deref().unwrap_or(""), p.state)).collect::<Vec<_>>().join(", ");
+			let list = otros.iter().take(show).map(|p| format!("{}:{}:{}", p.label(), p.service.as_deref().unwrap_or(""), p.state)).collect::<Vec<_>>().join(", ");
 			println!("  otros({}): {}", otros.len(), list);
 			if otros.len() > show { println!("  ... +{} más", otros.len() - show); }
 		}
 	}
 }
 
+/// Estado (si no está up), MAC y SO estimado del host según Nmap.
+fn print_nmap_host(h: &HostReport) {
+	let Some(n) = &h.nmap else { return };
+	if n.status != "up" { println!("  host {}{}", n.status, n.status_reason.as_deref().map(|r| format!(" ({r})")).unwrap_or_default()); }
+	if let Some(mac) = &n.mac { println!("  MAC: {mac}{}", n.mac_vendor.as_deref().map(|v| format!(" ({v})")).unwrap_or_default()); }
+	if let Some(os) = n.best_os() { println!("  SO: {os}"); }
+}
+
+/// `puerto:servicio:estado`, con `[producto versión]` si `-sV` lo identificó.
+fn known_port(p: &PortDetail) -> String {
+	let f = p.fingerprint();
+	format!("{}:{}:{}{}", p.label(), p.service.as_deref().unwrap_or(""), p.state, if f.is_empty() { String::new() } else { format!(" [{f}]") })
+}
+
 /// Versión extendida que marca con ★ los hosts considerados "interesantes" (>= min_open tras filtros).
 pub fn print_host_details_with_interest(reports: &[HostReport], hide_tcpwrapped: bool, only_open: bool, min_open: usize) {
 	println!("=== DETALLE PUERTOS POR HOST ===");
@@ -53,29 +83,56 @@ pub fn print_host_details_with_interest(reports: &[HostReport], hide_tcpwrapped:
 		let open_count = filtered.iter().filter(|p| p.state == "open").count();
 		let star = if min_open > 0 && open_count >= min_open { "★ " } else { "" };
 		println!("{}{} ({})", star, h.ip, h.target);
+		if let Some(e) = &h.discovery_error { println!("  descubrimiento fallido: {e}"); }
+		print_nmap_host(h);
 		if filtered.is_empty() { println!("  (sin puertos tras filtro)"); continue; }
 		let mut conocidos = Vec::new();
 		let mut otros = Vec::new();
 		for p in filtered { match p.service.as_deref() { Some("tcpwrapped") | None | Some("unknown") => otros.push(p), _ => conocidos.push(p) } }
 		if !conocidos.is_empty() {
-			let list = conocidos.iter().map(|p| format!("{}:{}:{}", p.port, p.service.as_deref().unwrap_or(""), p.state)).collect::<Vec<_>>().join(", ");
+			let list = conocidos.iter().map(known_port).collect::<Vec<_>>().join(", ");
 			println!("  conocidos: {}", list);
 		}
 		if !otros.is_empty() {
 			let show = 15usize.min(otros.len());
-			let list = otros.iter().take(show).map(|p| format!("{}:{}:{}", p.port, p.service.as_deref().unwrap_or(""), p.state)).collect::<Vec<_>>().join(", ");
+			let list = otros.iter().take(show).map(|p| format!("{}:{}:{}", p.label(), p.service.as_deref().unwrap_or(""), p.state)).collect::<Vec<_>>().join(", ");
 			println!("  otros({}): {}", otros.len(), list);
 			if otros.len() > show { println!("  ... +{} más", otros.len() - show); }
 		}
 	}
 }
 
+/// Protocolo de un banner Shodan (`transport`; sin él, TCP).
+fn shodan_protocol(m: &ShodanMatch) -> Protocol { m.transport.as_deref().and_then(|t| t.parse().ok()).unwrap_or_default() }
+
+/// Banner Shodan para un puerto concreto del host (si Shodan lo vio): primero los del dork, luego los de `/shodan/host`.
+fn shodan_for_port<'a>(r: &'a HostReport, p: &PortDetail) -> Option<&'a ShodanMatch> {
+	r.shodan.iter().chain(r.shodan_host.iter().flat_map(|h| h.banners.iter())).find(|m| m.port == p.port && shodan_protocol(m) == p.protocol)
+}
+
+/// "producto versión" del banner Shodan, o "sí" si lo vio sin identificar producto.
+fn shodan_label(m: &ShodanMatch) -> String {
+	match (&m.product, &m.version) { (Some(p), Some(v)) => format!("{p} {v}"), (Some(p), None) => p.clone(), _ => "sí".into() }
+}
+
 pub fn export_csv(path: &std::path::Path, reports: &[HostReport], hide_tcpwrapped: bool, only_open: bool) -> Result<()> {
 	let mut wtr = csv::Writer::from_path(path)?;
-	wtr.write_record(&["target","ip","port","state","service"])?;
+	wtr.write_record(["target","ip","ptr","host_status","mac","mac_vendor","os","uptime_secs","distance","port","protocol","state","reason","reason_ttl","service","product","version","extrainfo","ostype","tunnel","method","conf","cpe","shodan","tags","discovery_error"])?;
+	let opt = |v: &Option<String>| v.clone().unwrap_or_default();
+	let num = |v: Option<u8>| v.map(|n| n.to_string()).unwrap_or_default();
 	for r in reports {
 		let ports = filter_ports(&r.ports, hide_tcpwrapped, only_open);
-		for p in ports { wtr.write_record(&[ &r.target, &r.ip, &p.port.to_string(), &p.state, p.service.as_deref().unwrap_or("") ])?; }
+		let discovery_error = r.discovery_error.clone().unwrap_or_default();
+		let n = r.nmap.clone().unwrap_or_default();
+		let host = vec![ r.target.clone(), r.ip.clone(), r.ptr.join(";"), n.status.clone(), opt(&n.mac), opt(&n.mac_vendor), n.best_os().unwrap_or_default(), n.uptime_secs.map(|u| u.to_string()).unwrap_or_default(), num(n.distance) ];
+		let tail = [ r.tags.join(";"), discovery_error.clone() ];
+		// Un host caído o cuyo descubrimiento falló aparece aunque no tenga puertos (fila sin puerto)
+		if ports.is_empty() && (!discovery_error.is_empty() || n.is_down()) { wtr.write_record(host.iter().cloned().chain(std::iter::repeat_n(String::new(), 15)).chain(tail.iter().cloned()))?; }
+		for p in ports {
+			let shodan = shodan_for_port(r, &p).map(shodan_label).unwrap_or_default();
+			let port = [ p.port.to_string(), p.protocol.to_string(), p.state.clone(), opt(&p.reason), num(p.reason_ttl), opt(&p.service), opt(&p.product), opt(&p.version), opt(&p.extrainfo), opt(&p.ostype), opt(&p.tunnel), opt(&p.method), num(p.conf), p.cpe.join(";"), shodan ];
+			wtr.write_record(host.iter().cloned().chain(port).chain(tail.iter().cloned()))?;
+		}
 	}
 	wtr.flush()?;
 	Ok(())
@@ -83,34 +140,140 @@ pub fn export_csv(path: &std::path::Path, reports: &[HostReport], hide_tcpwrappe
 
 pub fn export_json(path: &std::path::Path, reports: &[HostReport], hide_tcpwrapped: bool, only_open: bool) -> Result<()> {
 	#[derive(serde::Serialize)]
-	struct JPort { port: u16, state: String, service: Option<String> }
-	#[derive(serde::Serialize)]
-	struct JHost<'a> { target: &'a str, ip: &'a str, ports: Vec<JPort> }
+	struct JHost<'a> { target: &'a str, ip: &'a str, ports: Vec<PortDetail>, #[serde(skip_serializing_if = "<[ShodanMatch]>::is_empty")] shodan: &'a [ShodanMatch], #[serde(skip_serializing_if = "Option::is_none")] shodan_host: Option<&'a ShodanHost>, #[serde(skip_serializing_if = "<[String]>::is_empty")] tags: &'a [String], #[serde(skip_serializing_if = "<[String]>::is_empty")] ptr: &'a [String], #[serde(skip_serializing_if = "Option::is_none")] nmap: Option<&'a NmapHostInfo>, #[serde(skip_serializing_if = "<[ScriptResult]>::is_empty")] host_scripts: &'a [ScriptResult], #[serde(skip_serializing_if = "Option::is_none")] discovery_error: Option<&'a str> }
 	let mut out = Vec::new();
 	for r in reports {
-		let ports = filter_ports(&r.ports, hide_tcpwrapped, only_open).into_iter()
-			.map(|p| JPort { port: p.port, state: p.state, service: p.service })
-			.collect();
-		out.push(JHost { target: &r.target, ip: &r.ip, ports });
+		let ports = filter_ports(&r.ports, hide_tcpwrapped, only_open);
+		out.push(JHost { target: &r.target, ip: &r.ip, ports, shodan: &r.shodan, shodan_host: r.shodan_host.as_ref(), tags: &r.tags, ptr: &r.ptr, nmap: r.nmap.as_ref(), host_scripts: &r.host_scripts, discovery_error: r.discovery_error.as_deref() });
 	}
 	fs::write(path, serde_json::to_string_pretty(&out)?)?;
 	Ok(())
 }
 
+/// Hosts agrupados por hostname del archivo de objetivos: una IP con varios nombres en `target` aparece bajo cada uno
+/// y un nombre con varias IPs las reúne; las IPs escritas tal cual (sin nombre) no entran.
+pub fn group_by_target(reports: &[HostReport]) -> BTreeMap<&str, Vec<&HostReport>> {
+	let mut groups: BTreeMap<&str, Vec<&HostReport>> = BTreeMap::new();
+	for r in reports { for t in r.target.split(',').filter(|t| !t.is_empty() && *t != r.ip) { groups.entry(t).or_default().push(r); } }
+	groups
+}
+
+/// `report_by_target.json`: por hostname, sus IPs con los puertos tras filtro (`22`, `161/udp`). No escribe nada si no hay hostnames.
+pub fn export_target_index(path: &std::path::Path, reports: &[HostReport], hide_tcpwrapped: bool, only_open: bool) -> Result<bool> {
+	#[derive(serde::Serialize)]
+	struct JIp<'a> { ip: &'a str, ports: Vec<String> }
+	#[derive(serde::Serialize)]
+	struct JTarget<'a> { target: &'a str, hosts: Vec<JIp<'a>> }
+	let groups = group_by_target(reports);
+	if groups.is_empty() { return Ok(false); }
+	let out: Vec<JTarget> = groups.into_iter().map(|(target, hosts)| JTarget { target, hosts: hosts.into_iter().map(|r| JIp { ip: &r.ip, ports: filter_ports(&r.ports, hide_tcpwrapped, only_open).iter().map(PortDetail::label).collect() }).collect() }).collect();
+	fs::write(path, serde_json::to_string_pretty(&out)?)?;
+	Ok(true)
+}
+
+fn or_dash(s: &str) -> &str { if s.is_empty() { "-" } else { s } }
+
+/// Estado, MAC, hostnames, SO, uptime, distancia y traza del host (lo que Nmap haya dado).
+fn md_nmap_host(md: &mut String, n: &NmapHostInfo) {
+	md.push_str(&format!("_Estado:_ {}{}\n\n", n.status, n.status_reason.as_deref().map(|r| format!(" ({r})")).unwrap_or_default()));
+	if let Some(mac) = &n.mac { md.push_str(&format!("_MAC:_ {mac}{}\n\n", n.mac_vendor.as_deref().map(|v| format!(" ({v})")).unwrap_or_default())); }
+	if !n.hostnames.is_empty() { md.push_str(&format!("_Hostnames Nmap:_ {}\n\n", n.hostnames.iter().map(|h| match &h.kind { Some(k) => format!("{} ({k})", h.name), None => h.name.clone() }).collect::<Vec<_>>().join(", "))); }
+	if !n.os.is_empty() { md.push_str(&format!("_SO:_ {}\n\n", n.os.iter().map(|o| format!("{} ({}%)", o.name, o.accuracy)).collect::<Vec<_>>().join(", "))); }
+	if let Some(u) = n.uptime_secs { md.push_str(&format!("_Uptime:_ {} d {} h{}\n\n", u / 86400, u % 86400 / 3600, n.last_boot.as_deref().map(|b| format!(" (arranque: {b})")).unwrap_or_default())); }
+	if let Some(d) = n.distance { md.push_str(&format!("_Distancia:_ {d} salto(s)\n\n")); }
+	if !n.trace.is_empty() {
+		md.push_str("| TTL | IP | RTT (ms) | Host |\n|----:|----|---------:|------|\n");
+		for h in &n.trace { md.push_str(&format!("| {} | {} | {} | {} |\n", h.ttl, h.ip, h.rtt_ms.map(|r| r.to_string()).unwrap_or_else(|| "-".into()), or_dash(h.host.as_deref().unwrap_or("")))); }
+		md.push('\n');
+	}
+}
+
+/// Un bloque por script: id en negrita y la salida de Nmap tal cual.
+fn md_scripts(md: &mut String, scripts: &[ScriptResult]) {
+	for s in scripts { md.push_str(&format!("**{}**\n\n```\n{}\n```\n\n", s.id, s.output.trim_matches('\n'))); }
+}
+
+/// Cómo se obtuvo el puerto/servicio: motivo y TTL, método y confianza de `-sV`, SO y CPE.
+fn detection(p: &PortDetail) -> String {
+	let mut parts = Vec::new();
+	if let Some(reason) = &p.reason { parts.push(match p.reason_ttl { Some(ttl) => format!("{reason} ttl {ttl}"), None => reason.clone() }); }
+	if let Some(m) = &p.method { parts.push(match p.conf { Some(c) => format!("{m} conf {c}"), None => m.clone() }); }
+	if let Some(os) = &p.ostype { parts.push(format!("SO {os}")); }
+	parts.extend(p.cpe.iter().map(|c| format!("`{c}`")));
+	parts.join(", ")
+}
+
 pub fn export_markdown(path: &std::path::Path, reports: &[HostReport], hide_tcpwrapped: bool, only_open: bool) -> Result<()> {
 	let mut md = String::new();
 	md.push_str("# Reporte de Escaneo\n\n");
+	let groups = group_by_target(reports);
+	if !groups.is_empty() {
+		md.push_str("## Por hostname\n\n| Hostname | IP | Puertos |\n|----------|----|---------|\n");
+		for (target, hosts) in &groups {
+			for r in hosts { md.push_str(&format!("| {target} | {} | {} |\n", r.ip, or_dash(&filter_ports(&r.ports, hide_tcpwrapped, only_open).iter().map(PortDetail::label).collect::<Vec<_>>().join(", ")))); }
+		}
+		md.push('\n');
+	}
 	for r in reports {
 		md.push_str(&format!("## {} ({})\n\n", r.ip, r.target));
+		if !r.tags.is_empty() { md.push_str(&format!("_Etiquetas:_ {}\n\n", r.tags.join(", "))); }
+		if !r.ptr.is_empty() { md.push_str(&format!("_PTR:_ {}\n\n", r.ptr.join(", "))); }
+		if let Some(e) = &r.discovery_error { md.push_str(&format!("_Descubrimiento fallido:_ {e}\n\n")); }
+		if let Some(n) = &r.nmap { md_nmap_host(&mut md, n); }
 		let filtered = filter_ports(&r.ports, hide_tcpwrapped, only_open);
-		if filtered.is_empty() { md.push_str("_Sin puertos tras filtro._\n\n"); continue; }
-		md.push_str("| Puerto | Estado | Servicio |\n|-------:|--------|----------|\n");
-		for p in filtered { md.push_str(&format!("| {} | {} | {} |\n", p.port, p.state, p.service.unwrap_or_default())); }
-		md.push('\n');
+		if filtered.is_empty() { md.push_str(if r.nmap.as_ref().is_some_and(|n| n.is_down()) { "_Host caído: Nmap no obtuvo respuesta._\n\n" } else { "_Sin puertos tras filtro._\n\n" }); } else {
+			md.push_str("| Puerto | Proto | Estado | Servicio | Producto | Detección | Shodan |\n|-------:|-------|--------|----------|----------|-----------|--------|\n");
+			for p in &filtered {
+				let service = match (&p.tunnel, &p.service) { (Some(t), Some(s)) => format!("{t}/{s}"), (_, s) => s.clone().unwrap_or_default() };
+				md.push_str(&format!("| {} | {} | {} | {} | {} | {} | {} |\n", p.port, p.protocol, p.state, service, or_dash(&p.fingerprint()), or_dash(&detection(p)), shodan_for_port(r, p).map(shodan_label).unwrap_or_else(|| "-".into())));
+			}
+			md.push('\n');
+			for p in filtered.iter().filter(|p| !p.scripts.is_empty()) {
+				md.push_str(&format!("### Scripts NSE {}/{}\n\n", p.port, p.protocol));
+				md_scripts(&mut md, &p.scripts);
+			}
+		}
+		if !r.host_scripts.is_empty() { md.push_str("### Scripts NSE de host\n\n"); md_scripts(&mut md, &r.host_scripts); }
+		if let Some(h) = &r.shodan_host {
+			let ports = h.ports.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
+			md.push_str(&format!("_Shodan host:_ puertos {} | hostnames {} | vulns {}\n\n", or_dash(&ports), or_dash(&h.hostnames.join(", ")), or_dash(&h.vulns.join(", "))));
+		}
+		// Puertos que Shodan reportó pero Nmap no confirmó tras filtros
+		let unconfirmed: Vec<&ShodanMatch> = r.shodan.iter().filter(|m| !filtered.iter().any(|p| p.port == m.port && p.protocol == shodan_protocol(m))).collect();
+		if !unconfirmed.is_empty() {
+			let list = unconfirmed.iter().map(|m| format!("{}{} ({})", m.port, if shodan_protocol(m) == Protocol::Udp { "/udp" } else { "" }, shodan_label(m))).collect::<Vec<_>>().join(", ");
+			md.push_str(&format!("_Solo Shodan (no confirmados por Nmap):_ {}\n\n", list));
+		}
 	}
 	fs::write(path, md)?; Ok(())
 }
 
+/// Banner pre-vuelo: total de resultados del dork y top `top` valores por facet.
+pub fn print_stats(stats: &ShodanCount, top: usize) {
+	println!("=== ESTADÍSTICAS SHODAN ===");
+	println!("Dork: {}", stats.query);
+	println!("Resultados totales: {}", stats.total);
+	for (facet, values) in &stats.facets {
+		let list = values.iter().take(top).map(|v| format!("{} ({})", v.value, v.count)).collect::<Vec<_>>().join(", ");
+		println!("  {facet}: {}", if list.is_empty() { "-".into() } else { list });
+	}
+}
+
+pub fn export_stats_json(path: &std::path::Path, stats: &ShodanCount) -> Result<()> {
+	fs::write(path, serde_json::to_string_pretty(stats)?)?; Ok(())
+}
+
+pub fn export_stats_csv(path: &std::path::Path, stats: &ShodanCount) -> Result<()> {
+	let mut wtr = csv::Writer::from_path(path)?;
+	wtr.write_record(["facet","value","count"])?;
+	wtr.write_record(["total","", &stats.total.to_string()])?;
+	for (facet, values) in &stats.facets {
+		for v in values { wtr.write_record([ facet, &v.value, &v.count.to_string() ])?; }
+	}
+	wtr.flush()?;
+	Ok(())
+}
+
 pub fn count_open_after_filter(ports: &[PortDetail]) -> usize { ports.iter().filter(|p| p.state == "open").count() }
 pub fn is_interesting_host(ports: &[PortDetail], min_open: usize) -> bool { count_open_after_filter(ports) >= min_open }
 
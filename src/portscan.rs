@@ -0,0 +1,169 @@
+//! Escáner TCP connect nativo (tokio), alternativa a `rustscan` con el mismo contrato `IpPorts` (`--discovery native`).
+//! Un semáforo global limita los sockets abiertos entre todos los hosts; cada host avanza por lotes cuyo tamaño
+//! se reduce ante errores de recursos locales (descriptores, puertos efímeros) y crece mientras no los haya.
+use anyhow::{Result, anyhow};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{collections::VecDeque, future::Future, io::ErrorKind, net::{IpAddr, SocketAddr}, sync::Arc, time::Duration};
+use tokio::{net::TcpStream, sync::Semaphore, task::JoinSet, time::timeout};
+use crate::{models::{DiscoveryStatus, IpPorts}, nmap::split_ports};
+
+/// Lote mínimo al que se reduce un host que agota recursos.
+const MIN_BATCH: usize = 16;
+/// Reintentos de un puerto que falló por falta de recursos locales.
+const MAX_RESOURCE_RETRIES: u8 = 3;
+
+#[derive(Debug, Clone)]
+pub struct NativeScanOptions {
+    /// Puertos a probar (ya expandidos desde `--scan-ports`).
+    pub ports: Vec<u16>,
+    pub timeout_ms: u64,
+    /// Tope global de sockets simultáneos (todos los hosts).
+    pub max_sockets: usize,
+    /// Lote inicial por host; se adapta entre `MIN_BATCH` y `max_sockets`.
+    pub batch: usize,
+}
+
+impl NativeScanOptions {
+    /// Valida la lista de puertos (`22,80,8000-8100`; el 0 se descarta).
+    pub fn new(ports: &str, timeout_ms: u64, max_sockets: usize, batch: usize) -> Result<Self> {
+        let ports: Vec<u16> = split_ports(ports).map_err(|e| anyhow!("lista de puertos inválida '{ports}': {e}"))?.into_iter().filter(|p| *p != 0).collect();
+        if ports.is_empty() { return Err(anyhow!("la lista de puertos del escáner nativo está vacía")); }
+        let max_sockets = max_sockets.max(1);
+        Ok(Self { ports, timeout_ms, max_sockets, batch: batch.clamp(MIN_BATCH.min(max_sockets), max_sockets) })
+    }
+
+    /// Ajusta el tope de sockets al límite de descriptores del proceso (avisando) y el lote inicial a ese tope.
+    fn fit_to_nofile(&self) -> Self {
+        let mut opts = self.clone();
+        if let Some(limit) = nofile_limit() {
+            // Margen para los descriptores que ya usa el proceso (logs, caché, HTTP)
+            let usable = limit.saturating_sub(128).max(MIN_BATCH);
+            if opts.max_sockets > usable {
+                eprintln!("[WARN] --scan-max-sockets {} supera el límite de descriptores ({limit}); uso {usable}", opts.max_sockets);
+                opts.max_sockets = usable;
+                opts.batch = opts.batch.min(usable);
+            }
+        }
+        opts
+    }
+}
+
+fn nofile_limit() -> Option<usize> {
+    let mut lim = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: getrlimit solo escribe en la estructura que le pasamos
+    let ok = unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut lim) } == 0;
+    (ok && lim.rlim_cur != libc::RLIM_INFINITY).then_some(lim.rlim_cur as usize)
+}
+
+enum Probe { Open, Closed, Exhausted }
+
+async fn probe(addr: SocketAddr, wait: Duration) -> Probe {
+    match timeout(wait, TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => Probe::Open,
+        // EMFILE/ENFILE/EADDRNOTAVAIL/ENOBUFS: el límite es nuestro, no del host remoto
+        Ok(Err(e)) if matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE | libc::EADDRNOTAVAIL | libc::ENOBUFS)) || e.kind() == ErrorKind::AddrNotAvailable => Probe::Exhausted,
+        // Rechazado, inalcanzable o sin respuesta en el plazo: no abierto
+        Ok(Err(_)) | Err(_) => Probe::Closed,
+    }
+}
+
+/// Puertos abiertos de una IP según `opts`. Error si algún puerto sigue sin probarse por falta de recursos locales
+/// tras `MAX_RESOURCE_RETRIES`: el host no se da por escaneado con esos puertos como cerrados.
+pub async fn native_scan_one(ip: &str, opts: &NativeScanOptions, sockets: Arc<Semaphore>) -> Result<Vec<u16>> {
+    let addr: IpAddr = ip.parse().map_err(|_| anyhow!("el escáner nativo necesita una IP y recibió '{ip}'"))?;
+    scan_with(addr, opts, sockets, probe).await
+}
+
+async fn scan_with<P, F>(addr: IpAddr, opts: &NativeScanOptions, sockets: Arc<Semaphore>, probe: P) -> Result<Vec<u16>>
+where P: Fn(SocketAddr, Duration) -> F + Copy + Send + 'static, F: Future<Output = Probe> + Send {
+    let wait = Duration::from_millis(opts.timeout_ms);
+    let mut pending: VecDeque<(u16, u8)> = opts.ports.iter().map(|p| (*p, 0)).collect();
+    let mut batch = opts.batch;
+    let mut open = Vec::new();
+    let mut unprobed = 0usize;
+    while !pending.is_empty() {
+        let mut set = JoinSet::new();
+        for (port, tries) in pending.drain(..batch.min(pending.len())) {
+            let sockets = sockets.clone();
+            set.spawn(async move { let _permit = sockets.acquire_owned().await.unwrap(); (port, tries, probe(SocketAddr::new(addr, port), wait).await) });
+        }
+        let mut exhausted = 0usize;
+        while let Some(r) = set.join_next().await {
+            match r? {
+                (port, _, Probe::Open) => open.push(port),
+                (_, _, Probe::Closed) => {}
+                (port, tries, Probe::Exhausted) => {
+                    exhausted += 1;
+                    if tries < MAX_RESOURCE_RETRIES { pending.push_back((port, tries + 1)); } else { unprobed += 1; }
+                }
+            }
+        }
+        batch = if exhausted > 0 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            (batch / 2).max(MIN_BATCH)
+        } else { (batch * 2).min(opts.max_sockets) };
+    }
+    if unprobed > 0 { return Err(anyhow!("{unprobed} puerto(s) sin probar en {addr} por falta de recursos locales (descriptores o puertos efímeros); baja --scan-max-sockets o sube el ulimit")); }
+    open.sort_unstable();
+    Ok(open)
+}
+
+/// Igual que `rustscan_many_with_progress`: `concurrency` hosts a la vez, todos bajo el tope global de sockets; un host que falla queda con su estado.
+pub async fn native_scan_many_with_progress(ips: &[String], concurrency: usize, opts: &NativeScanOptions) -> Result<Vec<IpPorts>> {
+    let pb = ProgressBar::new(ips.len() as u64);
+    pb.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:40.green/black} {pos}/{len} ({percent}%) Escáner nativo")?.progress_chars("##-"));
+    let opts = &opts.fit_to_nofile();
+    let hosts = Arc::new(Semaphore::new(concurrency.max(1)));
+    let sockets = Arc::new(Semaphore::new(opts.max_sockets));
+    let mut tasks = Vec::new();
+    for ip in ips {
+        let (ip, hosts, sockets, opts, pb) = (ip.clone(), hosts.clone(), sockets.clone(), opts.clone(), pb.clone());
+        tasks.push(tokio::spawn(async move {
+            let _permit = hosts.acquire_owned().await.unwrap();
+            let ports = native_scan_one(&ip, &opts, sockets).await;
+            pb.inc(1);
+            (ip, ports)
+        }));
+    }
+    let mut results = Vec::new();
+    for t in tasks {
+        let (ip, ports) = t.await?;
+        results.push(match ports { Ok(ports) => IpPorts::ok(ip, ports), Err(e) => IpPorts::failed(ip, DiscoveryStatus::Error, format!("{e:#}")) });
+    }
+    pb.finish_with_message("Escáner nativo listo");
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn finds_local_listeners() {
+        let a = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let b = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // Puerto libre: se reserva y se suelta para que quede cerrado
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let (pa, pb) = (a.local_addr().unwrap().port(), b.local_addr().unwrap().port());
+        let spec = format!("{pa},{pb},{closed}");
+        let opts = NativeScanOptions::new(&spec, 500, 64, 2).unwrap();
+        let got = native_scan_many_with_progress(&["127.0.0.1".into()], 4, &opts).await.unwrap();
+        let mut want = vec![pa, pb];
+        want.sort_unstable();
+        assert_eq!(got[0].ports, want);
+        assert!(NativeScanOptions::new("0", 500, 64, 16).is_err());
+        assert!(native_scan_one("ejemplo.cl", &opts, Arc::new(Semaphore::new(1))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn exhausted_ports_fail_the_host() {
+        // El 81 nunca consigue recursos: tras los reintentos el host falla en vez de quedar con ese puerto "cerrado"
+        let opts = NativeScanOptions::new("80-81", 500, 64, 16).unwrap();
+        let fake = |a: SocketAddr, _: Duration| async move { if a.port() == 81 { Probe::Exhausted } else { Probe::Open } };
+        let err = scan_with("192.0.2.10".parse().unwrap(), &opts, Arc::new(Semaphore::new(4)), fake).await.unwrap_err();
+        assert!(err.to_string().starts_with("1 puerto(s) sin probar en 192.0.2.10"), "{err}");
+        let fake = |_: SocketAddr, _: Duration| async move { Probe::Open };
+        assert_eq!(scan_with("192.0.2.10".parse().unwrap(), &opts, Arc::new(Semaphore::new(4)), fake).await.unwrap(), vec![80, 81]);
+    }
+}
@@ -0,0 +1,34 @@
+//! Perfil YAML del pipeline (`--profile`): valores por defecto que los flags de la línea de comandos sobrescriben.
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+use crate::args::RustscanSettings;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Mismos campos que `--rs-*` (ports, range, ulimit, tries, scan_order, timeout_ms, batch).
+    #[serde(default)]
+    pub rustscan: RustscanSettings,
+}
+
+pub fn load_profile(path: &Path) -> Result<Profile> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("No pude leer el perfil {}", path.display()))?;
+    let p: Profile = serde_yaml::from_str(&text).with_context(|| format!("Perfil inválido {}", path.display()))?;
+    p.rustscan.validate().with_context(|| format!("Sección rustscan del perfil {}", path.display()))?;
+    Ok(p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::ScanOrder;
+
+    #[test]
+    fn parses_rustscan_section() {
+        let p: Profile = serde_yaml::from_str("rustscan:\n  range: 1-1024\n  tries: 2\n  scan_order: random\n  ulimit: 6000\n").unwrap();
+        assert_eq!((p.rustscan.range.as_deref(), p.rustscan.tries, p.rustscan.scan_order, p.rustscan.ulimit), (Some("1-1024"), Some(2), Some(ScanOrder::Random), Some(6000)));
+        assert!(serde_yaml::from_str::<Profile>("rustscan:\n  trys: 2\n").is_err());
+        assert!(serde_yaml::from_str::<Profile>("{}").unwrap().rustscan == RustscanSettings::default());
+    }
+}
@@ -0,0 +1,59 @@
+//! DNS inverso (PTR) de las IPs del reporte final: consultas concurrentes con caché en disco
+//! (espacio `ptr` de la caché Shodan, mismo TTL). Los nombres quedan en `HostReport.ptr` y en `{hostname}` de las reglas.
+use anyhow::Result;
+use serde_json::json;
+use std::{collections::BTreeMap, net::IpAddr, sync::Arc};
+use tokio::sync::Semaphore;
+use trust_dns_resolver::{TokioAsyncResolver, error::ResolveErrorKind};
+use crate::{cache::DiskCache, models::HostReport, targets::{DnsOptions, build_resolver}};
+
+/// Resolvedor PTR con las mismas opciones `--dns-*` que los objetivos.
+#[derive(Debug, Clone)]
+pub struct PtrEnricher { pub dns: DnsOptions, pub cache: DiskCache }
+
+impl PtrEnricher {
+    pub fn new(dns: DnsOptions, cache: DiskCache) -> Self { Self { dns, cache } }
+
+    /// Nombres PTR por IP (solo las que tienen alguno). Los "sin registro" también se cachean; los errores transitorios no.
+    pub async fn lookup_many(&self, ips: &[String]) -> Result<BTreeMap<String, Vec<String>>> {
+        let resolver = build_resolver(&self.dns)?;
+        let sem = Arc::new(Semaphore::new(self.dns.concurrency.max(1)));
+        let mut tasks = Vec::new();
+        for ip in ips {
+            let (ip, resolver, cache, s) = (ip.clone(), resolver.clone(), self.cache.clone(), sem.clone());
+            tasks.push(tokio::spawn(async move { let _permit = s.acquire_owned().await.unwrap(); let names = ptr_one(&resolver, &cache, &ip).await; (ip, names) }));
+        }
+        let mut out = BTreeMap::new();
+        for t in tasks { let (ip, names) = t.await?; if !names.is_empty() { out.insert(ip, names); } }
+        Ok(out)
+    }
+
+    /// Consulta los PTR de todos los reportes y los guarda en `HostReport.ptr`.
+    pub async fn enrich(&self, reports: &mut [HostReport]) -> Result<()> {
+        let ips: Vec<String> = reports.iter().map(|r| r.ip.clone()).collect();
+        let names = self.lookup_many(&ips).await?;
+        attach_ptr(reports, &names);
+        Ok(())
+    }
+}
+
+async fn ptr_one(resolver: &TokioAsyncResolver, cache: &DiskCache, ip: &str) -> Vec<String> {
+    let Ok(addr) = ip.parse::<IpAddr>() else { return vec![] };
+    let key = DiskCache::key(&["ptr", ip]);
+    if let Some(v) = cache.get("ptr", &key) { return serde_json::from_value(v).unwrap_or_default(); }
+    let names: Vec<String> = match resolver.reverse_lookup(addr).await {
+        Ok(l) => {
+            let mut v: Vec<String> = Vec::new();
+            for n in l.iter() { let n = n.to_string().trim_end_matches('.').to_string(); if !v.contains(&n) { v.push(n); } }
+            v
+        }
+        Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => vec![],
+        Err(_) => return vec![],
+    };
+    cache.put("ptr", &key, &[ip], &json!(names)).ok();
+    names
+}
+
+pub fn attach_ptr(reports: &mut [HostReport], names: &BTreeMap<String, Vec<String>>) {
+    for r in reports.iter_mut() { if let Some(n) = names.get(&r.ip) { r.ptr = n.clone(); } }
+}
@@ -0,0 +1,73 @@
+//! Clasificador de rangos reservados/privados (IPv4 e IPv6): RFC 1918, loopback, link-local, CGNAT, multicast,
+//! documentación, etc. Por defecto esas IPs no se escanean; `--allow-private` lo permite (evaluaciones internas).
+use std::{net::IpAddr, sync::OnceLock};
+use crate::{models::ShodanMatch, targets::{ip_bits, parse_ip_range}};
+
+const RESERVED: &[(&str, &str)] = &[
+    ("0.0.0.0/8", "\"esta red\" (RFC 1122)"),
+    ("10.0.0.0/8", "privado (RFC 1918)"),
+    ("100.64.0.0/10", "CGNAT (RFC 6598)"),
+    ("127.0.0.0/8", "loopback"),
+    ("169.254.0.0/16", "link-local"),
+    ("172.16.0.0/12", "privado (RFC 1918)"),
+    ("192.0.0.0/24", "asignación IETF (RFC 6890)"),
+    ("192.0.2.0/24", "documentación (TEST-NET-1)"),
+    ("192.168.0.0/16", "privado (RFC 1918)"),
+    ("198.18.0.0/15", "benchmarking (RFC 2544)"),
+    ("198.51.100.0/24", "documentación (TEST-NET-2)"),
+    ("203.0.113.0/24", "documentación (TEST-NET-3)"),
+    ("224.0.0.0/4", "multicast"),
+    ("240.0.0.0/4", "reservado / broadcast"),
+    ("::/128", "no especificada"),
+    ("::1/128", "loopback"),
+    ("100::/64", "descarte (RFC 6666)"),
+    ("2001:db8::/32", "documentación"),
+    ("3fff::/20", "documentación (RFC 9637)"),
+    ("fc00::/7", "ULA privado (RFC 4193)"),
+    ("fe80::/10", "link-local"),
+    ("ff00::/8", "multicast"),
+];
+
+fn table() -> &'static [(u128, u128, bool, &'static str)] {
+    static TABLE: OnceLock<Vec<(u128, u128, bool, &'static str)>> = OnceLock::new();
+    TABLE.get_or_init(|| RESERVED.iter().map(|(cidr, label)| {
+        let (s, e, v6) = parse_ip_range(cidr).ok().flatten().expect("CIDR reservado inválido");
+        (s, e, v6, *label)
+    }).collect())
+}
+
+/// Tipo de rango reservado de la IP, o `None` si es pública. Las IPv4 mapeadas en IPv6 (`::ffff:a.b.c.d`) se evalúan como IPv4.
+pub fn reserved_range(ip: IpAddr) -> Option<&'static str> {
+    let ip = match ip { IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip), v4 => v4 };
+    let (bits, is_v6) = ip_bits(ip);
+    table().iter().find(|(s, e, v6, _)| *v6 == is_v6 && (*s..=*e).contains(&bits)).map(|(_, _, _, label)| *label)
+}
+
+/// Igual que `reserved_range` para una IP en texto (lo que no parsea no se considera reservado).
+pub fn reserved_label(ip: &str) -> Option<&'static str> { ip.parse().ok().and_then(reserved_range) }
+
+/// Quita los banners de IPs reservadas (salvo `allow_private`), avisando una vez por IP.
+pub fn drop_reserved_matches(matches: Vec<ShodanMatch>, allow_private: bool) -> Vec<ShodanMatch> {
+    if allow_private { return matches; }
+    let mut warned = std::collections::BTreeSet::new();
+    matches.into_iter().filter(|m| match reserved_label(&m.ip) {
+        Some(label) => { if warned.insert(m.ip.clone()) { eprintln!("[WARN] Shodan devolvió {} en rango reservado ({label}); se omite (usa --allow-private)", m.ip); } false }
+        None => true,
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_v4_and_v6() {
+        for (ip, label) in [("10.1.2.3", "privado (RFC 1918)"), ("172.31.255.255", "privado (RFC 1918)"), ("100.127.0.1", "CGNAT (RFC 6598)"), ("127.0.0.1", "loopback"), ("169.254.1.1", "link-local"), ("239.1.1.1", "multicast"), ("255.255.255.255", "reservado / broadcast"), ("198.51.100.7", "documentación (TEST-NET-2)"), ("fe80::1", "link-local"), ("fd12::1", "ULA privado (RFC 4193)"), ("2001:db8::5", "documentación"), ("::ffff:192.168.1.1", "privado (RFC 1918)")] {
+            assert_eq!(reserved_label(ip), Some(label), "{ip}");
+        }
+        for ip in ["8.8.8.8", "172.32.0.1", "100.128.0.1", "2800:150::1", "::ffff:1.1.1.1"] { assert_eq!(reserved_label(ip), None, "{ip}"); }
+        let m = |ip: &str| ShodanMatch { ip: ip.into(), port: 80, ..Default::default() };
+        assert_eq!(drop_reserved_matches(vec![m("10.0.0.1"), m("1.1.1.1"), m("10.0.0.1")], false).len(), 1);
+        assert_eq!(drop_reserved_matches(vec![m("10.0.0.1")], true).len(), 1);
+    }
+}
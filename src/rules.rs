@@ -1,11 +1,82 @@
 use serde::Deserialize;
 use std::fs;
 use anyhow::Result;
+use regex::Regex;
+use crate::models::{PortDetail, Protocol, ScriptResult};
 
 #[derive(Debug, Deserialize)]
 pub struct Rules { pub rules: Vec<Rule> }
 
+/// Una regla coincide con un puerto por número, por regex de servicio o por sus scripts NSE (`script` exacto y/o `script_output_regex`).
 #[derive(Debug, Deserialize)]
-pub struct Rule { pub name: String, #[serde(default)] pub ports: Vec<u16>, #[serde(default)] pub service_regex: Option<String>, #[serde(default)] pub cmds: Vec<String> }
+pub struct Rule { pub name: String, #[serde(default)] pub ports: Vec<RulePort>, #[serde(default)] pub service_regex: Option<String>, #[serde(default)] pub script: Option<String>, #[serde(default)] pub script_output_regex: Option<String>, #[serde(default)] pub cmds: Vec<String> }
+
+impl Rule {
+    /// ¿Aplica al puerto? (cualquiera de las condiciones definidas)
+    pub fn matches_port(&self, p: &PortDetail) -> bool {
+        let service_match = match (&self.service_regex, &p.service) { (Some(re), Some(svc)) => Regex::new(re).ok().map(|r| r.is_match(svc)).unwrap_or(false), _ => false };
+        self.ports.iter().any(|rp| rp.matches(p)) || service_match || self.matches_scripts(&p.scripts)
+    }
+
+    /// ¿Algún script cumple `script` (id exacto) y `script_output_regex` (sobre `output`)? Falso si la regla no define ninguno.
+    pub fn matches_scripts(&self, scripts: &[ScriptResult]) -> bool {
+        if self.script.is_none() && self.script_output_regex.is_none() { return false; }
+        let re = match self.script_output_regex.as_deref().map(Regex::new) { Some(Ok(re)) => Some(re), Some(Err(_)) => return false, None => None };
+        scripts.iter().any(|s| self.script.as_ref().is_none_or(|id| *id == s.id) && re.as_ref().is_none_or(|re| re.is_match(&s.output)))
+    }
+}
+
+/// Puerto de una regla: `22` o `tcp/22` (TCP), `udp/161` (UDP).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RawRulePort")]
+pub struct RulePort { pub protocol: Protocol, pub port: u16 }
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawRulePort { Num(u16), Text(String) }
+
+impl TryFrom<RawRulePort> for RulePort {
+    type Error = String;
+    fn try_from(raw: RawRulePort) -> Result<Self, String> {
+        match raw {
+            RawRulePort::Num(port) => Ok(RulePort { protocol: Protocol::Tcp, port }),
+            RawRulePort::Text(s) => {
+                let (protocol, port) = match s.split_once('/') { Some((proto, port)) => (proto.parse()?, port), None => (Protocol::Tcp, s.as_str()) };
+                Ok(RulePort { protocol, port: port.trim().parse().map_err(|_| format!("puerto inválido en regla '{s}' (usa 22, tcp/22 o udp/161)"))? })
+            }
+        }
+    }
+}
+
+impl RulePort {
+    pub fn matches(&self, p: &PortDetail) -> bool { self.port == p.port && self.protocol == p.protocol }
+}
 
 pub fn load_rules(path: &std::path::Path) -> Result<Rules> { let text = fs::read_to_string(path)?; let r: Rules = serde_yaml::from_str(&text)?; Ok(r) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rule_ports_with_protocol() {
+        let r: Rules = serde_yaml::from_str("rules:\n  - name: snmp\n    ports: [22, tcp/80, udp/161, '443']\n").unwrap();
+        let ports = &r.rules[0].ports;
+        assert_eq!(ports.iter().map(|p| (p.protocol, p.port)).collect::<Vec<_>>(), vec![(Protocol::Tcp, 22), (Protocol::Tcp, 80), (Protocol::Udp, 161), (Protocol::Tcp, 443)]);
+        let udp = PortDetail { port: 161, protocol: Protocol::Udp, state: "open".into(), service: Some("snmp".into()), ..Default::default() };
+        assert!(ports[2].matches(&udp) && !ports[0].matches(&udp));
+        assert!(serde_yaml::from_str::<Rules>("rules:\n  - name: x\n    ports: [sctp/9]\n").is_err());
+    }
+
+    #[test]
+    fn rules_match_nse_scripts() {
+        let r: Rules = serde_yaml::from_str("rules:\n  - name: anon\n    script: ftp-anon\n  - name: vuln\n    script_output_regex: '(?i)VULNERABLE'\n  - name: ambos\n    script: http-title\n    script_output_regex: Login\n").unwrap();
+        let script = |id: &str, output: &str| ScriptResult { id: id.into(), output: output.into(), ..Default::default() };
+        let ftp = PortDetail { port: 21, scripts: vec![script("ftp-anon", "Anonymous FTP login allowed")], ..Default::default() };
+        let web = PortDetail { port: 80, scripts: vec![script("http-title", "Inicio"), script("http-vuln-cve2017-5638", "State: VULNERABLE")], ..Default::default() };
+        let hits = |p: &PortDetail| r.rules.iter().filter(|x| x.matches_port(p)).map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(hits(&ftp), vec!["anon"]);
+        assert_eq!(hits(&web), vec!["vuln"]);
+        assert!(!r.rules[0].matches_port(&PortDetail { port: 21, ..Default::default() }));
+    }
+}
@@ -1,18 +1,142 @@
-use anyhow::{Result, Context};
+use anyhow::{Result, anyhow, bail};
 use indicatif::{ProgressBar, ProgressStyle};
-use tokio::{process::Command, io::{AsyncBufReadExt, BufReader}, sync::Semaphore};
-use std::sync::Arc;
-use crate::models::IpPorts;
-
-async fn rustscan_one(ip: &str, timeout_ms: u64, batch: u32) -> Result<Vec<u16>> {
-    let mut cmd = Command::new("rustscan"); cmd.arg("-a").arg(ip).arg("--timeout").arg(timeout_ms.to_string()).arg("--batch-size").arg(batch.to_string()).arg("--greppable").stdout(std::process::Stdio::piped());
-    let mut child = cmd.spawn().with_context(|| format!("No pude lanzar rustscan para {ip}"))?; let stdout = child.stdout.take().unwrap(); let mut reader = BufReader::new(stdout).lines();
-    let mut ports = Vec::<u16>::new(); while let Some(line) = reader.next_line().await? { if let Some((_ip, list)) = line.split_once("->") { for p in list.split(',') { if let Ok(n)= p.trim().parse::<u16>() { ports.push(n); } } } }
-    let _ = child.wait().await?; ports.sort_unstable(); ports.dedup(); Ok(ports)
+use tokio::{process::Command, sync::Semaphore, time::timeout};
+use std::{path::PathBuf, process::Stdio, sync::Arc, time::Duration};
+use crate::{args::{RustscanSettings, ScanOrder}, models::{DiscoveryStatus, IpPorts}, nmap::split_ports};
+
+/// Tiempo máximo por defecto de una ejecución de rustscan sobre un host (`--rs-host-timeout-secs`).
+pub const DEFAULT_RS_HOST_TIMEOUT_SECS: u64 = 900;
+
+const DEFAULT_RS_TIMEOUT_MS: u64 = 1500;
+const DEFAULT_RS_BATCH: u32 = 4500;
+
+/// Parámetros de cada ejecución de `rustscan`; con `out_dir` el stderr se guarda en `<out_dir>/<ip>/rustscan.stderr.txt`.
+#[derive(Debug, Clone)]
+pub struct RustscanOptions { pub settings: RustscanSettings, pub host_timeout: Duration, pub out_dir: Option<PathBuf> }
+
+impl Default for RustscanOptions {
+    fn default() -> Self { Self { settings: RustscanSettings::default(), host_timeout: Duration::from_secs(DEFAULT_RS_HOST_TIMEOUT_SECS), out_dir: None } }
+}
+
+impl RustscanSettings {
+    /// Cada valor ausente se toma de `fallback` (CLI sobre perfil); `ports`/`range` son una sola elección: si la CLI fija uno, el perfil no aporta ninguno.
+    pub fn or(self, fallback: &RustscanSettings) -> RustscanSettings {
+        let f = fallback.clone();
+        let (ports, range) = if self.ports.is_some() || self.range.is_some() { (self.ports, self.range) } else { (f.ports, f.range) };
+        RustscanSettings { ports, range, ulimit: self.ulimit.or(f.ulimit), tries: self.tries.or(f.tries), scan_order: self.scan_order.or(f.scan_order), timeout_ms: self.timeout_ms.or(f.timeout_ms), batch: self.batch.or(f.batch) }
+    }
+
+    /// Comprueba los valores antes de lanzar nada (mensajes con el nombre del flag).
+    pub fn validate(&self) -> Result<()> {
+        if self.ports.is_some() && self.range.is_some() { bail!("--rs-ports y --rs-range son excluyentes"); }
+        if let Some(p) = &self.ports { self.port_list(p)?; }
+        if let Some(r) = &self.range { parse_range(r)?; }
+        if self.timeout_ms == Some(0) { bail!("--rs-timeout-ms debe ser mayor que 0"); }
+        if self.batch == Some(0) { bail!("--rs-batch debe ser mayor que 0"); }
+        if let Some(t) = self.tries && !(1..=10).contains(&t) { bail!("--rs-tries debe estar entre 1 y 10"); }
+        let batch = self.batch.unwrap_or(DEFAULT_RS_BATCH);
+        if let Some(u) = self.ulimit && u < batch as u64 { bail!("--rs-ulimit ({u}) debe ser >= el batch ({batch}); si no RustScan reduce el batch o falla por descriptores"); }
+        Ok(())
+    }
+
+    fn port_list(&self, spec: &str) -> Result<Vec<u16>> {
+        let ports: Vec<u16> = split_ports(spec).map_err(|e| anyhow!("--rs-ports inválido '{spec}': {e}"))?;
+        if ports.is_empty() || ports.contains(&0) { bail!("--rs-ports inválido '{spec}': lista vacía o con puerto 0"); }
+        Ok(ports)
+    }
+
+    /// Argumentos de rustscan salvo `-a` y `--greppable`.
+    pub fn command_args(&self) -> Result<Vec<String>> {
+        let mut args = vec!["--timeout".into(), self.timeout_ms.unwrap_or(DEFAULT_RS_TIMEOUT_MS).to_string(), "--batch-size".into(), self.batch.unwrap_or(DEFAULT_RS_BATCH).to_string()];
+        if let Some(spec) = &self.ports {
+            let ports = self.port_list(spec)?;
+            // Un único tramo continuo va como --range (rustscan no expande rangos en --ports)
+            if ports.len() > 1 && (ports[ports.len() - 1] - ports[0]) as usize == ports.len() - 1 {
+                args.extend(["--range".into(), format!("{}-{}", ports[0], ports[ports.len() - 1])]);
+            } else {
+                args.extend(["--ports".into(), ports.iter().map(u16::to_string).collect::<Vec<_>>().join(",")]);
+            }
+        }
+        if let Some(r) = &self.range { let (a, b) = parse_range(r)?; args.extend(["--range".into(), format!("{a}-{b}")]); }
+        if let Some(u) = self.ulimit { args.extend(["--ulimit".into(), u.to_string()]); }
+        if let Some(t) = self.tries { args.extend(["--tries".into(), t.to_string()]); }
+        if let Some(o) = self.scan_order { args.extend(["--scan-order".into(), match o { ScanOrder::Serial => "serial", ScanOrder::Random => "random" }.into()]); }
+        Ok(args)
+    }
 }
 
-pub async fn rustscan_many_with_progress(ips: &Vec<String>, concurrency: usize, timeout_ms: u64, batch: u32) -> Result<Vec<IpPorts>> {
+fn parse_range(r: &str) -> Result<(u16, u16)> {
+    let err = || anyhow!("--rs-range inválido '{r}' (usa inicio-fin entre 1 y 65535)");
+    let (a, b) = r.split_once('-').ok_or_else(err)?;
+    let (a, b): (u16, u16) = (a.trim().parse().map_err(|_| err())?, b.trim().parse().map_err(|_| err())?);
+    if a == 0 || a > b { return Err(err()); }
+    Ok((a, b))
+}
+
+fn parse_greppable(stdout: &str) -> Vec<u16> {
+    let mut ports = Vec::<u16>::new(); for line in stdout.lines() { if let Some((_ip, list)) = line.split_once("->") { for p in list.trim().trim_matches(['[', ']']).split(',') { if let Ok(n) = p.trim().parse::<u16>() { ports.push(n); } } } }
+    ports.sort_unstable(); ports.dedup(); ports
+}
+
+async fn rustscan_one(ip: &str, opts: &RustscanOptions) -> IpPorts {
+    let extra = match opts.settings.command_args() { Ok(a) => a, Err(e) => return IpPorts::failed(ip.into(), DiscoveryStatus::Error, format!("{e:#}")) };
+    let mut cmd = Command::new("rustscan"); cmd.arg("-a").arg(ip).args(&extra).arg("--greppable").stdout(Stdio::piped()).stderr(Stdio::piped()).kill_on_drop(true);
+    let child = match cmd.spawn() { Ok(c) => c, Err(e) => return IpPorts::failed(ip.into(), DiscoveryStatus::SpawnFailed, format!("no pude lanzar rustscan: {e}")) };
+    // Al vencer el plazo se suelta el hijo y kill_on_drop lo termina
+    let output = match timeout(opts.host_timeout, child.wait_with_output()).await {
+        Err(_) => return IpPorts::failed(ip.into(), DiscoveryStatus::Timeout, format!("rustscan sin terminar tras {} s", opts.host_timeout.as_secs())),
+        Ok(Err(e)) => return IpPorts::failed(ip.into(), DiscoveryStatus::Error, format!("error esperando a rustscan: {e}")),
+        Ok(Ok(o)) => o,
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if let Some(dir) = &opts.out_dir && !stderr.trim().is_empty() {
+        let ip_dir = dir.join(ip); tokio::fs::create_dir_all(&ip_dir).await.ok();
+        tokio::fs::write(ip_dir.join("rustscan.stderr.txt"), stderr.as_bytes()).await.ok();
+    }
+    if !output.status.success() {
+        let last = stderr.lines().rev().find(|l| !l.trim().is_empty()).map(|l| format!(": {}", l.trim())).unwrap_or_default();
+        return IpPorts::failed(ip.into(), DiscoveryStatus::NonZeroExit, format!("rustscan terminó con {}{last}", output.status));
+    }
+    IpPorts::ok(ip.into(), parse_greppable(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// RustScan sobre `ips` (`concurrency` a la vez). Nunca falla por un host: cada resultado trae su `DiscoveryStatus`.
+pub async fn rustscan_many_with_progress(ips: &[String], concurrency: usize, opts: &RustscanOptions) -> Result<Vec<IpPorts>> {
     let total = ips.len() as u64; let pb = ProgressBar::new(total); pb.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:40.green/black} {pos}/{len} ({percent}%) RustScan")?.progress_chars("##-"));
-    let sem = Arc::new(Semaphore::new(concurrency)); let mut tasks = Vec::new(); for ip in ips { let ip = ip.clone(); let s = sem.clone(); let pb2 = pb.clone(); tasks.push(tokio::spawn(async move { let _permit = s.acquire_owned().await.unwrap(); let ports = rustscan_one(&ip, timeout_ms, batch).await.unwrap_or_default(); pb2.inc(1); IpPorts { ip, ports } })); }
+    let sem = Arc::new(Semaphore::new(concurrency)); let mut tasks = Vec::new(); for ip in ips { let ip = ip.clone(); let s = sem.clone(); let pb2 = pb.clone(); let opts = opts.clone(); tasks.push(tokio::spawn(async move { let _permit = s.acquire_owned().await.unwrap(); let r = rustscan_one(&ip, &opts).await; pb2.inc(1); r })); }
     let mut results = Vec::new(); for t in tasks { results.push(t.await?); } pb.finish_with_message("RustScan listo"); Ok(results)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_greppable_lines() {
+        assert_eq!(parse_greppable("192.0.2.10 -> [80,22,443]\n"), vec![22, 80, 443]);
+        assert_eq!(parse_greppable("192.0.2.10 -> 8080, 8443\nruido\n"), vec![8080, 8443]);
+        assert!(parse_greppable("Open 192.0.2.10:22\n").is_empty());
+    }
+
+    #[test]
+    fn settings_validation_and_args() {
+        let s = |f: fn(&mut RustscanSettings)| { let mut x = RustscanSettings::default(); f(&mut x); x };
+        assert_eq!(RustscanSettings::default().command_args().unwrap(), vec!["--timeout", "1500", "--batch-size", "4500"]);
+        let curated = s(|x| { x.ports = Some("443,22,8000-8002".into()); x.tries = Some(2); x.scan_order = Some(ScanOrder::Random); x.ulimit = Some(5000) });
+        curated.validate().unwrap();
+        assert_eq!(curated.command_args().unwrap()[4..], ["--ports", "22,443,8000,8001,8002", "--ulimit", "5000", "--tries", "2", "--scan-order", "random"]);
+        assert_eq!(s(|x| x.ports = Some("1-65535".into())).command_args().unwrap()[4..], ["--range", "1-65535"]);
+        for bad in [s(|x| { x.ports = Some("22".into()); x.range = Some("1-100".into()) }), s(|x| x.range = Some("100-1".into())), s(|x| x.range = Some("0-10".into())), s(|x| x.ports = Some("0,22".into())), s(|x| x.tries = Some(0)), s(|x| x.timeout_ms = Some(0)), s(|x| x.ulimit = Some(1000))] {
+            assert!(bad.validate().is_err(), "{bad:?}");
+        }
+        // CLI sobre perfil, campo a campo
+        let merged = s(|x| x.tries = Some(3)).or(&s(|x| { x.tries = Some(1); x.range = Some("1-1024".into()) }));
+        assert_eq!((merged.tries, merged.range.as_deref()), (Some(3), Some("1-1024")));
+        // La selección de puertos de la CLI sustituye entera a la del perfil
+        let over = s(|x| x.range = Some("1-1024".into())).or(&s(|x| x.ports = Some("22,443".into())));
+        over.validate().unwrap();
+        assert_eq!((over.ports, over.range.as_deref()), (None, Some("1-1024")));
+        let over = s(|x| x.ports = Some("80".into())).or(&s(|x| x.range = Some("1-100".into())));
+        assert_eq!((over.ports.as_deref(), over.range), (Some("80"), None));
+    }
+}
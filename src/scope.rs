@@ -0,0 +1,228 @@
+//! Alcance autorizado: allowlist / denylist (CIDR, rangos y dominios) y ventana de la autorización (engagement).
+//! Toda IP pasa por `Scope::filter_ips` antes de RustScan, Nmap y las reglas dinámicas; lo excluido se registra en
+//! `out/out_of_scope.txt`.
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Deserialize;
+use std::{collections::{BTreeMap, BTreeSet}, fs::OpenOptions, io::Write, net::IpAddr, path::{Path, PathBuf}, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
+use crate::{models::{HostReport, ShodanMatch}, targets::{ip_bits, parse_ip_range, Resolution}};
+
+#[derive(Debug, Clone, PartialEq)]
+enum ScopeRule {
+    /// Rango inclusivo de direcciones (`es_v6` separa familias)
+    Net { start: u128, end: u128, v6: bool },
+    /// Dominio y todos sus subdominios (`ejemplo.cl` cubre `www.ejemplo.cl`)
+    Domain(String),
+}
+
+impl ScopeRule {
+    fn parse(spec: &str) -> Result<Self> {
+        if let Some((start, end, v6)) = parse_ip_range(spec)? { return Ok(ScopeRule::Net { start, end, v6 }); }
+        let d = spec.trim_start_matches("*.").trim_start_matches('.').trim_end_matches('.').to_lowercase();
+        if d.is_empty() || !d.contains('.') || d.contains(['/', ':']) { bail!("ni red ni dominio válido"); }
+        Ok(ScopeRule::Domain(d))
+    }
+
+    fn matches_ip(&self, ip: IpAddr) -> bool {
+        let (bits, is_v6) = ip_bits(ip);
+        matches!(self, ScopeRule::Net { start, end, v6 } if *v6 == is_v6 && (*start..=*end).contains(&bits))
+    }
+
+    fn matches_name(&self, name: &str) -> bool {
+        let name = name.trim_end_matches('.').to_lowercase();
+        matches!(self, ScopeRule::Domain(d) if name == *d || name.ends_with(&format!(".{d}")))
+    }
+}
+
+/// Archivo de autorización (YAML): cliente y ventana `start`/`end` en RFC 3339.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Engagement {
+    #[serde(default)]
+    pub client: String,
+    #[serde(default)]
+    pub start: Option<String>,
+    #[serde(default)]
+    pub end: Option<String>,
+}
+
+impl Engagement {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("No pude leer el engagement {}", path.display()))?;
+        let e: Engagement = serde_yaml::from_str(&text).with_context(|| format!("Engagement inválido {}", path.display()))?;
+        // Valida las fechas al cargar, no recién al comprobar la ventana
+        for t in [&e.start, &e.end].into_iter().flatten() { parse_rfc3339(t).with_context(|| format!("fecha inválida '{t}' en {}", path.display()))?; }
+        Ok(e)
+    }
+
+    /// Error si `now` (segundos Unix) cae fuera de `[start, end]`.
+    pub fn check_window(&self, now: i64) -> Result<()> {
+        if let Some(s) = &self.start && now < parse_rfc3339(s)? { bail!("la autorización de '{}' empieza {s}; fuera de ventana", self.client); }
+        if let Some(e) = &self.end && now > parse_rfc3339(e)? { bail!("la autorización de '{}' terminó {e}; fuera de ventana", self.client); }
+        Ok(())
+    }
+}
+
+/// Segundos Unix de una fecha RFC 3339 (`2026-10-18T09:00:00Z`, `2026-10-18T09:00:00.5-03:00`).
+pub fn parse_rfc3339(s: &str) -> Result<i64> {
+    let bad = || anyhow!("fecha RFC 3339 inválida '{s}' (ej: 2026-10-18T09:00:00-03:00)");
+    let b = s.trim().as_bytes();
+    if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || !matches!(b[10], b'T' | b't' | b' ') || b[13] != b':' || b[16] != b':' { return Err(bad()); }
+    let num = |r: std::ops::Range<usize>| -> Result<i64> { std::str::from_utf8(&b[r]).ok().filter(|x| x.bytes().all(|c| c.is_ascii_digit())).and_then(|x| x.parse().ok()).ok_or_else(bad) };
+    let (y, mo, d, h, mi, sec) = (num(0..4)?, num(5..7)?, num(8..10)?, num(11..13)?, num(14..16)?, num(17..19)?);
+    let mut i = 19;
+    if b[i] == b'.' { i += 1; while i < b.len() && b[i].is_ascii_digit() { i += 1; } }
+    let offset = match b.get(i) {
+        Some(b'Z' | b'z') if i + 1 == b.len() => 0,
+        Some(sign @ (b'+' | b'-')) if i + 6 == b.len() && b[i + 3] == b':' => { let o = num(i + 1..i + 3)? * 3600 + num(i + 4..i + 6)? * 60; if *sign == b'-' { -o } else { o } }
+        _ => return Err(bad()),
+    };
+    let leap = (y % 4 == 0 && y % 100 != 0) || y % 400 == 0;
+    let dim = [31, if leap { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    if !(1..=12).contains(&mo) || d < 1 || d > dim[(mo - 1) as usize] || h > 23 || mi > 59 || sec > 60 { return Err(bad()); }
+    Ok(days_from_civil(y, mo, d) * 86_400 + h * 3600 + mi * 60 + sec - offset)
+}
+
+/// Días desde 1970-01-01 (algoritmo de H. Hinnant).
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+pub fn now_unix() -> i64 { SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0) }
+
+/// Listas de alcance + registro de exclusiones. Sin allowlist todo está permitido salvo lo denegado.
+#[derive(Debug, Default)]
+pub struct Scope {
+    allow: Vec<ScopeRule>,
+    deny: Vec<ScopeRule>,
+    pub engagement: Option<Engagement>,
+    log: Option<PathBuf>,
+    logged: Mutex<BTreeSet<String>>,
+}
+
+fn load_rules(path: &Path) -> Result<Vec<ScopeRule>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("No pude leer la lista de alcance {}", path.display()))?;
+    let mut rules = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let spec = line.split('#').next().unwrap_or("").trim();
+        if spec.is_empty() { continue; }
+        rules.push(ScopeRule::parse(spec).with_context(|| format!("{}:{}: '{spec}'", path.display(), n + 1))?);
+    }
+    Ok(rules)
+}
+
+impl Scope {
+    /// Sin archivos el alcance no restringe nada (comportamiento previo).
+    pub fn load(allowlist: Option<&Path>, denylist: Option<&Path>, engagement: Option<&Path>, log: &Path) -> Result<Self> {
+        Ok(Scope {
+            allow: allowlist.map(load_rules).transpose()?.unwrap_or_default(),
+            deny: denylist.map(load_rules).transpose()?.unwrap_or_default(),
+            engagement: engagement.map(Engagement::load).transpose()?,
+            log: Some(log.to_path_buf()),
+            logged: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    pub fn is_unrestricted(&self) -> bool { self.allow.is_empty() && self.deny.is_empty() }
+
+    /// Comprueba la ventana del engagement (si hay) contra la hora actual.
+    pub fn check_window(&self) -> Result<()> {
+        match &self.engagement { Some(e) => e.check_window(now_unix()), None => Ok(()) }
+    }
+
+    /// `None` si la IP está en alcance; si no, el motivo. `names` son hostnames conocidos de la IP (DNS, Shodan).
+    pub fn exclusion(&self, ip: &str, names: &[String]) -> Option<String> {
+        let Ok(addr) = ip.parse::<IpAddr>() else { return Some("no es una IP".into()) };
+        let hit = |rules: &[ScopeRule]| rules.iter().any(|r| r.matches_ip(addr) || names.iter().any(|n| r.matches_name(n)));
+        if hit(&self.deny) { return Some("denylist".into()); }
+        if !self.allow.is_empty() && !hit(&self.allow) { return Some("fuera de allowlist".into()); }
+        None
+    }
+
+    /// IPs en alcance (mismo orden); las excluidas se anotan una sola vez en el log.
+    pub fn filter_ips(&self, ips: &[String], names: &BTreeMap<String, Vec<String>>) -> Result<Vec<String>> {
+        if self.is_unrestricted() { return Ok(ips.to_vec()); }
+        let mut kept = Vec::new();
+        let mut excluded = Vec::new();
+        for ip in ips {
+            match self.exclusion(ip, names.get(ip).map(|v| v.as_slice()).unwrap_or(&[])) {
+                None => kept.push(ip.clone()),
+                Some(reason) => excluded.push((ip.clone(), reason)),
+            }
+        }
+        self.log_excluded(&excluded)?;
+        Ok(kept)
+    }
+
+    /// Descarta reportes fuera de alcance (última barrera antes de las reglas dinámicas).
+    pub fn retain_reports(&self, reports: &[HostReport]) -> Result<Vec<HostReport>> {
+        if self.is_unrestricted() { return Ok(reports.to_vec()); }
+        let names: BTreeMap<String, Vec<String>> = reports.iter().map(|r| (r.ip.clone(), r.target.split(',').map(String::from).collect())).collect();
+        let ips: Vec<String> = reports.iter().map(|r| r.ip.clone()).collect();
+        let kept: BTreeSet<String> = self.filter_ips(&ips, &names)?.into_iter().collect();
+        Ok(reports.iter().filter(|r| kept.contains(&r.ip)).cloned().collect())
+    }
+
+    fn log_excluded(&self, excluded: &[(String, String)]) -> Result<()> {
+        let Some(path) = &self.log else { return Ok(()) };
+        let mut logged = self.logged.lock().map_err(|_| anyhow!("log de alcance envenenado"))?;
+        let fresh: Vec<_> = excluded.iter().filter(|(ip, _)| logged.insert(ip.clone())).collect();
+        if fresh.is_empty() { return Ok(()); }
+        if let Some(parent) = path.parent() { std::fs::create_dir_all(parent)?; }
+        let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+        for (ip, reason) in &fresh { writeln!(f, "{ip}\t{reason}")?; }
+        println!("[SCOPE] {} host(s) fuera de alcance → {}", fresh.len(), path.display());
+        Ok(())
+    }
+}
+
+/// Añade a `names` los hostnames y dominios que Shodan asocia a cada IP (para reglas de dominio).
+pub fn add_match_names(names: &mut BTreeMap<String, Vec<String>>, matches: &[ShodanMatch]) {
+    for m in matches { let v = names.entry(m.ip.clone()).or_default(); for n in m.hostnames.iter().chain(&m.domains) { if !v.contains(n) { v.push(n.clone()); } } }
+}
+
+/// Añade a `names` los objetivos que se resolvieron a cada IP (hostnames del archivo de objetivos).
+pub fn add_target_names(names: &mut BTreeMap<String, Vec<String>>, resolved: &Resolution) {
+    for (ip, targets) in &resolved.names { let v = names.entry(ip.clone()).or_default(); for t in targets { if !v.contains(t) { v.push(t.clone()); } } }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(allow: &[&str], deny: &[&str]) -> Scope {
+        Scope { allow: allow.iter().map(|s| ScopeRule::parse(s).unwrap()).collect(), deny: deny.iter().map(|s| ScopeRule::parse(s).unwrap()).collect(), ..Default::default() }
+    }
+
+    #[test]
+    fn allow_and_deny_lists() {
+        let s = scope(&["192.0.2.0/24", "ejemplo.cl", "2001:db8::/64"], &["192.0.2.66", "intranet.ejemplo.cl"]);
+        assert_eq!(s.exclusion("192.0.2.10", &[]), None);
+        assert_eq!(s.exclusion("192.0.2.66", &[]).as_deref(), Some("denylist"));
+        assert_eq!(s.exclusion("198.51.100.7", &[]).as_deref(), Some("fuera de allowlist"));
+        assert_eq!(s.exclusion("198.51.100.7", &["www.ejemplo.cl".into()]), None);
+        assert_eq!(s.exclusion("198.51.100.8", &["vpn.intranet.ejemplo.cl".into()]).as_deref(), Some("denylist"));
+        assert_eq!(s.exclusion("2001:db8::5", &[]), None);
+        assert!(s.exclusion("noejemplo.cl", &[]).is_some());
+        assert!(!ScopeRule::Domain("ejemplo.cl".into()).matches_name("noejemplo.cl"));
+        let names = BTreeMap::new();
+        assert_eq!(scope(&[], &["10.0.0.0/8"]).filter_ips(&["10.1.2.3".into(), "192.0.2.1".into()], &names).unwrap(), vec!["192.0.2.1"]);
+        assert!(ScopeRule::parse("10.0.0.0/40").is_err());
+    }
+
+    #[test]
+    fn rfc3339_and_window() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z").unwrap(), 0);
+        assert_eq!(parse_rfc3339("2026-10-18T09:00:00-03:00").unwrap(), parse_rfc3339("2026-10-18T12:00:00Z").unwrap());
+        assert_eq!(parse_rfc3339("2024-02-29T00:00:00.250Z").unwrap(), 1_709_164_800);
+        for bad in ["2026-10-18", "2026-13-01T00:00:00Z", "2025-02-29T00:00:00Z", "2026-10-18T09:00:00", "2026-10-18T09:00:00+0300"] { assert!(parse_rfc3339(bad).is_err(), "{bad}"); }
+        let e = Engagement { client: "Muni".into(), start: Some("2026-10-01T00:00:00Z".into()), end: Some("2026-10-31T23:59:59Z".into()) };
+        let now = parse_rfc3339("2026-10-18T12:00:00Z").unwrap();
+        assert!(e.check_window(now).is_ok());
+        assert!(e.check_window(parse_rfc3339("2026-11-01T00:00:00Z").unwrap()).is_err());
+        assert!(e.check_window(parse_rfc3339("2026-09-30T23:59:59Z").unwrap()).is_err());
+    }
+}
@@ -1,85 +1,383 @@
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
 use indicatif::{ProgressBar, ProgressStyle};
-use reqwest::{Client, StatusCode};
+use reqwest::{Client, Response, StatusCode};
 use serde_json::Value;
-use std::{collections::BTreeSet, path::Path, fs, time::Duration};
-use tokio::time::sleep;
+use std::{collections::{BTreeMap, BTreeSet}, path::Path, fs, sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+use crate::cache::DiskCache;
+use crate::dork::{self, DorkErrorPolicy, DorkExpr};
+use crate::http::{get_with_retry, RateLimiter, RetryPolicy};
+use crate::config::DEFAULT_SHODAN_BASE_URL;
+use crate::vocab::{or_fields, Vocabulary};
+use crate::models::{ApiInfo, FacetValue, HostReport, ShodanCount, ShodanHost, ShodanLocation, ShodanMatch};
 
 pub fn http_client() -> Result<Client> { Ok(Client::builder().timeout(Duration::from_secs(30)).build()?) }
 
-pub fn build_dork_from_keywords(keywords_csv: &str) -> String {
-    // normaliza/dedup
-    let mut kws: Vec<String> = keywords_csv
-        .split(',')
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
-        .map(|s| s.replace('"', "").replace('\'', ""))
-        .map(|s| s.to_lowercase())
-        .collect();
-    kws.dedup();
-
-    let mut terms: Vec<String> = Vec::new();
-
-    for kw in kws {
-        match kw.as_str() {
-            // país
-            "chile" | "cl" => { terms.push("country:CL".into()); }
-            // TLD puro tipo .cl (no contiene otro punto después)
-            t if t.starts_with('.') && !t[1..].contains('.') => {
-                let tld = t; // conserva el . inicial
-                // Búsqueda de substring segura en varias vistas (sin disparar error 500)
-                terms.push(format!("(ssl:\"{tld}\" OR http.title:\"{tld}\" OR http.html:\"{tld}\")"));
-            }
-            // Dominio/FQDN (contiene punto interno o empieza con punto pero con más niveles)
-            t if t.starts_with('.') || t.contains('.') => {
-                let v = t.trim_start_matches('.');
-                if !v.is_empty() {
-                    terms.push(format!("(hostname:\"{v}\" OR ssl.cert.subject.cn:\"{v}\" OR ssl.cert.issuer.cn:\"{v}\")"));
-                }
-            }
+/// Presupuesto de créditos de consulta: cada página de `/shodan/host/search` cuesta 1.
+#[derive(Debug, Default)]
+pub struct CreditBudget { limit: Option<usize>, spent: AtomicUsize }
 
-            // términos municipales
-            "muni" | "municipalidad" | "municipio" | "ilustre" => terms.push(
-                "(http.title:\"Municipalidad\" OR http.html:\"Municipalidad\" OR org:\"Municipalidad\")".into(),
-            ),
+impl CreditBudget {
+    pub fn new(limit: Option<usize>) -> Self { Self { limit, spent: AtomicUsize::new(0) } }
+    /// Reduce el límite (p.ej. a los créditos que quedan en la cuenta); nunca lo amplía.
+    pub fn cap(&mut self, n: usize) { self.limit = Some(self.limit.map_or(n, |l| l.min(n))); }
+    /// Reserva `n` créditos; `false` si el presupuesto no alcanza.
+    pub fn try_spend(&self, n: usize) -> bool {
+        self.spent.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |s| match self.limit { Some(l) if s + n > l => None, _ => Some(s + n) }).is_ok()
+    }
+    pub fn spent(&self) -> usize { self.spent.load(Ordering::SeqCst) }
+    pub fn limit(&self) -> Option<usize> { self.limit }
+    /// Devuelve créditos reservados para una petición que no se cobró (p.ej. página saltada por 429).
+    pub fn refund(&self, n: usize) { let _ = self.spent.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |s| Some(s.saturating_sub(n))); }
+    pub fn exhausted(&self) -> bool { self.limit.is_some_and(|l| self.spent() >= l) }
+}
 
-            // ISO 2 letras
-            t if t.len() == 2 && t.chars().all(|c| c.is_ascii_alphabetic()) => {
-                terms.push(format!("country:{}", t.to_uppercase()));
-            }
+/// Cliente HTTP + API key + presupuesto + limitador/reintentos compartidos por todas las llamadas a Shodan.
+#[derive(Debug)]
+pub struct ShodanApi { pub client: Client, pub key: String, pub base_url: String, pub budget: CreditBudget, pub limiter: RateLimiter, pub retry: RetryPolicy, pub cache: DiskCache }
+
+impl ShodanApi {
+    /// `rate_per_sec` peticiones por segundo (Shodan admite 1/s en la mayoría de planes).
+    pub fn new(key: &str, max_credits: Option<usize>, rate_per_sec: f64, retry: RetryPolicy) -> Result<Self> {
+        Ok(Self { client: http_client()?, key: key.to_string(), base_url: DEFAULT_SHODAN_BASE_URL.into(), budget: CreditBudget::new(max_credits), limiter: RateLimiter::new(rate_per_sec, 1), retry, cache: DiskCache::disabled() })
+    }
+
+    /// Apunta el cliente a otra URL base (proxy de caché interno o servidor mock local).
+    pub fn with_base_url(mut self, base_url: &str) -> Self { self.base_url = base_url.trim_end_matches('/').to_string(); self }
+
+    /// Activa la caché en disco de páginas de búsqueda y fichas de host.
+    pub fn with_cache(mut self, cache: DiskCache) -> Self { self.cache = cache; self }
+
+    /// `{base_url}{path}?key=...&k=v` con los parámetros codificados.
+    pub fn url(&self, path: &str, params: &[(&str, &str)]) -> String {
+        let mut url = format!("{}{}?key={}", self.base_url, path, urlencoding::encode(&self.key));
+        for (k, v) in params { url.push_str(&format!("&{k}={}", urlencoding::encode(v))); }
+        url
+    }
+
+    /// GET a través del limitador y la política de reintentos.
+    pub async fn get(&self, url: &str) -> Result<Response> { get_with_retry(&self.client, url, &self.limiter, &self.retry).await }
+}
 
-            // término libre
-            other => terms.push(format!(
-                "(http.title:\"{o}\" OR http.html:\"{o}\" OR org:\"{o}\" OR product:\"{o}\")",
-                o = other
-            )),
+/// Consulta `/api-info` (plan y créditos restantes).
+pub async fn shodan_api_info(api: &ShodanApi) -> Result<ApiInfo> {
+    let url = api.url("/api-info", &[]);
+    let resp = api.get(&url).await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let text = resp.text().await.unwrap_or_default();
+        return Err(anyhow!("/api-info fallo ({}): {}", status, text));
+    }
+    Ok(resp.json().await?)
+}
+
+/// Créditos de consulta que Shodan cobra por una página de `/shodan/host/search`: gratis solo la primera de una búsqueda sin filtros.
+pub fn search_page_cost(query: &str, page: usize) -> usize { if page == 1 && !query.contains(':') { 0 } else { 1 } }
+
+/// Muestra los créditos de la cuenta al inicio y ajusta el presupuesto a los créditos de consulta restantes.
+pub async fn account_guard(api: &mut ShodanApi) {
+    match shodan_api_info(api).await {
+        Ok(info) => {
+            println!("[*] Cuenta Shodan: plan {} | créditos consulta {} | créditos escaneo {}", info.plan.as_deref().unwrap_or("?"), info.query_credits, info.scan_credits);
+            api.budget.cap(info.query_credits.max(0) as usize);
+            if info.query_credits <= 0 { eprintln!("[WARN] Sin créditos de consulta: solo se descargará la primera página de búsquedas sin filtros (/count y /shodan/host no gastan créditos)"); }
         }
+        Err(e) => eprintln!("[WARN] No pude leer /api-info: {e}"),
     }
+    if let Some(l) = api.budget.limit() { println!("[*] Presupuesto de créditos de consulta: {l}"); }
+}
+
+/// Dork con el vocabulario embebido (`vocab/default.yaml`); si la expresión no parsea usa el fallback.
+pub fn build_dork_from_keywords(keywords_csv: &str) -> String {
+    let vocab = Vocabulary::builtin();
+    build_dork_with_vocab(keywords_csv, &vocab).unwrap_or(vocab.fallback)
+}
 
-    if terms.is_empty() {
-        return "country:CL".into();
+/// Cláusula Shodan para una keyword normalizada (minúsculas, sin comillas).
+pub fn expand_keyword(kw: &str, vocab: &Vocabulary) -> Option<String> {
+    // entradas del vocabulario (país, términos sectoriales, ...)
+    if let Some(m) = vocab.lookup(kw) { return Some(m.render(kw)); }
+    match kw {
+        // TLD puro tipo .cl (no contiene otro punto después)
+        t if t.starts_with('.') && !t[1..].contains('.') => {
+            // Búsqueda de substring segura en varias vistas (sin disparar error 500); conserva el . inicial
+            Some(or_fields(&vocab.tld_fields, t))
+        }
+        // Dominio/FQDN (contiene punto interno o empieza con punto pero con más niveles)
+        t if t.starts_with('.') || t.contains('.') => {
+            let v = t.trim_start_matches('.');
+            if v.is_empty() { None } else { Some(or_fields(&vocab.domain_fields, v)) }
+        }
+        // ISO 2 letras
+        t if vocab.iso_country && t.len() == 2 && t.chars().all(|c| c.is_ascii_alphabetic()) => Some(format!("country:{}", t.to_uppercase())),
+        // término libre
+        other => Some(or_fields(&vocab.free_fields, other)),
     }
-    // Dedup de cláusulas equivalentes para evitar (country:CL) AND (country:CL)
-    use std::collections::BTreeSet;
-    let mut set = BTreeSet::new();
-    let mut ordered = Vec::new();
-    for t in terms { if set.insert(t.clone()) { ordered.push(t); } }
-    ordered.into_iter().map(|t| format!("({t})")).collect::<Vec<_>>().join(" AND ")
 }
 
-pub async fn shodan_precheck_count(client: &Client, key: &str, query: &str) -> Result<()> {
-    let url = format!(
-        "https://api.shodan.io/shodan/host/count?key={}&query={}",
-        urlencoding::encode(key),
-        urlencoding::encode(query)
-    );
-    let resp = client.get(&url).send().await?;
+/// Dork para una expresión de keywords (ver `dork`): una lista plana `a,b,c` da `(a) AND (b) AND (c)`.
+pub fn build_dork_with_vocab(keywords: &str, vocab: &Vocabulary) -> Result<String> {
+    let expr = dork::parse(keywords).with_context(|| format!("Expresión de keywords inválida: '{keywords}'"))?;
+    Ok(dork::render(&expr, vocab))
+}
+
+/// Facets que se piden por defecto en `stats` y en el banner pre-vuelo de `full`.
+pub const DEFAULT_FACETS: &str = "port,org,product,country,asn";
+
+/// Normaliza "port,org" -> "port:10,org:10" (respeta tamaños explícitos como "org:5").
+pub fn facets_param(facets_csv: &str, size: usize) -> String {
+    facets_csv.split(',').map(|f| f.trim()).filter(|f| !f.is_empty()).map(|f| if f.contains(':') { f.to_string() } else { format!("{f}:{size}") }).collect::<Vec<_>>().join(",")
+}
+
+/// Consulta `/shodan/host/count` (no consume créditos de consulta) con los facets indicados ("" = sin facets).
+pub async fn shodan_count(api: &ShodanApi, query: &str, facets: &str) -> Result<ShodanCount> {
+    let mut params = vec![("query", query)];
+    if !facets.is_empty() { params.push(("facets", facets)); }
+    let url = api.url("/shodan/host/count", &params);
+    let resp = api.get(&url).await?;
     let status = resp.status();
     if !status.is_success() {
         let text = resp.text().await.unwrap_or_default();
         return Err(anyhow!("Precheck /count fallo ({}): {}", status, text));
     }
-    Ok(())
+    let v: Value = resp.json().await?;
+    Ok(parse_count(query, &v))
+}
+
+fn parse_count(query: &str, v: &Value) -> ShodanCount {
+    let mut out = ShodanCount { query: query.to_string(), total: v.get("total").and_then(|x| x.as_u64()).unwrap_or(0), ..Default::default() };
+    if let Some(obj) = v.get("facets").and_then(|f| f.as_object()) {
+        for (name, arr) in obj {
+            let values = arr.as_array().map(|a| a.iter().map(|x| FacetValue {
+                // `value` llega como número (port, asn en algunos casos) o string
+                value: match x.get("value") { Some(Value::String(s)) => s.clone(), Some(other) => other.to_string(), None => String::new() },
+                count: x.get("count").and_then(|c| c.as_u64()).unwrap_or(0),
+            }).collect()).unwrap_or_default();
+            out.facets.insert(name.clone(), values);
+        }
+    }
+    out
+}
+
+pub async fn shodan_precheck_count(api: &ShodanApi, query: &str) -> Result<()> {
+    shodan_count(api, query, "").await.map(|_| ())
+}
+
+/// Dork que finalmente se usa, sus estadísticas de `/count` y los cambios aplicados al original (para el resumen).
+#[derive(Debug, Clone)]
+pub struct CheckedDork { pub query: String, pub stats: ShodanCount, pub changes: Vec<String> }
+
+/// Valida el dork de `expr` con `/count` y, si Shodan lo rechaza, aplica `policy`.
+pub async fn check_dork(api: &ShodanApi, expr: &DorkExpr, vocab: &Vocabulary, policy: &DorkErrorPolicy, facets: &str) -> Result<CheckedDork> {
+    let query = dork::render(expr, vocab);
+    let err = match shodan_count(api, &query, facets).await {
+        Ok(stats) => return Ok(CheckedDork { query, stats, changes: vec![] }),
+        Err(e) => e,
+    };
+    eprintln!("[WARN] Dork inválido (/count): {err}");
+    let mut changes = vec![format!("Dork original rechazado por /count: {query} ({err})")];
+    match policy {
+        DorkErrorPolicy::Abort => Err(err.context("Dork rechazado por Shodan; usa --on-dork-error fallback:<query> o simplify para continuar")),
+        DorkErrorPolicy::Fallback(fallback) => {
+            let stats = shodan_count(api, fallback, facets).await.with_context(|| format!("El dork de fallback '{fallback}' también falló"))?;
+            changes.push(format!("Reemplazado por fallback: {fallback}"));
+            Ok(CheckedDork { query: fallback.clone(), stats, changes })
+        }
+        DorkErrorPolicy::Simplify => {
+            for (field, q) in dork::simplifications(expr, vocab) {
+                match shodan_count(api, &q, facets).await {
+                    Ok(stats) => { changes.push(format!("Sin {field}: {q} (aceptado)")); return Ok(CheckedDork { query: q, stats, changes }); }
+                    Err(e) => changes.push(format!("Sin {field}: {q} (rechazado: {e})")),
+                }
+            }
+            for c in &changes { eprintln!("[WARN] {c}"); }
+            Err(anyhow!("Ninguna simplificación del dork fue aceptada por /count"))
+        }
+    }
+}
+
+/// Con `keep_banners` (puertos tomados de Shodan) alcanzar `limit` solo deja de admitir IPs nuevas: se siguen las páginas
+/// para completar los banners de las ya admitidas.
+/// No escribe archivos: quien llama exporta con `export_collected` lo que acumuló (p. ej. varias rondas del modo adaptativo).
+pub async fn shodan_collect(api: &ShodanApi, query: &str, limit: usize, pages: usize, keep_banners: bool, debug: bool) -> Result<Vec<ShodanMatch>> {
+    let mut ips: BTreeSet<String> = BTreeSet::new();
+    let mut matches: Vec<ShodanMatch> = Vec::new();
+    let max_pages = pages.clamp(1, 100);
+    let pb = ProgressBar::new(max_pages as u64);
+    pb.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} pág {pos}/{len} Shodan")?.progress_chars("##-"));
+    for page in 1..=max_pages { if ips.len() >= limit && !keep_banners { if debug { eprintln!("[DEBUG] Límite de IPs alcanzado antes de página {page}"); } pb.finish_with_message("Shodan listo"); break; }
+        let page_s = page.to_string();
+        let cache_key = DiskCache::key(&[&api.base_url, query, &page_s]);
+        let v: Value = if let Some(v) = api.cache.get("search", &cache_key) {
+            // Página en caché: no cuesta créditos ni peticiones
+            if debug { eprintln!("[DEBUG] Página {page} desde caché ({cache_key})"); }
+            v
+        } else {
+            let cost = search_page_cost(query, page);
+            if !api.budget.try_spend(cost) { pb.println(format!("[BUDGET] Presupuesto de créditos agotado ({}/{}); detengo la paginación en la página {page}", api.budget.spent(), api.budget.limit().unwrap_or_default())); break; }
+            // Sin minify: queremos ssl/http/vulns completos para el modelo ShodanMatch
+            let url = api.url("/shodan/host/search", &[("query", query), ("page", &page_s), ("minify", "false")]);
+            if debug { eprintln!("[DEBUG] GET {url}"); }
+            let response = api.get(&url).await?;
+            let status = response.status();
+            if debug { eprintln!("[DEBUG] Página {page} status={status}"); }
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                // 429 tras agotar reintentos: Shodan no cobra la página, se devuelve el crédito
+                eprintln!("[!] 429 persistente – salto de página {page}");
+                api.budget.refund(cost);
+                pb.inc(1);
+                continue;
+            } else if !status.is_success() {
+                let text = response.text().await.unwrap_or_default();
+                return Err(anyhow!("Shodan HTTP {}: {}", status, text));
+            }
+            let v: Value = response.json().await?;
+            api.cache.put("search", &cache_key, &[query, &page_s], &v)?;
+            v
+        };
+        if debug { eprintln!("[DEBUG] Página {page} matches parseados ok"); }
+        if !collect_matches(&mut ips, &mut matches, &v, limit, keep_banners) { if debug { eprintln!("[DEBUG] Límite alcanzado dentro de página {page}"); } pb.finish_with_message("Shodan listo"); break; }
+        pb.inc(1); }
+    pb.finish_and_clear();
+    Ok(matches)
+}
+
+/// Acumula los banners de una página. Devuelve `false` si la página venía vacía o se alcanzó `limit` IPs únicas;
+/// con `keep_banners` pasado el límite solo se guardan banners de IPs ya admitidas y se sigue paginando.
+fn collect_matches(ips: &mut BTreeSet<String>, matches: &mut Vec<ShodanMatch>, v: &Value, limit: usize, keep_banners: bool) -> bool {
+    let empty = Vec::new();
+    let arr = v.get("matches").and_then(|x| x.as_array()).unwrap_or(&empty);
+    if arr.is_empty(){ return false; }
+    for m in arr { if let Some(sm) = parse_match(m) {
+        if ips.len() >= limit && !ips.contains(&sm.ip) { continue; }
+        ips.insert(sm.ip.clone()); matches.push(sm);
+        if ips.len() >= limit && !keep_banners { return false; }
+    } }
+    true
+}
+
+fn opt_str(v: &Value, key: &str) -> Option<String> { v.get(key).and_then(|x| x.as_str()).map(|s| s.to_string()).filter(|s| !s.is_empty()) }
+
+fn str_list(v: Option<&Value>) -> Vec<String> { v.and_then(|x| x.as_array()).map(|a| a.iter().filter_map(|x| x.as_str().map(|s| s.to_string())).collect()).unwrap_or_default() }
+
+/// Aplana `ssl.cert.subject` / `ssl.cert.issuer` ({"CN": "...", "O": "..."}) a "CN=..., O=...".
+fn dn_string(v: Option<&Value>) -> Option<String> {
+    let obj = v?.as_object()?;
+    let parts: Vec<String> = obj.iter().filter_map(|(k, x)| x.as_str().map(|s| format!("{k}={s}"))).collect();
+    if parts.is_empty() { None } else { Some(parts.join(", ")) }
+}
+
+/// Convierte un elemento de `matches` en `ShodanMatch`; `None` si no trae `ip_str`.
+pub fn parse_match(m: &Value) -> Option<ShodanMatch> {
+    let ip = opt_str(m, "ip_str")?;
+    let port = m.get("port").and_then(|x| x.as_u64()).and_then(|p| u16::try_from(p).ok()).unwrap_or(0);
+    let location = m.get("location").filter(|l| l.is_object()).map(|l| ShodanLocation {
+        country_code: opt_str(l, "country_code"),
+        country_name: opt_str(l, "country_name"),
+        city: opt_str(l, "city"),
+        latitude: l.get("latitude").and_then(|x| x.as_f64()),
+        longitude: l.get("longitude").and_then(|x| x.as_f64()),
+    });
+    let cert = m.get("ssl").and_then(|s| s.get("cert"));
+    // En /search `vulns` es un objeto {CVE: {...}}; en /host puede venir como lista
+    let vulns = match m.get("vulns") {
+        Some(Value::Object(o)) => o.keys().cloned().collect(),
+        other => str_list(other),
+    };
+    Some(ShodanMatch {
+        ip,
+        port,
+        transport: opt_str(m, "transport"),
+        product: opt_str(m, "product"),
+        version: opt_str(m, "version"),
+        hostnames: str_list(m.get("hostnames")),
+        domains: str_list(m.get("domains")),
+        org: opt_str(m, "org"),
+        asn: opt_str(m, "asn"),
+        isp: opt_str(m, "isp"),
+        location,
+        ssl_subject: dn_string(cert.and_then(|c| c.get("subject"))),
+        ssl_issuer: dn_string(cert.and_then(|c| c.get("issuer"))),
+        http_title: m.get("http").and_then(|h| opt_str(h, "title")),
+        vulns,
+        timestamp: opt_str(m, "timestamp"),
+    })
+}
+
+/// IPs únicas (ordenadas) presentes en un conjunto de banners.
+pub fn unique_ips(matches: &[ShodanMatch]) -> Vec<String> { matches.iter().map(|m| m.ip.clone()).collect::<BTreeSet<_>>().into_iter().collect() }
+
+/// Escribe `ips.txt` y `shodan_matches.jsonl` en `out`.
+pub fn export_collected(out: &Path, ips: &[String], matches: &[ShodanMatch]) -> Result<()> {
+    fs::write(out.join("ips.txt"), ips.join("\n"))?;
+    write_matches_jsonl(&out.join("shodan_matches.jsonl"), matches)
+}
+
+pub fn write_matches_jsonl(path: &Path, matches: &[ShodanMatch]) -> Result<()> {
+    let mut out = String::new();
+    for m in matches { out.push_str(&serde_json::to_string(m)?); out.push('\n'); }
+    fs::write(path, out)?; Ok(())
+}
+
+/// Adjunta a cada reporte los banners Shodan de su IP (ordenados por puerto).
+pub fn attach_shodan_matches(reports: &mut [HostReport], matches: &[ShodanMatch]) {
+    for r in reports.iter_mut() {
+        let mut v: Vec<ShodanMatch> = matches.iter().filter(|m| m.ip == r.ip).cloned().collect();
+        v.sort_by_key(|m| m.port);
+        v.dedup_by(|a, b| a.port == b.port && a.transport == b.transport);
+        r.shodan = v;
+    }
+}
+
+/// Convierte la respuesta de `/shodan/host/{ip}` en `ShodanHost`.
+pub fn parse_host(v: &Value) -> Option<ShodanHost> {
+    let ip = opt_str(v, "ip_str")?;
+    let mut ports: Vec<u16> = v.get("ports").and_then(|x| x.as_array()).map(|a| a.iter().filter_map(|p| p.as_u64()).filter_map(|p| u16::try_from(p).ok()).collect()).unwrap_or_default();
+    ports.sort_unstable(); ports.dedup();
+    let banners = v.get("data").and_then(|x| x.as_array()).map(|a| a.iter().filter_map(parse_match).collect()).unwrap_or_default();
+    let vulns = match v.get("vulns") { Some(Value::Object(o)) => o.keys().cloned().collect(), other => str_list(other) };
+    Some(ShodanHost { ip, ports, hostnames: str_list(v.get("hostnames")), vulns, org: opt_str(v, "org"), os: opt_str(v, "os"), last_update: opt_str(v, "last_update"), banners })
+}
+
+/// Consulta `/shodan/host/{ip}` usando la caché de la API (espacio `host`, incluye respuestas 404).
+/// `Ok(None)` si Shodan no tiene información de la IP.
+pub async fn shodan_host(api: &ShodanApi, ip: &str, history: bool) -> Result<Option<ShodanHost>> {
+    let history_s = if history { "true" } else { "false" };
+    let cache_key = DiskCache::key(&[&api.base_url, ip, history_s]);
+    let v: Value = if let Some(v) = api.cache.get("host", &cache_key) { v } else {
+        let params: &[(&str, &str)] = if history { &[("history", "true")] } else { &[] };
+        let url = api.url(&format!("/shodan/host/{ip}"), params);
+        let resp = api.get(&url).await?;
+        let status = resp.status();
+        let v: Value = if status == StatusCode::NOT_FOUND { Value::Null } else if status.is_success() { resp.json().await? } else {
+            let text = resp.text().await.unwrap_or_default();
+            return Err(anyhow!("Shodan host {} HTTP {}: {}", ip, status, text));
+        };
+        api.cache.put("host", &cache_key, &[ip, history_s], &v)?;
+        v
+    };
+    Ok(parse_host(&v))
+}
+
+/// Enriquece una lista de IPs con `/shodan/host/{ip}`; los fallos por IP se avisan y no detienen el lote.
+pub async fn enrich_hosts(api: &ShodanApi, ips: &[String], history: bool) -> Result<BTreeMap<String, ShodanHost>> {
+    let pb = ProgressBar::new(ips.len() as u64);
+    pb.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} Shodan host")?.progress_chars("##-"));
+    let mut out = BTreeMap::new();
+    for ip in ips {
+        match shodan_host(api, ip, history).await {
+            Ok(Some(h)) => { out.insert(ip.clone(), h); }
+            Ok(None) => {}
+            Err(e) => pb.println(format!("[WARN] Enriquecimiento {ip}: {e}")),
+        }
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+    Ok(out)
+}
+
+/// Adjunta a cada reporte su ficha `/shodan/host/{ip}` si existe.
+pub fn attach_shodan_hosts(reports: &mut [HostReport], hosts: &BTreeMap<String, ShodanHost>) {
+    for r in reports.iter_mut() { if let Some(h) = hosts.get(&r.ip) { r.shodan_host = Some(h.clone()); } }
 }
 
 #[cfg(test)]
@@ -109,54 +407,75 @@ mod tests {
         // Debe haber solo una aparición de country:CL
         assert!(q.matches("country:CL").count() == 1, "Dork duplicado: {q}");
     }
-}
 
-pub async fn shodan_collect(client: &Client, key: &str, query: &str, limit: usize, pages: usize, out: &Path, debug: bool) -> Result<Vec<String>> {
-    let mut ips: BTreeSet<String> = BTreeSet::new();
-    let max_pages = pages.max(1).min(100);
-    let pb = ProgressBar::new(max_pages as u64);
-    pb.set_style(ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} pág {pos}/{len} Shodan")?.progress_chars("##-"));
-    for page in 1..=max_pages { if ips.len() >= limit { if debug { eprintln!("[DEBUG] Límite de IPs alcanzado antes de página {page}"); } pb.finish_with_message("Shodan listo"); break; }
-        let url = format!("https://api.shodan.io/shodan/host/search?key={}&query={}&page={}&minify=true", urlencoding::encode(key), urlencoding::encode(query), page);
-        if debug { eprintln!("[DEBUG] GET {url}"); }
-        let mut need_rate_retry = false;
-        let response = client.get(&url).send().await?;
-        let status = response.status();
-        if debug { eprintln!("[DEBUG] Página {page} status={status}"); }
-        if status == StatusCode::TOO_MANY_REQUESTS { need_rate_retry = true; }
-        if need_rate_retry {
-            eprintln!("[!] 429 en página {page} – backoff 2s");
-            sleep(Duration::from_secs(2)).await;
-            let response = client.get(&url).send().await?;
-            let status2 = response.status();
-            if debug { eprintln!("[DEBUG] Retry página {page} status={status2}"); }
-            if status2 == StatusCode::TOO_MANY_REQUESTS {
-                eprintln!("[!] 429 persistente – salto de página");
-                pb.inc(1);
-                continue;
-            } else if !status2.is_success() {
-                let text = response.text().await.unwrap_or_default();
-                return Err(anyhow!("Shodan HTTP {}: {}", status2, text));
-            } else {
-                let v: Value = response.json().await?;
-                if debug { eprintln!("[DEBUG] Página {page} matches parseados retry"); }
-                if !collect_ips_from_matches(&mut ips, &v, limit) { if debug { eprintln!("[DEBUG] Límite alcanzado dentro de página retry {page}"); } pb.finish_with_message("Shodan listo"); break; }
-            }
-        } else if !status.is_success() {
-            let text = response.text().await.unwrap_or_default();
-            return Err(anyhow!("Shodan HTTP {}: {}", status, text));
-        } else {
-            let v: Value = response.json().await?;
-            if debug { eprintln!("[DEBUG] Página {page} matches parseados ok"); }
-            if !collect_ips_from_matches(&mut ips, &v, limit) { if debug { eprintln!("[DEBUG] Límite alcanzado dentro de página {page}"); } pb.finish_with_message("Shodan listo"); break; }
-        }
-        pb.inc(1); sleep(Duration::from_millis(1100)).await; }
-    pb.finish_and_clear(); let list_path = out.join("ips.txt"); fs::write(&list_path, ips.iter().cloned().collect::<Vec<_>>().join("\n"))?; Ok(ips.into_iter().collect()) }
+    #[test]
+    fn builtin_vocab_keeps_legacy_dork() {
+        let q = build_dork_from_keywords("chile,.cl,muni,ejemplo.cl,pe,nginx");
+        assert_eq!(q, concat!(
+            "(country:CL) AND ((ssl:\".cl\" OR http.title:\".cl\" OR http.html:\".cl\")) AND ",
+            "((http.title:\"Municipalidad\" OR http.html:\"Municipalidad\" OR org:\"Municipalidad\")) AND ",
+            "((hostname:\"ejemplo.cl\" OR ssl.cert.subject.cn:\"ejemplo.cl\" OR ssl.cert.issuer.cn:\"ejemplo.cl\")) AND ",
+            "(country:PE) AND ((http.title:\"nginx\" OR http.html:\"nginx\" OR org:\"nginx\" OR product:\"nginx\"))"));
+        assert_eq!(build_dork_from_keywords(" , "), "country:CL");
+    }
 
-fn collect_ips_from_matches(ips: &mut BTreeSet<String>, v: &Value, limit: usize) -> bool {
-    let empty = Vec::new();
-    let arr = v.get("matches").and_then(|x| x.as_array()).unwrap_or(&empty);
-    if arr.is_empty(){ return false; }
-    for m in arr { if let Some(ip) = m.get("ip_str").and_then(|x| x.as_str()){ ips.insert(ip.to_string()); if ips.len() >= limit { return false; } } }
-    true
+    #[test]
+    fn parse_match_full_banner() {
+        let v = serde_json::json!({
+            "ip_str": "1.2.3.4", "port": 443, "transport": "tcp", "product": "nginx", "version": "1.18.0",
+            "hostnames": ["a.cl"], "domains": ["a.cl"], "org": "ACME", "asn": "AS123", "isp": "ISP",
+            "location": { "country_code": "CL", "city": "Santiago" },
+            "ssl": { "cert": { "subject": { "CN": "a.cl" }, "issuer": { "CN": "R3" } } },
+            "http": { "title": "Inicio" },
+            "vulns": { "CVE-2021-23017": { "cvss": 7.5 } },
+            "timestamp": "2025-01-01T00:00:00.000000"
+        });
+        let m = super::parse_match(&v).unwrap();
+        assert_eq!(m.port, 443);
+        assert_eq!(m.product.as_deref(), Some("nginx"));
+        assert_eq!(m.ssl_subject.as_deref(), Some("CN=a.cl"));
+        assert_eq!(m.http_title.as_deref(), Some("Inicio"));
+        assert_eq!(m.vulns, vec!["CVE-2021-23017".to_string()]);
+        assert_eq!(m.location.unwrap().country_code.as_deref(), Some("CL"));
+        assert!(super::parse_match(&serde_json::json!({ "port": 80 })).is_none());
+    }
+
+    #[test]
+    fn facets_and_count_parsing() {
+        assert_eq!(super::facets_param("port, org:5,,asn", 10), "port:10,org:5,asn:10");
+        let v = serde_json::json!({ "total": 1234, "facets": { "port": [{ "count": 900, "value": 443 }], "org": [{ "count": 10, "value": "ACME" }] } });
+        let c = super::parse_count("country:CL", &v);
+        assert_eq!(c.total, 1234);
+        assert_eq!(c.facets["port"][0].value, "443");
+        assert_eq!(c.facets["org"][0].value, "ACME");
+    }
+
+    #[test]
+    fn credit_budget_caps_and_spends() {
+        let mut b = super::CreditBudget::new(Some(5));
+        b.cap(2);
+        b.cap(10);
+        assert_eq!(b.limit(), Some(2));
+        assert!(b.try_spend(1) && b.try_spend(1));
+        assert!(!b.try_spend(1));
+        assert!(b.exhausted());
+        assert!(super::CreditBudget::new(None).try_spend(1000));
+        // Sin créditos solo pasa lo que Shodan no cobra
+        let empty = super::CreditBudget::new(Some(0));
+        assert!(empty.try_spend(super::search_page_cost("nginx", 1)));
+        assert!(!empty.try_spend(super::search_page_cost("nginx", 2)) && !empty.try_spend(super::search_page_cost("country:CL", 1)));
+    }
+
+    #[test]
+    fn parse_host_lookup() {
+        let v = serde_json::json!({
+            "ip_str": "1.2.3.4", "ports": [443, 22, 443], "hostnames": ["a.cl"], "vulns": ["CVE-2023-38408"], "os": "Linux",
+            "data": [{ "ip_str": "1.2.3.4", "port": 22, "product": "OpenSSH" }, { "ip_str": "1.2.3.4", "port": 443 }]
+        });
+        let h = super::parse_host(&v).unwrap();
+        assert_eq!(h.ports, vec![22, 443]);
+        assert_eq!(h.vulns, vec!["CVE-2023-38408".to_string()]);
+        assert_eq!(h.banners.len(), 2);
+        assert!(super::parse_host(&serde_json::Value::Null).is_none());
+    }
 }
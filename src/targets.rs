@@ -1,6 +1,360 @@
-use anyhow::Result;
-use trust_dns_resolver::{TokioAsyncResolver, config::{ResolverConfig, ResolverOpts}};
+use anyhow::{Context, Result, anyhow, bail};
+use std::{collections::{BTreeMap, HashSet, VecDeque}, fs::File, io::{BufRead, BufReader, Lines}, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr}, path::Path, sync::Arc, time::Duration};
+use tokio::{sync::Semaphore, task::JoinHandle};
+use trust_dns_resolver::{TokioAsyncResolver, config::{LookupIpStrategy, NameServerConfig, Protocol, ResolverConfig, ResolverOpts}, error::ResolveErrorKind, system_conf::read_system_conf, lookup_ip::LookupIp, proto::rr::RData};
+use crate::{models::HostReport, reserved::reserved_range};
 
-pub async fn load_targets(path: &std::path::Path) -> Result<Vec<String>> { let s = tokio::fs::read_to_string(path).await?; Ok(s.lines().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect()) }
+/// Tope por defecto de direcciones que puede expandir un prefijo/rango IPv4 o IPv6 (`--max-hosts`); un /16 cabe justo.
+pub const DEFAULT_MAX_HOSTS: u128 = 65_536;
 
-pub async fn resolve_targets(targets: &[String]) -> Result<Vec<(String, String)>> { let resolver = TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default()); let mut out = Vec::new(); for t in targets { if t.parse::<std::net::IpAddr>().is_ok(){ out.push((t.clone(), t.clone())); continue; } if let Ok(lookup) = resolver.lookup_ip(t.as_str()).await { if let Some(ip)= lookup.iter().next(){ out.push((t.clone(), ip.to_string())); } } } Ok(out) }
+/// Un objetivo ya expandido (IP o hostname) con las etiquetas de su línea (`10.0.0.5 # web-prod`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetEntry { pub target: String, pub tags: Vec<String> }
+
+/// Rango inclusivo pendiente de emitir; `next = None` cuando se agotó.
+#[derive(Debug)]
+enum Expansion { One(Option<String>), Range { next: Option<u128>, end: u128, v6: bool } }
+
+impl Expansion {
+    fn next_target(&mut self) -> Option<String> {
+        match self {
+            Expansion::One(t) => t.take(),
+            Expansion::Range { next, end, v6 } => {
+                let cur = (*next)?;
+                *next = if cur < *end { Some(cur + 1) } else { None };
+                Some(if *v6 { Ipv6Addr::from(cur).to_string() } else { Ipv4Addr::from(cur as u32).to_string() })
+            }
+        }
+    }
+}
+
+/// Iterador perezoso sobre un archivo de objetivos: lee línea a línea y expande CIDR/rangos a medida que se consumen.
+pub struct TargetEntries<R> { lines: Lines<R>, line_no: usize, current: Option<(Expansion, Vec<String>)>, max_hosts: u128 }
+
+impl<R: BufRead> Iterator for TargetEntries<R> {
+    type Item = Result<TargetEntry>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((exp, tags)) = &mut self.current {
+                if let Some(target) = exp.next_target() { return Some(Ok(TargetEntry { target, tags: tags.clone() })); }
+                self.current = None;
+            }
+            let line = match self.lines.next()? { Ok(l) => l, Err(e) => return Some(Err(e.into())) };
+            self.line_no += 1;
+            match parse_line(&line, self.max_hosts) {
+                Ok(Some(x)) => self.current = Some(x),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e.context(format!("línea {} de objetivos: '{}'", self.line_no, line.trim())))),
+            }
+        }
+    }
+}
+
+/// Objetivos desde cualquier lector (archivo, stdin, tests).
+pub fn parse_targets<R: BufRead>(reader: R, max_hosts: u128) -> TargetEntries<R> { TargetEntries { lines: reader.lines(), line_no: 0, current: None, max_hosts } }
+
+/// Archivo de objetivos: una entrada por línea (IP, hostname, `10.0.0.0/24`, `10.0.0.1-50`, `10.0.0.1-10.0.1.20`, `2001:db8::/120`),
+/// `#` inicia comentario y lo que sigue a `#` en una línea con objetivo son etiquetas (separadas por coma o espacio).
+/// El archivo se valida completo antes de devolver el iterador: una línea inválida no corta la ejecución con parte ya resuelta.
+pub fn load_targets(path: &Path, max_hosts: u128) -> Result<TargetEntries<BufReader<File>>> {
+    let open = || File::open(path).with_context(|| format!("No pude abrir el archivo de objetivos {}", path.display()));
+    validate_targets(BufReader::new(open()?), max_hosts).with_context(|| format!("archivo de objetivos {} inválido", path.display()))?;
+    Ok(parse_targets(BufReader::new(open()?), max_hosts))
+}
+
+/// Revisa todas las líneas sin expandir los rangos; el error lista cada línea inválida.
+pub fn validate_targets<R: BufRead>(reader: R, max_hosts: u128) -> Result<()> {
+    let mut errors = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if let Err(e) = parse_line(&line, max_hosts) { errors.push(format!("línea {}: '{}': {e:#}", i + 1, line.trim())); }
+    }
+    if errors.is_empty() { Ok(()) } else { bail!("{} línea(s) inválida(s):\n  {}", errors.len(), errors.join("\n  ")) }
+}
+
+fn parse_line(line: &str, max_hosts: u128) -> Result<Option<(Expansion, Vec<String>)>> {
+    let (spec, comment) = match line.split_once('#') { Some((s, c)) => (s.trim(), Some(c)), None => (line.trim(), None) };
+    if spec.is_empty() { return Ok(None); }
+    let tags: Vec<String> = comment.map(|c| c.split([',', ' ', '\t']).map(|t| t.trim()).filter(|t| !t.is_empty()).map(String::from).collect()).unwrap_or_default();
+    if spec.contains(char::is_whitespace) { bail!("más de un objetivo en la línea (usa '#' antes de las etiquetas)"); }
+    Ok(Some((parse_spec(spec, max_hosts)?, tags)))
+}
+
+pub(crate) fn ip_bits(ip: IpAddr) -> (u128, bool) { match ip { IpAddr::V4(v4) => (u32::from(v4) as u128, false), IpAddr::V6(v6) => (u128::from(v6), true) } }
+
+/// Rango inclusivo `(inicio, fin, es_v6)` de un CIDR o rango con guion; `None` si `spec` no es una IP/red (hostname).
+pub fn parse_ip_range(spec: &str) -> Result<Option<(u128, u128, bool)>> {
+    if let Ok(ip) = spec.parse::<IpAddr>() { let (b, v6) = ip_bits(ip); return Ok(Some((b, b, v6))); }
+    if let Some((addr, prefix)) = spec.split_once('/') {
+        let ip: IpAddr = addr.parse().map_err(|_| anyhow!("CIDR con dirección inválida"))?;
+        let (bits, v6) = ip_bits(ip);
+        let width = if v6 { 128 } else { 32 };
+        let prefix: u32 = prefix.parse().ok().filter(|p| *p <= width).ok_or_else(|| anyhow!("prefijo CIDR inválido (0..={width})"))?;
+        let host_bits = width - prefix;
+        let mask = if host_bits == 128 { u128::MAX } else { (1u128 << host_bits) - 1 };
+        return Ok(Some((bits & !mask, bits | mask, v6)));
+    }
+    if let Some((a, b)) = spec.split_once('-') {
+        // "my-host.example.com" no es un rango: solo si la izquierda es una IP
+        if let Ok(start) = a.parse::<IpAddr>() {
+            let (s, v6) = ip_bits(start);
+            let e = match b.parse::<IpAddr>() {
+                Ok(end) if end.is_ipv6() == v6 => ip_bits(end).0,
+                Ok(_) => bail!("rango con familias IP distintas"),
+                // 10.0.0.1-50: reemplaza el último octeto
+                Err(_) if !v6 => (s & !0xff) | b.parse::<u8>().map_err(|_| anyhow!("fin de rango inválido"))? as u128,
+                Err(_) => bail!("en IPv6 el rango debe indicar la dirección final completa"),
+            };
+            if s > e { bail!("rango invertido"); }
+            return Ok(Some((s, e, v6)));
+        }
+    }
+    Ok(None)
+}
+
+fn parse_spec(spec: &str, max_hosts: u128) -> Result<Expansion> {
+    match parse_ip_range(spec)? {
+        // IP suelta: se conserva tal como se escribió
+        Some(_) if spec.parse::<IpAddr>().is_ok() => Ok(Expansion::One(Some(spec.to_string()))),
+        Some((start, end, v6)) => {
+            if end - start >= max_hosts { bail!("el rango {} supera el tope de {max_hosts} direcciones (--max-hosts)", if v6 { "IPv6" } else { "IPv4" }); }
+            Ok(Expansion::Range { next: Some(start), end, v6 })
+        }
+        None => Ok(Expansion::One(Some(spec.to_string()))),
+    }
+}
+
+/// Resultado de resolver un archivo de objetivos, agregado por IP a medida que llegan los pares objetivo→IP (muchos a muchos):
+/// no se guarda cada par, así que un CIDR grande cuesta una entrada por IP. Incluye los nombres que no resolvieron
+/// y las cadenas CNAME observadas (`www.ejemplo.cl → cdn.ejemplo.net → edge.cdn.net`).
+#[derive(Debug, Default)]
+pub struct Resolution {
+    /// IPs únicas en orden de aparición.
+    pub ips: Vec<String>,
+    /// Hostnames del archivo que apuntan a cada IP (las IPs escritas tal cual no aportan nombre).
+    pub names: BTreeMap<String, Vec<String>>,
+    /// Etiquetas por IP: unión sin repetir de las líneas que llevaron a esa IP.
+    pub tags: BTreeMap<String, Vec<String>>,
+    pub failures: Vec<(String, String)>,
+    pub cnames: BTreeMap<String, Vec<String>>,
+    seen: HashSet<String>,
+}
+
+impl Resolution {
+    /// Registra un par objetivo→IP.
+    pub fn add(&mut self, t: &TargetEntry, ip: &str) {
+        if t.target != ip { let v = self.names.entry(ip.to_string()).or_default(); if !v.contains(&t.target) { v.push(t.target.clone()); } }
+        if !t.tags.is_empty() { let v = self.tags.entry(ip.to_string()).or_default(); for tag in &t.tags { if !v.contains(tag) { v.push(tag.clone()); } } }
+        if self.seen.insert(ip.to_string()) { self.ips.push(ip.to_string()); }
+    }
+}
+
+/// Opciones del resolvedor DNS (`--dns-*`). Sin servidores ni `system` se usan los resolvedores públicos por defecto.
+#[derive(Debug, Clone)]
+pub struct DnsOptions {
+    /// Servidores explícitos (p. ej. un stub local `127.0.0.53` o el DNS interno del cliente para nombres split-horizon).
+    pub servers: Vec<SocketAddr>,
+    /// Usar `/etc/resolv.conf` (servidores, dominios de búsqueda y opciones del sistema).
+    pub system: bool,
+    /// Timeout por consulta; `None` respeta el de resolv.conf (o 5 s del resolvedor).
+    pub timeout_ms: Option<u64>,
+    /// Intentos por consulta; `None` respeta el de resolv.conf (o 2).
+    pub attempts: Option<usize>,
+    /// Resoluciones simultáneas.
+    pub concurrency: usize,
+}
+
+/// Resoluciones simultáneas por defecto (`--dns-concurrency`).
+pub const DEFAULT_DNS_CONCURRENCY: usize = 32;
+
+impl Default for DnsOptions {
+    fn default() -> Self { Self { servers: vec![], system: false, timeout_ms: None, attempts: None, concurrency: DEFAULT_DNS_CONCURRENCY } }
+}
+
+/// `10.0.0.53`, `127.0.0.1:5353`, `::1` o `[::1]:5353` (puerto 53 si no se indica).
+pub fn parse_nameserver(s: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = s.parse::<SocketAddr>() { return Ok(addr); }
+    s.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)).map_err(|_| anyhow!("servidor DNS inválido '{s}' (usa IP o IP:puerto)"))
+}
+
+pub fn build_resolver(dns: &DnsOptions) -> Result<TokioAsyncResolver> {
+    let (config, mut opts) = if dns.system {
+        read_system_conf().context("No pude leer /etc/resolv.conf")?
+    } else if !dns.servers.is_empty() {
+        // UDP con TCP de respaldo para respuestas truncadas, igual que los grupos predefinidos del resolvedor
+        let group: Vec<NameServerConfig> = dns.servers.iter().flat_map(|a| [NameServerConfig::new(*a, Protocol::Udp), NameServerConfig::new(*a, Protocol::Tcp)]).collect();
+        (ResolverConfig::from_parts(None, vec![], group), ResolverOpts::default())
+    } else {
+        (ResolverConfig::default(), ResolverOpts::default())
+    };
+    // A y AAAA en paralelo: con el valor por defecto (Ipv4thenIpv6) un host con A nunca devuelve sus AAAA
+    opts.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+    if let Some(ms) = dns.timeout_ms { opts.timeout = Duration::from_millis(ms); }
+    if let Some(n) = dns.attempts { opts.attempts = n; }
+    Ok(TokioAsyncResolver::tokio(config, opts))
+}
+
+/// Cadena CNAME de una respuesta (sin repetir; A y AAAA traen cada una la suya).
+fn cname_chain(lookup: &LookupIp) -> Vec<String> {
+    let mut chain: Vec<String> = Vec::new();
+    for r in lookup.as_lookup().records() {
+        if let Some(RData::CNAME(c)) = r.data() { let n = c.to_string().trim_end_matches('.').to_string(); if !chain.contains(&n) { chain.push(n); } }
+    }
+    chain
+}
+
+/// IPs de un hostname y su cadena CNAME, o el motivo del fallo.
+async fn lookup(resolver: &TokioAsyncResolver, name: &str) -> std::result::Result<(Vec<IpAddr>, Vec<String>), String> {
+    match resolver.lookup_ip(name).await {
+        Ok(l) => {
+            let mut ips: Vec<IpAddr> = Vec::new();
+            for ip in l.iter() { if !ips.contains(&ip) { ips.push(ip); } }
+            Ok((ips, cname_chain(&l)))
+        }
+        Err(e) => Err(match e.kind() { ResolveErrorKind::NoRecordsFound { .. } => "sin registros A/AAAA".to_string(), _ => e.to_string() }),
+    }
+}
+
+/// Resuelve (en orden y sin colapsar duplicados) cada objetivo a todas sus IPs (registros A y AAAA); consume el iterador de `load_targets`.
+/// Hasta `dns.concurrency` consultas en vuelo; el archivo se sigue leyendo de a poco y el orden de salida es el de entrada.
+/// Las IPs en rangos reservados/privados se descartan con aviso salvo `allow_private`; los nombres que no resuelven,
+/// o que solo resuelven a rangos reservados (con la etiqueta del rango), quedan en `failures`.
+/// Con `map` cada par y cada fallo se escribe según llega en ese CSV (`targets.csv`: `target,ip,cname,tags,error`).
+pub async fn resolve_targets<I: IntoIterator<Item = Result<TargetEntry>>>(targets: I, dns: &DnsOptions, allow_private: bool, map: Option<&Path>) -> Result<Resolution> {
+    type Pending = (TargetEntry, Option<JoinHandle<std::result::Result<(Vec<IpAddr>, Vec<String>), String>>>);
+    let resolver = build_resolver(dns)?;
+    let concurrency = dns.concurrency.max(1);
+    let sem = Arc::new(Semaphore::new(concurrency));
+    let mut res = Resolution::default();
+    let mut skipped = 0usize;
+    let mut queue: VecDeque<Pending> = VecDeque::new();
+    let mut targets = targets.into_iter();
+    let mut wtr = match map { Some(p) => { let mut w = csv::Writer::from_path(p)?; w.write_record(["target", "ip", "cname", "tags", "error"])?; Some(w) } None => None };
+    loop {
+        // Ventana acotada: se lanzan consultas mientras haya sitio y se drena por el frente para conservar el orden
+        while queue.len() < concurrency * 4 {
+            let Some(t) = targets.next() else { break };
+            let t = t?;
+            let task = if t.target.parse::<IpAddr>().is_ok() { None } else {
+                let (resolver, sem, name) = (resolver.clone(), sem.clone(), t.target.clone());
+                Some(tokio::spawn(async move { let _permit = sem.acquire_owned().await.unwrap(); lookup(&resolver, &name).await }))
+            };
+            queue.push_back((t, task));
+        }
+        let Some((t, task)) = queue.pop_front() else { break };
+        let ips: Vec<IpAddr> = match task {
+            None => vec![t.target.parse().expect("IP ya validada")],
+            Some(h) => match h.await? {
+                Ok((ips, chain)) => { if !chain.is_empty() { res.cnames.insert(t.target.clone(), chain); } ips }
+                Err(reason) => {
+                    eprintln!("[WARN] No pude resolver {}: {reason}", t.target);
+                    if let Some(w) = wtr.as_mut() { w.write_record([t.target.as_str(), "", "", &t.tags.join(";"), &reason])?; }
+                    res.failures.push((t.target.clone(), reason));
+                    continue;
+                }
+            },
+        };
+        let (mut kept, mut reserved): (usize, Vec<&str>) = (0, Vec::new());
+        for ip in ips {
+            if !allow_private && let Some(label) = reserved_range(ip) {
+                // Un aviso por línea sería ruido con un /16 privado: se detallan los primeros y luego se cuentan
+                if skipped < 10 { eprintln!("[WARN] {} ({ip}) está en rango reservado ({label}); se omite (usa --allow-private)", t.target); }
+                skipped += 1;
+                if !reserved.contains(&label) { reserved.push(label); }
+                continue;
+            }
+            let ip = if t.target.parse::<IpAddr>().is_ok() { t.target.clone() } else { ip.to_string() };
+            if let Some(w) = wtr.as_mut() { w.write_record([t.target.as_str(), &ip, &res.cnames.get(&t.target).map(|c| c.join(" > ")).unwrap_or_default(), &t.tags.join(";"), ""])?; }
+            res.add(&t, &ip);
+            kept += 1;
+        }
+        // Un hostname que solo apunta a rangos reservados no desaparece en silencio: queda como fallo con el rango
+        if kept == 0 && !reserved.is_empty() && t.target.parse::<IpAddr>().is_err() {
+            let reason = format!("solo resuelve a rango reservado ({})", reserved.join(", "));
+            if let Some(w) = wtr.as_mut() { w.write_record([t.target.as_str(), "", "", &t.tags.join(";"), &reason])?; }
+            res.failures.push((t.target.clone(), reason));
+        }
+    }
+    if skipped > 10 { eprintln!("[WARN] {skipped} objetivo(s) en rangos reservados omitidos en total"); }
+    if let Some(mut w) = wtr { w.flush()?; }
+    Ok(res)
+}
+
+/// Deja en `HostReport.target` todos los hostnames que resolvieron a la IP (separados por coma).
+pub fn attach_targets(reports: &mut [HostReport], names: &BTreeMap<String, Vec<String>>) {
+    for r in reports.iter_mut() { if let Some(n) = names.get(&r.ip) { r.target = n.join(","); } }
+}
+
+pub fn attach_tags(reports: &mut [HostReport], tags: &BTreeMap<String, Vec<String>>) {
+    for r in reports.iter_mut() { if let Some(t) = tags.get(&r.ip) { r.tags = t.clone(); } }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(text: &str) -> Result<Vec<TargetEntry>> { parse_targets(text.as_bytes(), 16).collect() }
+    fn names(text: &str) -> Vec<String> { expand(text).unwrap().into_iter().map(|t| t.target).collect() }
+
+    #[test]
+    fn expands_cidr_ranges_and_tags() {
+        let got = expand("# alcance\n\n10.0.0.5 # web-prod, dmz\nwww.ejemplo.cl\n10.0.0.8/30\n10.0.1.254-255 #borde\n").unwrap();
+        assert_eq!(got[0], TargetEntry { target: "10.0.0.5".into(), tags: vec!["web-prod".into(), "dmz".into()] });
+        assert_eq!(got[1].target, "www.ejemplo.cl");
+        assert_eq!(got.iter().skip(2).map(|t| t.target.as_str()).collect::<Vec<_>>(), vec!["10.0.0.8", "10.0.0.9", "10.0.0.10", "10.0.0.11", "10.0.1.254", "10.0.1.255"]);
+        assert_eq!(got[7].tags, vec!["borde"]);
+        assert_eq!(names("10.0.0.250-10.0.1.1"), vec!["10.0.0.250", "10.0.0.251", "10.0.0.252", "10.0.0.253", "10.0.0.254", "10.0.0.255", "10.0.1.0", "10.0.1.1"]);
+        assert_eq!(names("255.255.255.255/32"), vec!["255.255.255.255"]);
+        assert_eq!(names("mi-host.ejemplo.cl"), vec!["mi-host.ejemplo.cl"]);
+    }
+
+    #[test]
+    fn ipv6_cap_and_errors() {
+        assert_eq!(names("2001:db8::/124").len(), 16);
+        assert_eq!(names("2001:db8::1-2001:db8::3"), vec!["2001:db8::1", "2001:db8::2", "2001:db8::3"]);
+        assert!(expand("2001:db8::/64").is_err());
+        // El mismo tope vale para IPv4
+        assert!(expand("10.0.0.0/27").is_err());
+        assert_eq!(names("10.0.0.0/28").len(), 16);
+        assert!(expand("10.0.0.0/33").is_err());
+        assert!(expand("10.0.0.9-3").is_err());
+        assert!(expand("10.0.0.1 10.0.0.2").is_err());
+        let err = expand("10.0.0.1\n10.0.0.0/40\n").unwrap_err();
+        assert!(format!("{err:#}").contains("línea 2"));
+        // La validación previa reporta todas las líneas malas de una vez
+        let err = validate_targets("10.0.0.1 10.0.0.2\nwww.ejemplo.cl\n10.0.0.0/15\n".as_bytes(), 16).unwrap_err().to_string();
+        assert!(err.starts_with("2 línea(s) inválida(s)") && err.contains("línea 1: '10.0.0.1 10.0.0.2'") && err.contains("línea 3: '10.0.0.0/15'"), "{err}");
+        assert!(validate_targets("www.ejemplo.cl\n10.0.0.0/28 # dmz\n".as_bytes(), 16).is_ok());
+        // Perezoso: un /8 no se materializa, se consume de a poco
+        let mut it = parse_targets("10.0.0.0/8\n".as_bytes(), 1 << 24);
+        assert_eq!(it.nth(70_000).unwrap().unwrap().target, "10.1.17.112");
+    }
+
+    #[test]
+    fn many_to_many_target_mapping() {
+        let e = |t: &str| TargetEntry { target: t.into(), tags: vec![] };
+        let mut res = Resolution::default();
+        for (t, ip) in [(e("a.ejemplo.cl"), "1.1.1.1"), (e("a.ejemplo.cl"), "2606:4700::1"), (e("b.ejemplo.cl"), "1.1.1.1"), (TargetEntry { target: "1.1.1.1".into(), tags: vec!["dmz".into()] }, "1.1.1.1")] { res.add(&t, ip); }
+        assert_eq!(res.ips, vec!["1.1.1.1", "2606:4700::1"]);
+        assert_eq!((res.tags["1.1.1.1"].clone(), res.tags.get("2606:4700::1")), (vec!["dmz".to_string()], None));
+        let names = &res.names;
+        assert_eq!(names["1.1.1.1"], vec!["a.ejemplo.cl", "b.ejemplo.cl"]);
+        assert_eq!(names["2606:4700::1"], vec!["a.ejemplo.cl"]);
+        let mut reports = vec![HostReport { target: "1.1.1.1".into(), ip: "1.1.1.1".into(), ..Default::default() }, HostReport { target: "8.8.8.8".into(), ip: "8.8.8.8".into(), ..Default::default() }];
+        attach_targets(&mut reports, names);
+        assert_eq!(reports[0].target, "a.ejemplo.cl,b.ejemplo.cl");
+        assert_eq!(reports[1].target, "8.8.8.8");
+        // Agrupado por hostname: la IP compartida aparece bajo ambos nombres y la IP sin nombre no entra
+        let groups = crate::output::group_by_target(&reports);
+        assert_eq!(groups.iter().map(|(t, hs)| (*t, hs.iter().map(|h| h.ip.as_str()).collect::<Vec<_>>())).collect::<Vec<_>>(), vec![("a.ejemplo.cl", vec!["1.1.1.1"]), ("b.ejemplo.cl", vec!["1.1.1.1"])]);
+    }
+
+    #[test]
+    fn nameserver_specs() {
+        assert_eq!(parse_nameserver("10.0.0.53").unwrap(), "10.0.0.53:53".parse().unwrap());
+        assert_eq!(parse_nameserver("127.0.0.1:5353").unwrap(), "127.0.0.1:5353".parse().unwrap());
+        assert_eq!(parse_nameserver("::1").unwrap(), "[::1]:53".parse().unwrap());
+        assert_eq!(parse_nameserver("[fd00::53]:5300").unwrap(), "[fd00::53]:5300".parse().unwrap());
+        assert!(parse_nameserver("dns.ejemplo.cl").is_err());
+    }
+}
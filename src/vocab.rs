@@ -0,0 +1,128 @@
+//! Vocabulario declarativo keywords -> cláusulas Shodan (YAML), con valores por defecto embebidos.
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use std::path::Path;
+
+const DEFAULT_VOCAB: &str = include_str!("../vocab/default.yaml");
+
+/// Entrada del vocabulario: `aliases` se traducen a `query` (cláusula cruda), `template` (con `{kw}`)
+/// o a un OR de `fields` con `value` (por defecto la keyword).
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct KeywordMapping {
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub query: Option<String>,
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub fields: Vec<String>,
+    #[serde(default)]
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct VocabFile {
+    fallback: Option<String>,
+    inherit: Option<bool>,
+    iso_country: Option<bool>,
+    tld_fields: Option<Vec<String>>,
+    domain_fields: Option<Vec<String>>,
+    free_fields: Option<Vec<String>>,
+    #[serde(default)]
+    keywords: Vec<KeywordMapping>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Vocabulary {
+    pub fallback: String,
+    pub iso_country: bool,
+    pub tld_fields: Vec<String>,
+    pub domain_fields: Vec<String>,
+    pub free_fields: Vec<String>,
+    pub keywords: Vec<KeywordMapping>,
+}
+
+impl Vocabulary {
+    /// Vocabulario embebido (`vocab/default.yaml`).
+    pub fn builtin() -> Self {
+        let f: VocabFile = serde_yaml::from_str(DEFAULT_VOCAB).expect("vocab/default.yaml inválido");
+        Self::merge(f, None).expect("vocab/default.yaml incompleto")
+    }
+
+    /// Carga un vocabulario de usuario; lo que no defina se toma del embebido y, salvo `inherit: false`,
+    /// sus keywords se evalúan antes que las por defecto.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("No pude leer el vocabulario {}", path.display()))?;
+        let f: VocabFile = serde_yaml::from_str(&text).with_context(|| format!("Vocabulario inválido {}", path.display()))?;
+        Self::merge(f, Some(Self::builtin()))
+    }
+
+    fn merge(f: VocabFile, base: Option<Vocabulary>) -> Result<Self> {
+        let missing = |name: &str| anyhow!("falta '{name}' en el vocabulario");
+        for m in &f.keywords {
+            if m.aliases.is_empty() { return Err(anyhow!("entrada de vocabulario sin aliases")); }
+            if m.query.is_none() && m.template.is_none() && m.fields.is_empty() { return Err(anyhow!("'{}' necesita query, template o fields", m.aliases[0])); }
+        }
+        let mut keywords = f.keywords;
+        let base = match base {
+            Some(b) => { if f.inherit.unwrap_or(true) { keywords.extend(b.keywords.iter().cloned()); } b }
+            None => Vocabulary { fallback: f.fallback.clone().ok_or_else(|| missing("fallback"))?, iso_country: true, tld_fields: vec![], domain_fields: vec![], free_fields: vec![], keywords: vec![] },
+        };
+        Ok(Vocabulary {
+            fallback: f.fallback.unwrap_or(base.fallback),
+            iso_country: f.iso_country.unwrap_or(base.iso_country),
+            tld_fields: f.tld_fields.unwrap_or(base.tld_fields),
+            domain_fields: f.domain_fields.unwrap_or(base.domain_fields),
+            free_fields: f.free_fields.unwrap_or(base.free_fields),
+            keywords,
+        })
+    }
+
+    /// Entrada cuyo alias coincide con la keyword (ya normalizada a minúsculas).
+    pub fn lookup(&self, kw: &str) -> Option<&KeywordMapping> { self.keywords.iter().find(|m| m.aliases.iter().any(|a| a.eq_ignore_ascii_case(kw))) }
+}
+
+/// `(f1:"v" OR f2:"v")`; sin paréntesis si hay un solo campo.
+pub fn or_fields(fields: &[String], value: &str) -> String {
+    let parts: Vec<String> = fields.iter().map(|f| format!("{f}:\"{value}\"")).collect();
+    if parts.len() == 1 { parts.into_iter().next().unwrap_or_default() } else { format!("({})", parts.join(" OR ")) }
+}
+
+impl KeywordMapping {
+    /// Cláusula Shodan para `kw` según esta entrada.
+    pub fn render(&self, kw: &str) -> String {
+        if let Some(q) = &self.query { return q.clone(); }
+        if let Some(t) = &self.template { return t.replace("{kw}", kw); }
+        or_fields(&self.fields, self.value.as_deref().unwrap_or(kw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_vocab_overrides_and_inherits() {
+        let dir = std::env::temp_dir().join(format!("shodan-pipeline-vocab-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("v.yaml");
+        std::fs::write(&path, "fallback: 'country:PE'\nkeywords:\n  - aliases: [peru, pe]\n    query: 'country:PE'\n  - aliases: [hospital]\n    fields: [http.title]\n    value: Hospital\n").unwrap();
+        let v = Vocabulary::load(&path).unwrap();
+        assert_eq!(v.fallback, "country:PE");
+        assert_eq!(v.lookup("PERU").unwrap().render("peru"), "country:PE");
+        assert_eq!(v.lookup("hospital").unwrap().render("hospital"), "http.title:\"Hospital\"");
+        // hereda las entradas embebidas y sus listas de campos
+        assert!(v.lookup("muni").is_some());
+        assert_eq!(v.free_fields, Vocabulary::builtin().free_fields);
+        std::fs::write(&path, "inherit: false\nkeywords:\n  - aliases: [x]\n").unwrap();
+        assert!(Vocabulary::load(&path).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn bundled_sector_vocab_loads() {
+        let v = Vocabulary::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("vocab").join("salud.yaml")).unwrap();
+        assert_eq!(v.lookup("pacs").unwrap().render("pacs"), "(http.title:\"pacs\" OR product:\"pacs\" OR http.html:\"DICOM\")");
+        assert_eq!(v.fallback, "country:CL");
+    }
+}
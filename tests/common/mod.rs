@@ -0,0 +1,151 @@
+//! Utilidades compartidas por los tests de integración: servidor Shodan simulado a partir de
+//! `tests/fixtures/shodan/*.json`, un DNS local con una zona fija y un `nmap` falso que copia `tests/fixtures/nmap/host.xml`.
+#![allow(dead_code)]
+
+use std::{path::{Path, PathBuf}, sync::{Arc, Mutex}};
+use std::net::SocketAddr;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, UdpSocket}};
+use trust_dns_resolver::proto::{op::{Message, MessageType, ResponseCode}, rr::{Name, RData, Record, RecordType, rdata::{A, AAAA, CNAME, PTR}}};
+
+pub const TEST_KEY: &str = "test-key";
+
+pub fn fixtures_dir() -> PathBuf { Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures") }
+
+/// Directorio temporal vacío y exclusivo para un test.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("shodan-pipeline-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Servidor HTTP local que imita los endpoints de Shodan usados por el crate.
+/// Rutas → fixture: `/api-info` → `api_info.json`, `/shodan/host/count` → `count.json`,
+/// `/shodan/host/search?page=N` → `search_page_N.json` (sin fixture: página vacía), `/shodan/host/{ip}` → `host_{ip}.json`.
+/// `/count` con `http.html` en la query responde 500.
+pub struct MockShodan { pub base_url: String, pub requests: Arc<Mutex<Vec<String>>> }
+
+impl MockShodan {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut sock, _)) = listener.accept().await {
+                let log = log.clone();
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 8192];
+                    let n = sock.read(&mut buf).await.unwrap_or(0);
+                    let req = String::from_utf8_lossy(&buf[..n]).to_string();
+                    let target = req.split_whitespace().nth(1).unwrap_or("/").to_string();
+                    log.lock().unwrap().push(target.clone());
+                    let (status, body) = route(&target);
+                    let resp = format!("HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len());
+                    let _ = sock.write_all(resp.as_bytes()).await;
+                    let _ = sock.shutdown().await;
+                });
+            }
+        });
+        Self { base_url, requests }
+    }
+
+    /// Peticiones recibidas cuya ruta empieza por `path`.
+    pub fn hits(&self, path: &str) -> usize { self.requests.lock().unwrap().iter().filter(|r| r.starts_with(path)).count() }
+}
+
+/// DNS local (UDP) con una zona fija, para probar resolución con `--dns-server` sin red:
+/// `interno.lab` → A 10.20.0.5 (nombre split-horizon), `multi.lab` → A 198.51.100.1/.2 + AAAA 2001:db8::1,
+/// `www.lab` → CNAME `multi.lab`, PTR de 192.0.2.10 → `web.ejemplo.lab`; el resto responde NXDOMAIN.
+pub struct MockDns { pub addr: SocketAddr }
+
+impl MockDns {
+    pub async fn start() -> Self {
+        let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = sock.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 4096];
+            while let Ok((n, peer)) = sock.recv_from(&mut buf).await {
+                let Ok(req) = Message::from_vec(&buf[..n]) else { continue };
+                let resp = dns_answer(&req);
+                let _ = sock.send_to(&resp.to_vec().unwrap(), peer).await;
+            }
+        });
+        Self { addr }
+    }
+}
+
+fn dns_answer(req: &Message) -> Message {
+    let mut resp = Message::new();
+    resp.set_id(req.id()).set_message_type(MessageType::Response).set_op_code(req.op_code()).set_recursion_desired(true).set_recursion_available(true);
+    let Some(q) = req.queries().first() else { return resp };
+    resp.add_query(q.clone());
+    let mut name = q.name().to_ascii().trim_end_matches('.').to_lowercase();
+    if name == "www.lab" {
+        resp.add_answer(Record::from_rdata(q.name().clone(), 60, RData::CNAME(CNAME(Name::from_ascii("multi.lab.").unwrap()))));
+        name = "multi.lab".into();
+    }
+    let owner = Name::from_ascii(format!("{name}.")).unwrap();
+    let rdata: Vec<RData> = match (name.as_str(), q.query_type()) {
+        ("interno.lab", RecordType::A) => vec![RData::A(A("10.20.0.5".parse().unwrap()))],
+        ("multi.lab", RecordType::A) => vec![RData::A(A("198.51.100.1".parse().unwrap())), RData::A(A("198.51.100.2".parse().unwrap()))],
+        ("multi.lab", RecordType::AAAA) => vec![RData::AAAA(AAAA("2001:db8::1".parse().unwrap()))],
+        ("10.2.0.192.in-addr.arpa", RecordType::PTR) => vec![RData::PTR(PTR(Name::from_ascii("web.ejemplo.lab.").unwrap()))],
+        ("interno.lab" | "multi.lab" | "10.2.0.192.in-addr.arpa", _) => vec![],
+        _ => { resp.set_response_code(ResponseCode::NXDomain); vec![] }
+    };
+    for r in rdata { resp.add_answer(Record::from_rdata(owner.clone(), 60, r)); }
+    resp
+}
+
+fn query_param<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query.split('&').filter_map(|kv| kv.split_once('=')).find(|(k, _)| *k == name).map(|(_, v)| v)
+}
+
+fn route(target: &str) -> (&'static str, String) {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if query_param(query, "key") != Some(TEST_KEY) { return ("401 Unauthorized", r#"{"error": "Invalid API key"}"#.into()); }
+    // Igual que Shodan con búsquedas de texto muy amplias: http.html hace fallar /count
+    if path == "/shodan/host/count" && query_param(query, "query").is_some_and(|q| q.contains("http.html")) { return ("500 Internal Server Error", r#"{"error": "Request timed out or an internal error occurred"}"#.into()); }
+    let dir = fixtures_dir().join("shodan");
+    let file = match path {
+        "/api-info" => "api_info.json".to_string(),
+        "/shodan/host/count" => "count.json".to_string(),
+        "/shodan/host/search" => format!("search_page_{}.json", query_param(query, "page").unwrap_or("1")),
+        p if p.starts_with("/shodan/host/") => format!("host_{}.json", &p["/shodan/host/".len()..]),
+        _ => return ("404 Not Found", r#"{"error": "Not found"}"#.into()),
+    };
+    match std::fs::read_to_string(dir.join(&file)) {
+        Ok(body) => ("200 OK", body),
+        Err(_) if path == "/shodan/host/search" => ("200 OK", r#"{"matches": [], "total": 0}"#.into()),
+        Err(_) => ("404 Not Found", r#"{"error": "No information available for that IP."}"#.into()),
+    }
+}
+
+/// Crea en `dir` un ejecutable `nmap` falso que escribe el XML de fixture en la ruta de `-oX`.
+/// Devuelve un PATH con `dir` delante para lanzar el binario del pipeline.
+#[cfg(unix)]
+pub fn fake_nmap_path(dir: &Path) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let xml = fixtures_dir().join("nmap").join("host.xml");
+    // Cada invocación deja sus argumentos en `<dir>/nmap.args`
+    let log = dir.join("nmap.args");
+    let script = format!("#!/bin/sh\necho \"$*\" >> '{}'\nout=\"\"\nwhile [ $# -gt 0 ]; do\n  if [ \"$1\" = \"-oX\" ]; then out=\"$2\"; fi\n  shift\ndone\nif [ \"$out\" = \"-\" ]; then cat \"{x}\"; else cat \"{x}\" > \"$out\"; fi\n", log.display(), x = xml.display());
+    let bin = dir.join("nmap");
+    std::fs::write(&bin, script).unwrap();
+    std::fs::set_permissions(&bin, std::fs::Permissions::from_mode(0o755)).unwrap();
+    format!("{}:{}", dir.display(), std::env::var("PATH").unwrap_or_default())
+}
+
+/// `rustscan` falso en `dir` (usar junto a `fake_nmap_path`): falla con código 3 y mensaje en stderr para
+/// `fail_ip`; para el resto imprime `ip -> [22,80]` en formato `--greppable`.
+#[cfg(unix)]
+pub fn fake_rustscan(dir: &Path, fail_ip: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    // Cada invocación deja sus argumentos en `<dir>/rustscan.args`
+    let log = dir.join("rustscan.args");
+    let script = format!("#!/bin/sh\necho \"$*\" >> '{}'\nip=\"$2\"\nif [ \"$ip\" = \"{fail_ip}\" ]; then echo \"boom: sin permisos\" >&2; exit 3; fi\necho \"$ip -> [22,80]\"\n", log.display());
+    let bin = dir.join("rustscan");
+    std::fs::write(&bin, script).unwrap();
+    std::fs::set_permissions(&bin, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
@@ -0,0 +1,434 @@
+//! Pipeline contra el servidor Shodan simulado (sin red ni créditos reales).
+mod common;
+
+use common::{MockDns, MockShodan, TEST_KEY, temp_dir};
+use std::time::Duration;
+use shodan_pipeline::{cache::{CacheMode, DiskCache}, dork::{self, DorkErrorPolicy}, http::RetryPolicy, shodan::{account_guard, check_dork, enrich_hosts, export_collected, shodan_collect, unique_ips, ShodanApi}, targets::{parse_targets, resolve_targets, DnsOptions}, vocab::Vocabulary};
+
+fn mock_api(base_url: &str, max_credits: Option<usize>) -> ShodanApi {
+    ShodanApi::new(TEST_KEY, max_credits, 1000.0, RetryPolicy::default()).unwrap().with_base_url(base_url)
+}
+
+#[tokio::test]
+async fn collect_pages_from_mock() {
+    let mock = MockShodan::start().await;
+    let out = temp_dir("collect");
+    let api = mock_api(&mock.base_url, None);
+    let matches = shodan_collect(&api, "country:CL", 10, 5, false, false).await.unwrap();
+    assert_eq!(unique_ips(&matches), vec!["192.0.2.10", "192.0.2.20", "192.0.2.30"]);
+    assert_eq!(matches.len(), 4);
+    assert_eq!(matches[0].http_title.as_deref(), Some("Municipalidad de Ejemplo"));
+    // 2 páginas con datos + 1 vacía que corta la paginación
+    assert_eq!(mock.hits("/shodan/host/search"), 3);
+    export_collected(&out, &unique_ips(&matches), &matches).unwrap();
+    assert_eq!(std::fs::read_to_string(out.join("ips.txt")).unwrap().lines().count(), 3);
+    assert_eq!(std::fs::read_to_string(out.join("shodan_matches.jsonl")).unwrap().lines().count(), 4);
+}
+
+/// Con los puertos de Shodan el límite corta IPs nuevas, no los banners de las ya admitidas.
+#[tokio::test]
+async fn limit_keeps_banners_of_admitted_ips() {
+    let mock = MockShodan::start().await;
+    let api = mock_api(&mock.base_url, None);
+    let cut = shodan_collect(&api, "country:CL", 1, 5, false, false).await.unwrap();
+    assert_eq!((cut.len(), mock.hits("/shodan/host/search")), (1, 1));
+    let kept = shodan_collect(&api, "country:CL", 1, 5, true, false).await.unwrap();
+    assert_eq!(unique_ips(&kept), vec!["192.0.2.10"]);
+    assert_eq!(kept.iter().map(|m| m.port).collect::<Vec<_>>(), vec![443, 22]);
+    // Sigue hasta la página vacía (o `pages`) por si aparecen más banners de 192.0.2.10
+    assert_eq!(mock.hits("/shodan/host/search"), 4);
+}
+
+#[tokio::test]
+async fn budget_stops_pagination() {
+    let mock = MockShodan::start().await;
+    let mut api = mock_api(&mock.base_url, Some(100));
+    account_guard(&mut api).await;
+    // api_info.json informa 42 créditos de consulta
+    assert_eq!(api.budget.limit(), Some(42));
+    let api = mock_api(&mock.base_url, Some(1));
+    let matches = shodan_collect(&api, "country:CL", 10, 5, false, false).await.unwrap();
+    assert_eq!(unique_ips(&matches), vec!["192.0.2.10"]);
+    assert_eq!(mock.hits("/shodan/host/search"), 1);
+    // Cuenta sin créditos: la primera página de una búsqueda sin filtros es gratis y se descarga; la segunda no
+    let api = mock_api(&mock.base_url, Some(0));
+    let free = shodan_collect(&api, "nginx", 10, 5, false, false).await.unwrap();
+    assert!(!free.is_empty());
+    assert_eq!((mock.hits("/shodan/host/search"), api.budget.spent()), (2, 0));
+}
+
+#[tokio::test]
+async fn invalid_key_is_an_error() {
+    let mock = MockShodan::start().await;
+    let api = ShodanApi::new("otra", None, 1000.0, RetryPolicy::default()).unwrap().with_base_url(&mock.base_url);
+    assert!(shodan_collect(&api, "country:CL", 10, 1, false, false).await.is_err());
+}
+
+#[tokio::test]
+async fn dork_error_policies() {
+    let mock = MockShodan::start().await;
+    let api = ShodanApi::new(TEST_KEY, None, 1000.0, RetryPolicy { max_attempts: 1, ..Default::default() }).unwrap().with_base_url(&mock.base_url);
+    let vocab = Vocabulary::builtin();
+    let expr = dork::parse("chile, nginx").unwrap();
+    assert!(check_dork(&api, &expr, &vocab, &DorkErrorPolicy::Abort, "").await.is_err());
+    let fb = check_dork(&api, &expr, &vocab, &DorkErrorPolicy::Fallback("country:PE".into()), "").await.unwrap();
+    assert_eq!(fb.query, "country:PE");
+    assert_eq!(fb.changes.len(), 2);
+    let simple = check_dork(&api, &expr, &vocab, &DorkErrorPolicy::Simplify, "").await.unwrap();
+    assert_eq!(simple.query, "(country:CL) AND ((http.title:\"nginx\" OR org:\"nginx\" OR product:\"nginx\"))");
+    assert!(simple.changes[1].starts_with("Sin http.html"));
+    let ok = check_dork(&api, &dork::parse("chile").unwrap(), &vocab, &DorkErrorPolicy::Abort, "").await.unwrap();
+    assert!(ok.changes.is_empty());
+}
+
+#[tokio::test]
+async fn cached_search_pages_cost_nothing() {
+    let mock = MockShodan::start().await;
+    let dir = temp_dir("search-cache");
+    let cache = |mode| DiskCache::new(dir.join("cache"), Duration::from_secs(60), mode);
+    let api = mock_api(&mock.base_url, None).with_cache(cache(CacheMode::Use));
+    let first = shodan_collect(&api, "country:CL", 10, 5, false, false).await.unwrap();
+    assert_eq!(mock.hits("/shodan/host/search"), 3);
+    let api = mock_api(&mock.base_url, Some(0)).with_cache(cache(CacheMode::Use));
+    let second = shodan_collect(&api, "country:CL", 10, 5, false, false).await.unwrap();
+    assert_eq!(first, second);
+    assert_eq!(mock.hits("/shodan/host/search"), 3, "todo debe salir de la caché");
+    assert_eq!(api.budget.spent(), 0);
+    let api = mock_api(&mock.base_url, None).with_cache(cache(CacheMode::Refresh));
+    shodan_collect(&api, "country:CL", 10, 5, false, false).await.unwrap();
+    assert_eq!(mock.hits("/shodan/host/search"), 6);
+}
+
+#[tokio::test]
+async fn enrich_hosts_uses_disk_cache() {
+    let mock = MockShodan::start().await;
+    let cache = DiskCache::new(temp_dir("enrich"), Duration::from_secs(60), CacheMode::Use);
+    let api = mock_api(&mock.base_url, None).with_cache(cache);
+    let ips = vec!["192.0.2.40".to_string(), "192.0.2.99".to_string()];
+    let hosts = enrich_hosts(&api, &ips, false).await.unwrap();
+    let h = &hosts["192.0.2.40"];
+    assert_eq!(h.ports, vec![80, 8443]);
+    assert_eq!(h.banners[0].product.as_deref(), Some("Apache httpd"));
+    // 192.0.2.99 no existe en Shodan (404) y no es un error
+    assert!(!hosts.contains_key("192.0.2.99"));
+    let again = enrich_hosts(&api, &ips, false).await.unwrap();
+    assert_eq!(again.len(), 1);
+    assert_eq!(mock.hits("/shodan/host/192.0.2"), 2, "la segunda pasada debe salir de la caché");
+}
+
+#[tokio::test]
+async fn resolves_against_local_dns_server() {
+    let dns = MockDns::start().await;
+    let opts = DnsOptions { servers: vec![dns.addr], timeout_ms: Some(500), attempts: Some(1), concurrency: 4, ..Default::default() };
+    let list = "interno.lab\nwww.lab # web\nnoexiste.lab\n203.0.113.9\n";
+    let map = temp_dir("dns-map").join("targets.csv");
+    let res = resolve_targets(parse_targets(list.as_bytes(), 16), &opts, true, Some(&map)).await.unwrap();
+    // Orden de entrada aunque se resuelva en paralelo; todas las A/AAAA del CNAME
+    assert_eq!(res.ips[0], "10.20.0.5");
+    assert_eq!(res.ips.last().map(String::as_str), Some("203.0.113.9"));
+    let mut www: Vec<&str> = res.names.iter().filter(|(_, n)| n.contains(&"www.lab".to_string())).map(|(ip, _)| ip.as_str()).collect();
+    www.sort();
+    assert_eq!(www, vec!["198.51.100.1", "198.51.100.2", "2001:db8::1"]);
+    assert_eq!(res.tags["198.51.100.1"], vec!["web"]);
+    assert!(!res.names.contains_key("203.0.113.9"));
+    assert_eq!(res.cnames["www.lab"], vec!["multi.lab"]);
+    assert_eq!(res.failures.len(), 1);
+    assert_eq!(res.failures[0].0, "noexiste.lab");
+    // targets.csv se escribe durante la resolución: un par por fila y los fallos con su motivo
+    let csv = std::fs::read_to_string(&map).unwrap();
+    assert!(csv.starts_with("target,ip,cname,tags,error\ninterno.lab,10.20.0.5,,,\n"), "{csv}");
+    assert!(csv.contains("www.lab,198.51.100.1,multi.lab,web,") && csv.contains("noexiste.lab,,,,"), "{csv}");
+    assert_eq!(csv.lines().count(), 7, "{csv}");
+    // Sin --allow-private el nombre interno (10/8) y los de documentación se descartan...
+    let res = resolve_targets(parse_targets(list.as_bytes(), 16), &opts, false, Some(&map)).await.unwrap();
+    assert!(res.ips.is_empty());
+    // ...pero los hostnames quedan como fallo con la etiqueta del rango; la IP literal solo se avisa
+    let failed: Vec<(&str, &str)> = res.failures.iter().map(|(t, r)| (t.as_str(), r.as_str())).collect();
+    assert_eq!(failed.len(), 3, "{failed:?}");
+    assert_eq!(failed[0], ("interno.lab", "solo resuelve a rango reservado (privado (RFC 1918))"));
+    assert_eq!(failed[1].0, "www.lab");
+    assert!(failed[1].1.contains("documentación (TEST-NET-2)") && failed[1].1.contains(", documentación"), "{failed:?}");
+    assert_eq!(failed[2].0, "noexiste.lab");
+    let csv = std::fs::read_to_string(&map).unwrap();
+    assert!(csv.contains("interno.lab,,,,solo resuelve a rango reservado (privado (RFC 1918))") && csv.contains("www.lab,,,web,\"solo resuelve"), "{csv}");
+    assert!(!csv.contains("203.0.113.9"), "{csv}");
+}
+
+/// `full` completo: Shodan simulado + `nmap` falso, puertos tomados de Shodan.
+/// Los fixtures usan 192.0.2.0/24 (documentación), por eso los binarios se lanzan con `--allow-private`;
+/// el DNS inverso va contra el DNS local (los demás binarios usan `--no-ptr` para no salir a la red).
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn full_pipeline_offline() {
+    let mock = MockShodan::start().await;
+    let dns = MockDns::start().await;
+    let dir = temp_dir("full");
+    let path = common::fake_nmap_path(&dir);
+    std::fs::write(dir.join("rules.yaml"), "rules:\n  - name: titulo\n    script: http-title\n    script_output_regex: Login\n    cmds: ['echo titulo {ip} {port}']\n  - name: reloj\n    script: clock-skew\n    cmds: ['echo reloj {ip}']\n").unwrap();
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_shodan-pipeline"))
+        .current_dir(&dir)
+        .env("PATH", path)
+        .args(["--key", TEST_KEY, "--shodan-base-url", &mock.base_url, "--shodan-rate", "1000", "--out", "out", "--allow-private"])
+        .args(["--dns-server", &dns.addr.to_string(), "--dns-timeout-ms", "500", "--dns-attempts", "1"])
+        .args(["full", "--keywords", "chile", "--limit", "10", "--pages", "3", "--port-source", "shodan"])
+        .output().await.unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let out = dir.join("out");
+    let report = std::fs::read_to_string(out.join("report.json")).unwrap();
+    assert!(report.contains("192.0.2.10") && report.contains("OpenSSH"), "{report}");
+    assert!(report.contains("\"web.ejemplo.lab\""), "PTR en el reporte: {report}");
+    assert!(out.join("shodan_cache").join("ptr").exists());
+    assert!(out.join("stats.json").exists());
+    let csv = std::fs::read_to_string(out.join("report.csv")).unwrap();
+    assert!(csv.contains("nginx 1.18.0") && csv.contains(",22,tcp,open,syn-ack,0,ssh,OpenSSH,8.4p1,"), "{csv}");
+    assert!(csv.contains("cpe:/a:openbsd:openssh:8.4p1;cpe:/o:linux:linux_kernel"), "{csv}");
+    let json: Vec<serde_json::Value> = serde_json::from_str(&report).unwrap();
+    let https = json.iter().flat_map(|h| h["ports"].as_array().unwrap()).find(|p| p["port"] == 443).unwrap();
+    assert_eq!((https["product"].as_str(), https["version"].as_str(), https["tunnel"].as_str(), https["conf"].as_u64()), (Some("nginx"), Some("1.18.0"), Some("ssl"), Some(10)));
+    assert_eq!(https["scripts"][0]["data"]["title"], "Acceso & Login");
+    assert_eq!(json[0]["host_scripts"][0]["id"], "clock-skew");
+    // Datos de host de Nmap: lo de <hosthint> no pisa al <host> real
+    assert_eq!((json[0]["nmap"]["status"].as_str(), json[0]["nmap"]["status_reason"].as_str()), (Some("up"), Some("user-set")));
+    assert_eq!((json[0]["nmap"]["os"][0]["accuracy"].as_u64(), json[0]["nmap"]["distance"].as_u64()), (Some(95), Some(3)));
+    assert_eq!(json[0]["nmap"]["trace"][0]["host"], "gw.ejemplo.cl");
+    assert!(csv.contains(",up,,,Linux 5.0 - 5.14 (95%),93784,3,22,tcp,"), "{csv}");
+    // Reglas por script NSE: de puerto (una vez por puerto) y de host (una vez por host)
+    let logs = out.join("192.0.2.10");
+    assert!(logs.join("titulo_443.log").exists() && !logs.join("titulo_22.log").exists());
+    assert!(std::fs::read_to_string(logs.join("reloj_host.log")).unwrap().contains("reloj 192.0.2.10"));
+    assert!(mock.hits("/api-info") >= 1 && mock.hits("/shodan/host/count") >= 1);
+}
+
+/// Modo adaptativo: cada ampliación vuelve a consultar Shodan, pero ips.txt y shodan_matches.jsonl quedan con lo acumulado
+/// (incluidos los objetivos explícitos), no solo con la última ronda.
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn adaptive_rounds_export_everything() {
+    let mock = MockShodan::start().await;
+    let dir = temp_dir("adaptive");
+    std::fs::write(dir.join("t.txt"), "192.0.2.99\n").unwrap();
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_shodan-pipeline"))
+        .current_dir(&dir)
+        .env("PATH", common::fake_nmap_path(&dir))
+        .args(["--key", TEST_KEY, "--shodan-base-url", &mock.base_url, "--shodan-rate", "1000", "--out", "out", "--allow-private", "--no-ptr"])
+        .args(["full", "--keywords", "chile", "--limit", "3", "--pages", "1", "--port-source", "shodan", "--targets", "t.txt", "--no-enrich"])
+        .args(["--interesting-target", "5", "--interesting-min-open", "100"])
+        .output().await.unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("[ADAPT] Ampliado conjunto a 4 IPs"));
+    let out = dir.join("out");
+    assert_eq!(std::fs::read_to_string(out.join("ips.txt")).unwrap(), "192.0.2.10\n192.0.2.99\n192.0.2.20\n192.0.2.30");
+    assert_eq!(std::fs::read_to_string(out.join("shodan_matches.jsonl")).unwrap().lines().count(), 4);
+}
+
+/// Sin `--allow-private` las IPs de documentación de los fixtures se descartan antes de escanear.
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn reserved_ranges_are_skipped_by_default() {
+    let mock = MockShodan::start().await;
+    let dir = temp_dir("reserved");
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_shodan-pipeline"))
+        .current_dir(&dir)
+        .env("PATH", common::fake_nmap_path(&dir))
+        .args(["--key", TEST_KEY, "--shodan-base-url", &mock.base_url, "--shodan-rate", "1000", "--out", "out"])
+        .args(["intel", "--keywords", "chile", "--limit", "10", "--pages", "3"])
+        .output().await.unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("192.0.2.10 en rango reservado (documentación (TEST-NET-1))"));
+    assert_eq!(std::fs::read_to_string(dir.join("out").join("ips.txt")).unwrap(), "");
+}
+
+/// `campaign` con dos consultas que devuelven las mismas IPs: directorio por consulta + reporte combinado sin duplicados.
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn campaign_offline() {
+    let mock = MockShodan::start().await;
+    let dir = temp_dir("campaign");
+    let path = common::fake_nmap_path(&dir);
+    std::fs::write(dir.join("c.yaml"), "name: semanal\nqueries:\n  - name: todo\n    keywords: chile\n    limit: 10\n    pages: 3\n    port_source: shodan\n  - name: sin-telnet\n    keywords: 'chile, -port:23'\n    limit: 10\n    pages: 3\n    port_source: shodan\n").unwrap();
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_shodan-pipeline"))
+        .current_dir(&dir)
+        .env("PATH", path)
+        .args(["--key", TEST_KEY, "--shodan-base-url", &mock.base_url, "--shodan-rate", "1000", "--out", "out", "--allow-private", "--no-ptr"])
+        .args(["campaign", "--file", "c.yaml"])
+        .output().await.unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let root = dir.join("out").join("semanal");
+    for q in ["todo", "sin-telnet"] { assert!(root.join(q).join("report.json").exists(), "{q}"); }
+    let merged: Vec<serde_json::Value> = serde_json::from_str(&std::fs::read_to_string(root.join("report.json")).unwrap()).unwrap();
+    let per_query: Vec<serde_json::Value> = serde_json::from_str(&std::fs::read_to_string(root.join("todo").join("report.json")).unwrap()).unwrap();
+    assert_eq!(merged.len(), per_query.len());
+    let summary = std::fs::read_to_string(root.join("campaign.json")).unwrap();
+    assert!(summary.contains("\"sin-telnet\"") && summary.contains("-port:23"), "{summary}");
+    let md = std::fs::read_to_string(root.join("report.md")).unwrap();
+    assert!(md.contains("| 443 | tcp | open | ssl/http | nginx 1.18.0 | syn-ack ttl 0, probed conf 10, `cpe:/a:igor_sysoev:nginx:1.18.0` |"), "{md}");
+    assert!(md.contains("### Scripts NSE 443/tcp\n\n**http-title**\n\n```\nAcceso & Login\n```") && md.contains("### Scripts NSE de host"), "{md}");
+    assert!(md.contains("_SO:_ Linux 5.0 - 5.14 (95%), Linux 4.15 - 5.8 (90%)") && md.contains("| 1 | 198.51.100.1 | 0.52 | gw.ejemplo.cl |"), "{md}");
+}
+
+/// `rustscan --discovery native` sin el binario rustscan: mismo JSONL contra un listener local.
+#[tokio::test(flavor = "multi_thread")]
+async fn native_discovery_writes_jsonl() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let dir = temp_dir("native");
+    std::fs::write(dir.join("t.txt"), "127.0.0.1 # local\n").unwrap();
+    let ports = format!("{port},{}", if port == 65535 { 65534 } else { port + 1 });
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_shodan-pipeline"))
+        .current_dir(&dir)
+        .env("PATH", "")
+        .args(["--out", "out", "--allow-private", "--discovery", "native", "--scan-ports", &ports, "--scan-timeout-ms", "500"])
+        .args(["rustscan", "--input-targets", "t.txt"])
+        .output().await.unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let jsonl = std::fs::read_to_string(dir.join("t.rustscan.jsonl")).unwrap();
+    assert_eq!(jsonl.trim(), format!("{{\"ip\":\"127.0.0.1\",\"ports\":[{port}]}}"));
+}
+
+/// RustScan que falla en un host: con `mark-error` no pasa a Nmap, su stderr queda guardado y el reporte lo lista.
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn rustscan_failures_are_reported() {
+    let mock = MockShodan::start().await;
+    let dir = temp_dir("rsfail");
+    let path = common::fake_nmap_path(&dir);
+    common::fake_rustscan(&dir, "192.0.2.20");
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_shodan-pipeline"))
+        .current_dir(&dir)
+        .env("PATH", path)
+        .args(["--key", TEST_KEY, "--shodan-base-url", &mock.base_url, "--shodan-rate", "1000", "--out", "out", "--allow-private", "--no-ptr", "--on-discovery-error", "mark-error"])
+        .args(["full", "--keywords", "chile", "--limit", "10", "--pages", "3", "--port-source", "rustscan"])
+        .output().await.unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let out = dir.join("out");
+    assert_eq!(std::fs::read_to_string(out.join("192.0.2.20").join("rustscan.stderr.txt")).unwrap().trim(), "boom: sin permisos");
+    assert!(!out.join("192.0.2.20").join("nmap.xml").exists(), "mark-error no debe lanzar Nmap");
+    assert!(out.join("192.0.2.10").join("nmap.xml").exists());
+    let report: Vec<serde_json::Value> = serde_json::from_str(&std::fs::read_to_string(out.join("report.json")).unwrap()).unwrap();
+    let failed = report.iter().find(|h| h["ip"] == "192.0.2.20").expect("el host fallido debe figurar en el reporte");
+    assert!(failed["discovery_error"].as_str().unwrap().starts_with("non-zero-exit"), "{failed}");
+    assert!(std::fs::read_to_string(out.join("report.csv")).unwrap().contains("marcado como error"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Descubrimiento fallido: 1 host(s)"));
+    let jsonl = std::fs::read_to_string(out.join("rustscan.jsonl")).unwrap();
+    assert!(jsonl.contains("\"status\":\"non-zero-exit\""), "{jsonl}");
+}
+
+/// `--profile` fija los `--rs-*` por defecto, los flags los sobrescriben y un valor inválido se rechaza antes de escanear.
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn rustscan_profile_and_flags() {
+    let dir = temp_dir("rsprofile");
+    let path = common::fake_nmap_path(&dir);
+    common::fake_rustscan(&dir, "192.0.2.99");
+    std::fs::write(dir.join("targets.txt"), "192.0.2.10\n").unwrap();
+    std::fs::write(dir.join("perfil.yaml"), "rustscan:\n  range: 1-1024\n  tries: 1\n  scan_order: random\n  ulimit: 8000\n").unwrap();
+    let run = |extra: &'static [&'static str]| {
+        tokio::process::Command::new(env!("CARGO_BIN_EXE_shodan-pipeline"))
+            .current_dir(&dir)
+            .env("PATH", &path)
+            .args(["--out", "out", "--allow-private", "--no-ptr", "--profile", "perfil.yaml"])
+            .args(["rustscan", "--input-targets", "targets.txt"])
+            .args(extra)
+            .output()
+    };
+    let output = run(&["--rs-tries", "3", "--rs-batch", "2000"]).await.unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let logged = std::fs::read_to_string(dir.join("rustscan.args")).unwrap();
+    assert_eq!(logged.trim(), "-a 192.0.2.10 --timeout 1500 --batch-size 2000 --range 1-1024 --ulimit 8000 --tries 3 --scan-order random --greppable");
+    assert!(std::fs::read_to_string(dir.join("targets.rustscan.jsonl")).unwrap().contains("[22,80]"));
+    let output = run(&["--rs-batch", "9000"]).await.unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--rs-ulimit"), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(dir.join("rustscan.args")).unwrap().lines().count(), 1, "no debe lanzar rustscan con opciones inválidas");
+    // Un archivo con una línea inválida se rechaza entero antes de escanear la primera IP válida
+    std::fs::write(dir.join("malo.txt"), "192.0.2.11\n192.0.2.12 192.0.2.13\n").unwrap();
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_shodan-pipeline"))
+        .current_dir(&dir)
+        .env("PATH", &path)
+        .args(["--out", "out", "--allow-private", "--no-ptr", "rustscan", "--input-targets", "malo.txt"])
+        .output().await.unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("línea 2: '192.0.2.12 192.0.2.13'"), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!std::fs::read_to_string(dir.join("rustscan.args")).unwrap().contains("192.0.2.11"));
+    // Los flags previos del subcomando siguen funcionando
+    let output = run(&["--timeout-ms", "900", "--batch", "4000"]).await.unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(std::fs::read_to_string(dir.join("rustscan.args")).unwrap().lines().last().unwrap().starts_with("-a 192.0.2.10 --timeout 900 --batch-size 4000 "));
+}
+
+/// Alcance: la denylist saca IPs de Shodan antes de Nmap y un engagement vencido impide arrancar.
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn scope_filters_and_window() {
+    let mock = MockShodan::start().await;
+    let dir = temp_dir("scope");
+    let path = common::fake_nmap_path(&dir);
+    std::fs::write(dir.join("deny.txt"), "# no tocar\n192.0.2.20\n192.0.2.28-40\n").unwrap();
+    std::fs::write(dir.join("expired.yaml"), "client: Ejemplo\nstart: 2020-01-01T00:00:00Z\nend: 2020-01-31T23:59:59-03:00\n").unwrap();
+    let run = |extra: &'static [&'static str]| {
+        tokio::process::Command::new(env!("CARGO_BIN_EXE_shodan-pipeline"))
+            .current_dir(&dir)
+            .env("PATH", &path)
+            .args(["--key", TEST_KEY, "--shodan-base-url", &mock.base_url, "--shodan-rate", "1000", "--out", "out", "--allow-private", "--no-ptr"])
+            .args(extra)
+            .args(["full", "--keywords", "chile", "--limit", "10", "--pages", "3", "--port-source", "shodan"])
+            .output()
+    };
+    let output = run(&["--denylist", "deny.txt"]).await.unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let out = dir.join("out");
+    let report = std::fs::read_to_string(out.join("report.json")).unwrap();
+    assert!(report.contains("192.0.2.10") && !report.contains("\"ip\": \"192.0.2.20\""), "{report}");
+    assert!(!out.join("192.0.2.20").exists(), "Nmap no debe tocar IPs excluidas");
+    let excluded = std::fs::read_to_string(out.join("out_of_scope.txt")).unwrap();
+    assert_eq!(excluded.lines().collect::<Vec<_>>(), vec!["192.0.2.20\tdenylist", "192.0.2.30\tdenylist"]);
+    let output = run(&["--engagement", "expired.yaml"]).await.unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("fuera de ventana"));
+}
+
+/// Subcomando `nmap` con una matriz fija TCP+UDP: los `U:` llegan a Nmap (con `-sU`) si hay root, como en `full`.
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn nmap_fixed_ports_keep_udp() {
+    let dir = temp_dir("nmapfixed");
+    let path = common::fake_nmap_path(&dir);
+    std::fs::write(dir.join("in.jsonl"), "{\"ip\":\"192.0.2.10\",\"ports\":[]}\n").unwrap();
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_shodan-pipeline"))
+        .current_dir(&dir)
+        .env("PATH", path)
+        .args(["--out", "out", "--allow-private", "--no-ptr"])
+        .args(["nmap", "--input-jsonl", "in.jsonl", "--fixed-ports", "T:22,U:161", "--nmap-extra=-sT -Pn"])
+        .output().await.unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let logged = std::fs::read_to_string(dir.join("nmap.args")).unwrap();
+    if unsafe { libc::geteuid() } == 0 {
+        assert!(logged.starts_with("-sU -sT -Pn -p T:22,U:161 192.0.2.10"), "{logged}");
+    } else {
+        assert!(logged.starts_with("-sT -Pn -p 22 192.0.2.10"), "{logged}");
+    }
+}
+
+/// Subcomando `nmap`: los hostnames del JSONL (`targets`) activan las reglas de dominio del alcance.
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn nmap_scope_uses_jsonl_hostnames() {
+    let dir = temp_dir("nmapscope");
+    let path = common::fake_nmap_path(&dir);
+    std::fs::write(dir.join("in.jsonl"), "{\"ip\":\"192.0.2.10\",\"ports\":[22],\"targets\":[\"www.ejemplo.cl\"]}\n{\"ip\":\"192.0.2.11\",\"ports\":[22],\"targets\":[\"legado.cliente.cl\"]}\n").unwrap();
+    std::fs::write(dir.join("deny.txt"), "cliente.cl\n").unwrap();
+    let output = tokio::process::Command::new(env!("CARGO_BIN_EXE_shodan-pipeline"))
+        .current_dir(&dir)
+        .env("PATH", path)
+        .args(["--out", "out", "--allow-private", "--no-ptr", "--denylist", "deny.txt"])
+        .args(["nmap", "--input-jsonl", "in.jsonl"])
+        .output().await.unwrap();
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let out = dir.join("out");
+    assert!(out.join("192.0.2.10").join("nmap.xml").exists() && !out.join("192.0.2.11").exists());
+    assert!(std::fs::read_to_string(out.join("out_of_scope.txt")).unwrap().starts_with("192.0.2.11\t"));
+    let report = std::fs::read_to_string(out.join("report.json")).unwrap();
+    assert!(report.contains("\"target\": \"www.ejemplo.cl\""), "{report}");
+    let by_target: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(out.join("report_by_target.json")).unwrap()).unwrap();
+    assert_eq!(by_target, serde_json::json!([{"target": "www.ejemplo.cl", "hosts": [{"ip": "192.0.2.10", "ports": ["22", "443"]}]}]));
+}